@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Read all .vm or .asm files from a zip archive as (filename, content) pairs
+/// Other files (e.g. .tst or .cmp files) and the directory structure are ignored, but two files
+/// with the same name in different directories are an error
+pub fn read_project(bytes: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("Invalid zip archive: {}", e))?;
+
+    let mut files = Vec::new();
+    let mut names = HashSet::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| format!("Invalid zip archive: {}", e))?;
+
+        if entry.is_dir() {
+            continue;
+        }
+
+        let name = match Path::new(entry.name()).file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.ends_with(".vm") || name.ends_with(".asm") => name.to_owned(),
+            _ => continue,
+        };
+
+        if !names.insert(name.clone()) {
+            return Err(format!("The archive contains more than one {}", name));
+        }
+
+        let mut content = String::new();
+        entry
+            .read_to_string(&mut content)
+            .map_err(|e| format!("Could not read {}: {}", name, e))?;
+        files.push((name, content));
+    }
+
+    let n_vm = files
+        .iter()
+        .filter(|(name, _)| name.ends_with(".vm"))
+        .count();
+    match (n_vm, files.len() - n_vm) {
+        (0, 0) => Err("The archive contains no .vm or .asm files".to_owned()),
+        (0, 1) | (_, 0) => Ok(files),
+        (0, _) => Err("The archive contains more than one .asm file".to_owned()),
+        _ => Err("The archive contains both .vm and .asm files".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_read_project() {
+        let bytes = zip(&[
+            ("project/Main.vm", "push constant 1"),
+            ("project/Main.tst", "load Main.vm;"),
+            ("project/Sys.vm", "push constant 2"),
+        ]);
+
+        assert_eq!(
+            Ok(vec![
+                ("Main.vm".to_owned(), "push constant 1".to_owned()),
+                ("Sys.vm".to_owned(), "push constant 2".to_owned())
+            ]),
+            read_project(&bytes)
+        );
+    }
+
+    #[test]
+    fn test_read_invalid_projects() {
+        assert!(read_project(b"not a zip").is_err());
+
+        assert_eq!(
+            Err("The archive contains no .vm or .asm files".to_owned()),
+            read_project(&zip(&[("Main.jack", "class Main {}")]))
+        );
+        assert_eq!(
+            Err("The archive contains both .vm and .asm files".to_owned()),
+            read_project(&zip(&[("Main.vm", ""), ("Main.asm", "")]))
+        );
+        assert_eq!(
+            Err("The archive contains more than one .asm file".to_owned()),
+            read_project(&zip(&[("A.asm", ""), ("B.asm", "")]))
+        );
+        assert_eq!(
+            Err("The archive contains more than one Main.vm".to_owned()),
+            read_project(&zip(&[("a/Main.vm", ""), ("b/Main.vm", "")]))
+        );
+    }
+}
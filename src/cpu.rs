@@ -1,6 +1,6 @@
 use parse::script::parser::ScriptParser;
 use simulators::cpu::Cpu;
-use simulators::execute_script;
+use simulators::{execute_script, ScriptError};
 
 mod definitions;
 mod parse;
@@ -15,7 +15,7 @@ pub fn execute<'w>(
     tst_name: &Path,
     tst_content: String,
     writer: impl Into<Option<&'w mut dyn Write>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), ScriptError> {
     let parser = ScriptParser::new(tst_name, &tst_content);
     execute_script(parser, Cpu::default(), writer)
 }
@@ -85,4 +85,24 @@ mod tests {
         let (tst_name, tst_content) = cpu_test!("fill/FillAutomatic.tst");
         execute(&tst_name, tst_content, None).unwrap();
     }
+
+    #[test]
+    fn test_output_rom() {
+        let tst_name = PathBuf::from(cpu_path!("mult/MultRom.tst"));
+        let tst_content = r#"
+            load Mult.asm,
+            output-list ROM[0]%D1.6.1 ROM[1]%B1.16.1 ROM[99]%D1.6.1;
+            output;"#
+            .to_owned();
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute(&tst_name, tst_content, w).unwrap();
+
+        // @R2, M=0 and the empty ROM behind the program
+        assert_eq!(
+            "| ROM[0] |      ROM[1]      |ROM[99] |\n|      2 | 1110101010001000 |      0 |\n",
+            String::from_utf8(v).unwrap()
+        );
+    }
 }
@@ -1,6 +1,7 @@
 // see Definitions.java int the official implementation
 pub const MEM_SIZE: usize = 24577;
 pub const KBD: usize = 24576;
+pub const ROM_SIZE: usize = 32768;
 
 pub const BITS_PER_WORD: usize = 16;
 pub const SCREEN_WIDTH_IN_WORDS: usize = 32;
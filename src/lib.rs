@@ -1,3 +1,5 @@
+#[cfg(feature = "zip")]
+mod archive;
 mod definitions;
 mod keyboard;
 #[allow(dead_code)]
@@ -5,15 +7,16 @@ mod parse;
 #[allow(dead_code)]
 mod simulators;
 
-use definitions::{
-    Address, Word, BITS_PER_WORD, SCREEN_HEIGHT, SCREEN_WIDTH, SCREEN_WIDTH_IN_WORDS,
-};
+use definitions::{Address, Word, SCREEN_HEIGHT, SCREEN_WIDTH, SCREEN_WIDTH_IN_WORDS};
 use parse::assembly::{self, AssemblyParseError, AssemblyParser};
-use parse::bytecode::{self, BytecodeParseError, BytecodeParser};
+use parse::bytecode::{self, BytecodeParseError, BytecodeParser, ParsedProgram};
+use parse::script::tst::NumberFormat;
 use simulators::cpu::{Cpu, CpuError};
+use simulators::vm::heap;
 use simulators::vm::meta::FileInfo;
-use simulators::vm::stdlib::Stdlib;
-use simulators::vm::{VMError, VM};
+use simulators::vm::stdlib::{Stdlib, StdlibError};
+use simulators::vm::{BreakpointTarget, VMError, VM};
+use simulators::{diff_memory, display_pixels, dump_memory};
 use wasm_bindgen::prelude::*;
 
 use wasm_bindgen::Clamped;
@@ -39,21 +42,55 @@ impl Simulator {
         }
     }
 
-    pub fn step_times(&mut self, times: u32) -> SimResult {
+    pub fn step_times(&mut self, times: u32) -> StepReport {
+        let mut report = StepReport {
+            executed: 0,
+            reason: StopReason::Completed,
+            error: None,
+        };
+
         match self {
-            Self::None => return Err("Cannot step without a Simulator".into()),
+            Self::None => {
+                report.reason = StopReason::Error;
+                report.error = Some("Cannot step without a Simulator".to_owned());
+            }
             Self::VM(vm) => {
-                for _ in 0..times {
-                    vm.step()?;
+                let cycles = vm.cycle_count();
+                let result = vm.step_times(times);
+                // every step counts a cycle, even the one that failed
+                let steps = (vm.cycle_count() - cycles) as u32;
+
+                match result {
+                    Ok(hit_breakpoint) => {
+                        report.executed = steps;
+                        if hit_breakpoint {
+                            report.reason = StopReason::Breakpoint;
+                        }
+                    }
+                    Err(VMError::StdlibError(StdlibError::Halt)) => {
+                        report.executed = steps.saturating_sub(1);
+                        report.reason = StopReason::Halted;
+                    }
+                    Err(e) => {
+                        report.executed = steps.saturating_sub(1);
+                        report.reason = StopReason::Error;
+                        report.error = Some(e.to_string());
+                    }
                 }
             }
             Self::Cpu(cpu) => {
-                for _ in 0..times {
-                    cpu.step()?;
+                while report.executed < times {
+                    if let Err(e) = cpu.step() {
+                        report.reason = StopReason::Error;
+                        report.error = Some(e.to_string());
+                        break;
+                    }
+                    report.executed += 1;
                 }
             }
         }
-        Ok(())
+
+        report
     }
 
     pub fn set_input_key(&mut self, key: i16) -> SimResult {
@@ -72,6 +109,14 @@ impl Simulator {
         }
     }
 
+    pub fn current_key(&self) -> Word {
+        match self {
+            Self::None => 0,
+            Self::VM(vm) => vm.current_key(),
+            Self::Cpu(cpu) => cpu.current_key(),
+        }
+    }
+
     pub fn current_file_offset(&self) -> Option<usize> {
         match self {
             Self::None => None,
@@ -80,6 +125,14 @@ impl Simulator {
         }
     }
 
+    pub fn memory(&self) -> Option<&[Word]> {
+        match self {
+            Self::None => None,
+            Self::VM(vm) => Some(vm.memory()),
+            Self::Cpu(cpu) => Some(cpu.memory()),
+        }
+    }
+
     pub fn display(&self) -> Option<&[Word]> {
         match self {
             Self::None => None,
@@ -89,10 +142,131 @@ impl Simulator {
     }
 }
 
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Completed,
+    Stopped,
+    Breakpoint,
+    Halted,
+    Error,
+}
+
+/// The radix of the values in App::memory_dump
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordFormat {
+    Binary,
+    Decimal,
+    Hex,
+}
+
+impl From<WordFormat> for NumberFormat {
+    fn from(format: WordFormat) -> Self {
+        match format {
+            WordFormat::Binary => NumberFormat::Binary,
+            WordFormat::Decimal => NumberFormat::Decimal,
+            WordFormat::Hex => NumberFormat::Hex,
+        }
+    }
+}
+
+/// How far App::step_times got before it stopped
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepReport {
+    pub executed: u32,
+    pub reason: StopReason,
+    error: Option<String>,
+}
+
+#[wasm_bindgen]
+impl StepReport {
+    /// The error message if reason is StopReason.Error
+    #[wasm_bindgen(getter)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+/// An offset in one of the loaded files, like the one returned by current_file_offset
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePosition {
+    file_name: String,
+    pub offset: usize,
+}
+
+#[wasm_bindgen]
+impl FilePosition {
+    #[wasm_bindgen(getter)]
+    pub fn file_name(&self) -> String {
+        self.file_name.clone()
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuRegisters {
+    pub a: Word,
+    pub d: Word,
+    pub m: Word,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameDims {
+    pub n_args: usize,
+    pub n_locals: usize,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub total: usize,
+    pub free: usize,
+    pub largest_free_block: usize,
+    pub free_blocks: usize,
+}
+
+impl From<heap::HeapStats> for HeapStats {
+    fn from(stats: heap::HeapStats) -> Self {
+        Self {
+            total: stats.total,
+            free: stats.free,
+            largest_free_block: stats.largest_free_block,
+            free_blocks: stats.free_blocks,
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramStats {
+    pub instructions: usize,
+    pub functions: usize,
+    pub statics: usize,
+}
+
+impl From<bytecode::ProgramStats> for ProgramStats {
+    fn from(stats: bytecode::ProgramStats) -> Self {
+        Self {
+            instructions: stats.instructions,
+            functions: stats.functions,
+            statics: stats.statics,
+        }
+    }
+}
+
+// the most steps a single call of App::step_times will execute, so that a huge step count
+// cannot freeze the tab for too long
+const MAX_STEPS_PER_CALL: u32 = 1_000_000;
+
 #[wasm_bindgen]
 pub struct App {
     sim: Simulator,
     programs: Vec<(String, String)>, // (filename, content)
+    stop_requested: bool,
 }
 
 impl Default for App {
@@ -127,6 +301,78 @@ impl From<AssemblyParseError> for JsValue {
 
 type SimResult = Result<(), JsValue>;
 
+impl App {
+    /// The rgba pixels of the screen
+    fn display_buffer(&self) -> Vec<u8> {
+        const BLANK: [Word; SCREEN_HEIGHT * SCREEN_WIDTH_IN_WORDS] =
+            [0; SCREEN_HEIGHT * SCREEN_WIDTH_IN_WORDS];
+        let display = self.sim.display().unwrap_or(&BLANK);
+
+        let mut data = Vec::with_capacity(Self::data_buffer_size());
+        for black in display_pixels(display) {
+            let color = if black { 0 } else { 255 };
+            data.extend([color, color, color, 255]);
+        }
+        data
+    }
+
+    /// Catch common mistakes with the added files before trying to parse them
+    fn validate_files(&self) -> Result<(), String> {
+        if let Some((name, _)) = self
+            .programs
+            .iter()
+            .find(|(name, _)| name.ends_with(".jack"))
+        {
+            return Err(format!(
+                "{}: Jack source must be compiled to .vm first; \
+                 this emulator does not include the Jack compiler",
+                name
+            ));
+        }
+
+        match self.programs.first() {
+            Some((first, _)) if first.ends_with(".vm") => {
+                if let Some((name, _)) = self.programs.iter().find(|(n, _)| !n.ends_with(".vm")) {
+                    return Err(format!(
+                        "{}: cannot be loaded together with .vm files; \
+                         either load multiple .vm files or a single .asm file",
+                        name
+                    ));
+                }
+            }
+            Some((first, _)) if first.ends_with(".asm") => {
+                if let Some((name, _)) = self.programs.get(1) {
+                    return Err(format!(
+                        "{}: only a single .asm file can be loaded at a time",
+                        name
+                    ));
+                }
+            }
+            Some((first, _)) => {
+                return Err(format!(
+                    "{}: unsupported file type, expected .vm or .asm",
+                    first
+                ));
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn parse_vm_files(&self) -> Result<ParsedProgram, JsValue> {
+        let stdlib = Stdlib::new();
+        let programs = self
+            .programs
+            .iter()
+            .map(|(name, content)| bytecode::SourceFile::new(name, content))
+            .collect::<Vec<_>>();
+
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        Ok(bytecode_parser.parse()?)
+    }
+}
+
 #[wasm_bindgen]
 impl App {
     pub fn new() -> Self {
@@ -136,6 +382,7 @@ impl App {
         Self {
             sim: Simulator::None,
             programs: Vec::new(),
+            stop_requested: false,
         }
     }
 
@@ -148,6 +395,8 @@ impl App {
     }
 
     pub fn load_files(&mut self) -> SimResult {
+        self.validate_files()?;
+
         let is_vm = self
             .programs
             .first()
@@ -155,24 +404,9 @@ impl App {
             .unwrap_or(false);
 
         if is_vm {
-            for (name, _) in &self.programs {
-                if !name.ends_with(".vm") {
-                    return Err("Either load multiple .vm files or a single .asm file".into());
-                }
-            }
+            let program = self.parse_vm_files()?;
 
             let mut vm = VM::new(Stdlib::new());
-
-            let stdlib = Stdlib::new();
-            let programs = self
-                .programs
-                .iter()
-                .map(|(name, content)| bytecode::SourceFile::new(name, content))
-                .collect::<Vec<_>>();
-
-            let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
-            let program = bytecode_parser.parse()?;
-
             vm.load(program);
             self.sim = Simulator::VM(vm.into());
         } else {
@@ -192,10 +426,49 @@ impl App {
         Ok(())
     }
 
+    /// Run the loaded program again from the start without parsing the files again
+    pub fn restart(&mut self) -> SimResult {
+        match &mut self.sim {
+            Simulator::None => Err("Trying to restart without a loaded program".into()),
+            Simulator::VM(vm) => {
+                vm.reset();
+                Ok(())
+            }
+            Simulator::Cpu(cpu) => {
+                cpu.reset();
+                Ok(())
+            }
+        }
+    }
+
+    /// Parse the added .vm files without loading them, to show the size of the program
+    pub fn analyze_files(&self) -> Result<ProgramStats, JsValue> {
+        self.validate_files()?;
+        Ok(self.parse_vm_files()?.stats().into())
+    }
+
     // --- General Simulator features ---
 
-    pub fn step_times(&mut self, times: u32) -> SimResult {
-        self.sim.step_times(times)
+    /// Step up to times steps, errors don't throw but are part of the report
+    /// At most MAX_STEPS_PER_CALL steps are executed, check the report to see how many ran
+    pub fn step_times(&mut self, times: u32) -> StepReport {
+        if std::mem::take(&mut self.stop_requested) {
+            return StepReport {
+                executed: 0,
+                reason: StopReason::Stopped,
+                error: None,
+            };
+        }
+
+        self.sim.step_times(times.min(MAX_STEPS_PER_CALL))
+    }
+
+    /// Make the next step_times return before executing any more steps
+    /// Because wasm is single threaded, this cannot interrupt a step_times call that is already
+    /// running. Instead the frontend should run programs in batches (e.g. one step_times per
+    /// animation frame) and call this between the batches
+    pub fn request_stop(&mut self) {
+        self.stop_requested = true;
     }
 
     pub fn step(&mut self) -> SimResult {
@@ -210,34 +483,94 @@ impl App {
         self.sim.memory_at(address)
     }
 
+    /// The value of the keyboard register (KBD), 0 if no key is pressed
+    pub fn current_key(&self) -> Word {
+        self.sim.current_key()
+    }
+
+    /// len words starting at start in a single call, e.g. for the RAM viewer
+    /// The range is cut off at the end of the memory, so it is empty if start is outside of it
+    pub fn memory_range(&self, start: Address, len: usize) -> Vec<Word> {
+        let memory = self.sim.memory().unwrap_or_default();
+        let start = start.min(memory.len());
+        let end = start.saturating_add(len).min(memory.len());
+        memory[start..end].to_vec()
+    }
+
     pub fn current_file_offset(&self) -> Option<usize> {
         self.sim.current_file_offset()
     }
 
+    /// The disassembled instructions from around - radius to around + radius in the file of the
+    /// current function, cut off at the start and end of that file
+    pub fn disassemble_window(&self, around: usize, radius: usize) -> Vec<String> {
+        if let Simulator::VM(vm) = &self.sim {
+            return vm
+                .disassembly_window(around, radius)
+                .into_iter()
+                .map(|(_, text, _)| text)
+                .collect();
+        }
+
+        Vec::new()
+    }
+
+    /// The instruction at the pc as an object, e.g. { op: "push", segment: "constant", index: 10 }
+    /// for the VM or { op: "c", dest: "M", comp: "D+1", jump: null } for the CPU
+    pub fn current_instruction_struct(&self) -> JsValue {
+        let serialized = match &self.sim {
+            Simulator::None => return JsValue::NULL,
+            Simulator::VM(vm) => vm
+                .current_instruction()
+                .map(|i| serde_wasm_bindgen::to_value(&i)),
+            Simulator::Cpu(cpu) => cpu
+                .current_instruction()
+                .map(|i| serde_wasm_bindgen::to_value(&i)),
+        };
+        serialized.and_then(Result::ok).unwrap_or(JsValue::NULL)
+    }
+
+    /// A copy of the entire memory, which can later be passed to diff_against
+    pub fn memory_snapshot(&self) -> Vec<Word> {
+        self.sim.memory().map(|m| m.to_vec()).unwrap_or_default()
+    }
+
+    /// Compare the current memory against an earlier snapshot of it
+    /// The differences are returned as flattened (address, snapshot value, current value) triples
+    pub fn diff_against(&self, snapshot: Vec<Word>) -> Vec<i32> {
+        let memory = self.sim.memory().unwrap_or_default();
+        diff_memory(&snapshot, memory)
+            .into_iter()
+            .flat_map(|(address, old, new)| [address as i32, old as i32, new as i32])
+            .collect()
+    }
+
+    /// All non-zero memory cells as "address: value" lines, e.g. for bug reports
+    pub fn memory_dump(&self, format: WordFormat) -> String {
+        let memory = self.sim.memory().unwrap_or_default();
+        dump_memory(memory, None, format.into())
+    }
+
+    /// Every memory cell in start..end as "address: value" lines
+    pub fn memory_range_dump(&self, format: WordFormat, start: Address, end: Address) -> String {
+        let memory = self.sim.memory().unwrap_or_default();
+        dump_memory(memory, Some(start..end), format.into())
+    }
+
     pub fn data_buffer_size() -> usize {
         const BYTES_PER_PIXEL: usize = 4; // rgba
         BYTES_PER_PIXEL * SCREEN_WIDTH * SCREEN_HEIGHT
     }
 
-    pub fn display_data(&self) -> Option<ImageData> {
-        let display = self.sim.display()?;
-        let mut data = Vec::with_capacity(Self::data_buffer_size());
-        for row_idx in 0..SCREEN_HEIGHT {
-            for word_idx in 0..SCREEN_WIDTH_IN_WORDS {
-                let word = display[row_idx * SCREEN_WIDTH_IN_WORDS + word_idx];
-                for pixel_idx in 0..BITS_PER_WORD {
-                    let mask = 1 << pixel_idx;
-                    let value = word & mask;
-                    let color = if value == 0 { 255 } else { 0 };
-
-                    data.push(color);
-                    data.push(color);
-                    data.push(color);
-                    data.push(255);
-                }
-            }
-        }
+    /// The 11 row bitmap that the Output class uses to draw a character
+    /// Characters which can't be printed don't have a glyph
+    pub fn char_glyph(code: u32) -> Option<Vec<Word>> {
+        Stdlib::char_glyph(code).map(|glyph| glyph.to_vec())
+    }
 
+    /// The screen as an image, which is blank (all white) if no program is loaded
+    pub fn display_data(&self) -> Option<ImageData> {
+        let data = self.display_buffer();
         ImageData::new_with_u8_clamped_array_and_sh(
             Clamped(data.as_slice()),
             SCREEN_WIDTH as u32,
@@ -247,6 +580,50 @@ impl App {
     }
 }
 
+// Loading whole projects
+#[cfg(feature = "zip")]
+#[wasm_bindgen]
+impl App {
+    /// Replace the added files with the .vm or .asm files from a zip archive and load them
+    /// If the files cannot be loaded, the previously added files are kept
+    pub fn load_zip(&mut self, bytes: &[u8]) -> SimResult {
+        let files = archive::read_project(bytes)?;
+        let previous = std::mem::replace(&mut self.programs, files);
+        let result = self.load_files();
+        if result.is_err() {
+            self.programs = previous;
+        }
+        result
+    }
+}
+
+// CPU Emulator specific stuff
+#[wasm_bindgen]
+impl App {
+    /// Record the last capacity steps, so that step_back can undo them
+    pub fn enable_history(&mut self, capacity: usize) {
+        if let Simulator::Cpu(cpu) = &mut self.sim {
+            cpu.enable_history(capacity);
+        }
+    }
+
+    /// Undo the last recorded step, returns false if there was nothing to undo
+    pub fn step_back(&mut self) -> bool {
+        if let Simulator::Cpu(cpu) = &mut self.sim {
+            return cpu.step_back();
+        }
+        false
+    }
+
+    pub fn cpu_registers(&self) -> Option<CpuRegisters> {
+        if let Simulator::Cpu(cpu) = &self.sim {
+            let (a, d, m) = cpu.registers();
+            return Some(CpuRegisters { a, d, m });
+        }
+        None
+    }
+}
+
 // VM Emulator specific stuff
 #[wasm_bindgen]
 impl App {
@@ -261,6 +638,22 @@ impl App {
         }
     }
 
+    /// The first instruction that is executed after loading the program
+    pub fn entry_point(&self) -> Option<usize> {
+        if let Simulator::VM(vm) = &self.sim {
+            return Some(vm.entry_point());
+        }
+        None
+    }
+
+    /// The number of functions on the call stack
+    pub fn call_stack_depth(&self) -> usize {
+        if let Simulator::VM(vm) = &self.sim {
+            return vm.call_stack_depth();
+        }
+        0
+    }
+
     pub fn locals(&self) -> Vec<Word> {
         if let Simulator::VM(vm) = &self.sim {
             if let Some(locals) = vm.locals() {
@@ -271,6 +664,30 @@ impl App {
         Vec::new()
     }
 
+    /// The names of the current locals from a "// @locals" annotation (can be empty)
+    pub fn local_names(&self) -> Vec<JsValue> {
+        if let Simulator::VM(vm) = &self.sim {
+            if let Some(names) = vm.local_names() {
+                return names.iter().map(|n| JsValue::from_str(n)).collect();
+            }
+        }
+
+        Vec::new()
+    }
+
+    /// The messages of the recoverable errors in builtin functions since the last call
+    pub fn take_stdlib_log(&mut self) -> Vec<JsValue> {
+        if let Simulator::VM(vm) = &mut self.sim {
+            return vm
+                .take_stdlib_log()
+                .iter()
+                .map(|e| JsValue::from_str(&e.to_string()))
+                .collect();
+        }
+
+        Vec::new()
+    }
+
     pub fn args(&self) -> Vec<Word> {
         if let Simulator::VM(vm) = &self.sim {
             if let Some(args) = vm.args() {
@@ -291,6 +708,250 @@ impl App {
         Vec::new()
     }
 
+    /// Type a whole string (followed by a newline) into Keyboard.readChar/readLine
+    /// Characters without a key code are skipped
+    pub fn set_input_string(&mut self, s: String) {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.set_input_string(&s);
+        }
+    }
+
+    /// Release the current key and drop everything typed with set_input_string
+    pub fn clear_input(&mut self) {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.clear_input();
+        }
+    }
+
+    /// Start or stop recording all input, so that it can be exported with export_replay
+    pub fn set_record_replay(&mut self, record: bool) {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.set_record_replay(record);
+        }
+    }
+
+    /// The recorded input as an object like
+    /// { events: [{ cycle: 10, input: { kind: "key", value: 65 } }] }
+    pub fn export_replay(&self) -> JsValue {
+        match &self.sim {
+            Simulator::VM(vm) => vm
+                .replay_log()
+                .and_then(|log| serde_wasm_bindgen::to_value(log).ok())
+                .unwrap_or(JsValue::NULL),
+            _ => JsValue::NULL,
+        }
+    }
+
+    /// Replay a log from export_replay, this should be called right after loading the program
+    pub fn import_replay(&mut self, log: JsValue) -> SimResult {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.replay(serde_wasm_bindgen::from_value(log)?);
+        }
+        Ok(())
+    }
+
+    /// How often each kind of instruction occurs in the loaded program as [mnemonic, count]
+    /// pairs, e.g. [["push", 2], ["add", 1]]
+    pub fn opcode_histogram(&self) -> JsValue {
+        match &self.sim {
+            Simulator::VM(vm) => {
+                serde_wasm_bindgen::to_value(&vm.opcode_histogram()).unwrap_or(JsValue::NULL)
+            }
+            _ => JsValue::NULL,
+        }
+    }
+
+    /// The disassembly of the whole loaded program, empty if there is none
+    pub fn full_listing(&self) -> String {
+        if let Simulator::VM(vm) = &self.sim {
+            return vm.full_listing();
+        }
+        String::new()
+    }
+
+    /// Whether the program is blocked until a key is pressed, e.g. in Keyboard.readLine
+    pub fn is_waiting_for_input(&self) -> bool {
+        match &self.sim {
+            Simulator::VM(vm) => vm.is_waiting_for_input(),
+            _ => false,
+        }
+    }
+
+    /// Step, but run a called function until it returns instead of stepping into it
+    /// This is limited to MAX_STEPS_PER_CALL steps, so that an endless loop cannot freeze the tab
+    pub fn step_over(&mut self) -> SimResult {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.step_over(Some(MAX_STEPS_PER_CALL as u64))?;
+        }
+        Ok(())
+    }
+
+    /// Step until the current function returns to its caller
+    /// This is limited to MAX_STEPS_PER_CALL steps, so that an endless loop cannot freeze the tab
+    /// Like step, this throws if the program halts
+    pub fn step_out(&mut self) -> SimResult {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.finish_function(Some(MAX_STEPS_PER_CALL as u64))?;
+        }
+        Ok(())
+    }
+
+    /// Step until the vm reaches another line of the source file
+    pub fn step_line(&mut self) -> SimResult {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.step_line(Some(MAX_STEPS_PER_CALL as u64))?;
+        }
+        Ok(())
+    }
+
+    /// The line of the current instruction in its source file (starting at 1)
+    pub fn current_source_line(&self) -> Option<u32> {
+        if let Simulator::VM(vm) = &self.sim {
+            return vm.current_source_line();
+        }
+        None
+    }
+
+    pub fn current_frame_info(&self) -> Option<FrameDims> {
+        if let Simulator::VM(vm) = &self.sim {
+            let (n_args, n_locals) = vm.current_frame_dims()?;
+            return Some(FrameDims { n_args, n_locals });
+        }
+        None
+    }
+
+    /// Stop step_times/step once the vm has executed the given number of steps
+    pub fn add_cycle_breakpoint(&mut self, cycle: u64) {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.add_cycle_breakpoint(cycle);
+        }
+    }
+
+    /// Stop whenever the vm reaches the instruction at the given pc
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.add_breakpoint(BreakpointTarget::Instruction(pc));
+        }
+    }
+
+    /// Stop whenever the vm reaches the given offset in the given file
+    /// This is the offset returned by current_file_offset
+    pub fn add_file_breakpoint(&mut self, file_name: &str, file_offset: usize) -> SimResult {
+        let file = self
+            .module_index(file_name)
+            .ok_or_else(|| format!("{}: no file with this name is loaded", file_name))?;
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.add_breakpoint(BreakpointTarget::FileOffset {
+                file,
+                offset: file_offset,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn remove_file_breakpoint(&mut self, file_name: &str, file_offset: usize) -> bool {
+        let file = self.module_index(file_name);
+        if let (Simulator::VM(vm), Some(file)) = (&mut self.sim, file) {
+            return vm.remove_breakpoint(BreakpointTarget::FileOffset {
+                file,
+                offset: file_offset,
+            });
+        }
+        false
+    }
+
+    /// Step until a breakpoint is hit and return the file and offset the vm stopped at
+    /// Returns None if no breakpoint was hit within max_steps
+    pub fn run_until_breakpoint(
+        &mut self,
+        max_steps: u32,
+    ) -> Result<Option<FilePosition>, JsValue> {
+        let hit = match &mut self.sim {
+            Simulator::VM(vm) => vm.step_times(max_steps)?,
+            _ => false,
+        };
+        if !hit {
+            return Ok(None);
+        }
+
+        let offset = match &self.sim {
+            Simulator::VM(vm) => vm.current_file_offset(),
+            _ => None,
+        };
+        Ok(self
+            .current_file_name()
+            .zip(offset)
+            .map(|(file_name, offset)| FilePosition { file_name, offset }))
+    }
+
+    /// All breakpoints as objects like
+    /// { target: { kind: "instruction", value: 10 }, enabled: true, hits: 2 }
+    /// where kind is one of "cycle", "instruction" or "file_offset"
+    pub fn breakpoints(&self) -> JsValue {
+        match &self.sim {
+            Simulator::VM(vm) => {
+                serde_wasm_bindgen::to_value(&vm.breakpoints()).unwrap_or(JsValue::NULL)
+            }
+            _ => JsValue::NULL,
+        }
+    }
+
+    /// The number of bytecode instructions executed since the program was loaded
+    pub fn instructions_executed(&self) -> u64 {
+        match &self.sim {
+            Simulator::VM(vm) => vm.instructions_executed(),
+            _ => 0,
+        }
+    }
+
+    /// The number of steps spent inside of builtin functions since the program was loaded
+    pub fn builtin_steps(&self) -> u64 {
+        match &self.sim {
+            Simulator::VM(vm) => vm.builtin_steps(),
+            _ => 0,
+        }
+    }
+
+    /// Count the cycles, executed instructions and builtin steps from 0 again
+    pub fn reset_counters(&mut self) {
+        if let Simulator::VM(vm) = &mut self.sim {
+            vm.reset_cycle_count();
+            vm.reset_profiler();
+        }
+    }
+
+    pub fn last_return_value(&self) -> Option<Word> {
+        if let Simulator::VM(vm) = &self.sim {
+            return vm.last_return_value();
+        }
+        None
+    }
+
+    /// The memory from start (inclusive) to end (exclusive)
+    /// Addresses outside of the memory are padded with 0
+    pub fn memory_range_padded(&self, start: Address, end: Address) -> Vec<Word> {
+        if let Simulator::VM(vm) = &self.sim {
+            return vm.mem_range_padded(start..end);
+        }
+        Vec::new()
+    }
+
+    /// Statistics about the free list of the builtin Memory implementation
+    pub fn heap_stats(&self) -> Option<HeapStats> {
+        if let Simulator::VM(vm) = &self.sim {
+            return Some(vm.heap_stats().into());
+        }
+        None
+    }
+
+    /// Whether the vm is inside a builtin function, which can't be stepped into
+    pub fn in_builtin(&self) -> bool {
+        if let Simulator::VM(vm) = &self.sim {
+            return vm.in_builtin();
+        }
+        false
+    }
+
     pub fn current_function_name(&self) -> Option<String> {
         if let Simulator::VM(vm) = &self.sim {
             return vm.current_function_name().map(|n| n.to_owned());
@@ -298,6 +959,11 @@ impl App {
         None
     }
 
+    /// The index of the loaded file with the given name, as used by FileInfo::VM
+    fn module_index(&self, file_name: &str) -> Option<usize> {
+        self.programs.iter().position(|(name, _)| name == file_name)
+    }
+
     pub fn current_file_name(&self) -> Option<String> {
         if let Simulator::VM(vm) = &self.sim {
             match vm.current_file_info()? {
@@ -311,3 +977,307 @@ impl App {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_stop() {
+        let mut app = App::new();
+        app.add_file(
+            "Main.vm".to_owned(),
+            "label LOOP\npush constant 1\npop temp 0\ngoto LOOP".to_owned(),
+        );
+        app.load_files().unwrap();
+
+        app.request_stop();
+        let report = app.step_times(100);
+        assert_eq!(0, report.executed);
+        assert_eq!(StopReason::Stopped, report.reason);
+
+        // the request only applies to a single call
+        let report = app.step_times(100);
+        assert_eq!(100, report.executed);
+        assert_eq!(StopReason::Completed, report.reason);
+
+        let report = app.step_times(u32::MAX);
+        assert_eq!(MAX_STEPS_PER_CALL, report.executed);
+    }
+
+    #[test]
+    fn test_display_buffer_without_program_is_white() {
+        let app = App::new();
+        let buffer = app.display_buffer();
+        assert_eq!(App::data_buffer_size(), buffer.len());
+        assert!(buffer.iter().all(|&byte| byte == 255));
+    }
+
+    #[test]
+    fn test_jack_files_are_rejected() {
+        let mut app = App::new();
+        app.add_file("Main.vm".to_owned(), "push constant 1".to_owned());
+        assert_eq!(Ok(()), app.validate_files());
+
+        app.add_file("Main.jack".to_owned(), "class Main {}".to_owned());
+        assert_eq!(
+            Err("Main.jack: Jack source must be compiled to .vm first; \
+                 this emulator does not include the Jack compiler"
+                .to_owned()),
+            app.validate_files()
+        );
+    }
+
+    #[test]
+    fn test_mixed_vm_and_asm_files_are_rejected() {
+        let mut app = App::new();
+        app.add_file("Main.vm".to_owned(), "push constant 1".to_owned());
+        app.add_file("Prog.asm".to_owned(), "@0".to_owned());
+        assert_eq!(
+            Err("Prog.asm: cannot be loaded together with .vm files; \
+                 either load multiple .vm files or a single .asm file"
+                .to_owned()),
+            app.validate_files()
+        );
+    }
+
+    #[test]
+    fn test_multiple_asm_files_are_rejected() {
+        let mut app = App::new();
+        app.add_file("First.asm".to_owned(), "@0".to_owned());
+        assert_eq!(Ok(()), app.validate_files());
+
+        app.add_file("Second.asm".to_owned(), "@1".to_owned());
+        assert_eq!(
+            Err("Second.asm: only a single .asm file can be loaded at a time".to_owned()),
+            app.validate_files()
+        );
+    }
+
+    #[test]
+    fn test_unknown_file_types_are_rejected() {
+        let mut app = App::new();
+        app.add_file("notes.txt".to_owned(), "hello".to_owned());
+        assert_eq!(
+            Err("notes.txt: unsupported file type, expected .vm or .asm".to_owned()),
+            app.validate_files()
+        );
+
+        // the cpu emulator can only parse assembly, not assembled machine code
+        let mut app = App::new();
+        app.add_file("Prog.hack".to_owned(), "0000000000000000".to_owned());
+        assert_eq!(
+            Err("Prog.hack: unsupported file type, expected .vm or .asm".to_owned()),
+            app.validate_files()
+        );
+    }
+
+    #[test]
+    fn test_memory_dump() {
+        let mut app = App::new();
+        assert_eq!("", app.memory_dump(WordFormat::Decimal));
+
+        app.add_file(
+            "Main.vm".to_owned(),
+            "push constant 10\npop temp 0\nlabel LOOP\ngoto LOOP".to_owned(),
+        );
+        app.load_files().unwrap();
+        app.step_times(2);
+
+        // the popped value is still in the memory cell above the stack
+        assert_eq!(
+            "    0: 0100\n    5: 000A\n  256: 000A\n",
+            app.memory_dump(WordFormat::Hex)
+        );
+        assert_eq!(
+            "    4: 0\n    5: 10\n",
+            app.memory_range_dump(WordFormat::Decimal, 4, 6)
+        );
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut app = App::new();
+        app.add_file(
+            "Main.vm".to_owned(),
+            "push constant 10\npop temp 0\nlabel LOOP\ngoto LOOP".to_owned(),
+        );
+        app.load_files().unwrap();
+        app.step_times(3);
+        assert_eq!(Some(10), app.memory_at(5));
+
+        app.restart().unwrap();
+        assert_eq!(Some(0), app.memory_at(5));
+        assert_eq!(Some(0), app.current_file_offset());
+
+        app.step_times(2);
+        assert_eq!(Some(10), app.memory_at(5));
+    }
+
+    #[test]
+    fn test_full_listing() {
+        let mut app = App::new();
+        assert_eq!("", app.full_listing());
+
+        app.add_file(
+            "Main.vm".to_owned(),
+            "function Main.main 0
+             push constant 1
+             call Main.double 1
+             return
+             function Main.double 0
+             label START
+             push argument 0
+             push argument 0
+             add
+             if-goto START
+             return"
+                .to_owned(),
+        );
+        app.load_files().unwrap();
+
+        let listing = app.full_listing();
+        assert!(listing.starts_with("// Main.main\n0: function Main.main 0\n"));
+        assert!(listing.contains("2: call Main.double 1\n"));
+        assert!(listing.contains("\n\n// Main.double\n4: function Main.double 0\n"));
+        assert!(listing.ends_with("8: if-goto 5\n9: return\n"));
+    }
+
+    #[test]
+    fn test_memory_range() {
+        use crate::definitions::MEM_SIZE;
+
+        let mut app = App::new();
+        assert!(app.memory_range(0, 10).is_empty());
+
+        app.add_file(
+            "Main.vm".to_owned(),
+            "push constant 10\npop temp 0\nlabel LOOP\ngoto LOOP".to_owned(),
+        );
+        app.load_files().unwrap();
+        app.step_times(2);
+
+        assert_eq!(vec![0, 10, 0], app.memory_range(4, 3));
+        assert_eq!(vec![0, 0], app.memory_range(MEM_SIZE - 2, usize::MAX));
+        assert!(app.memory_range(MEM_SIZE, 10).is_empty());
+    }
+
+    #[test]
+    fn test_disassemble_window() {
+        let mut app = App::new();
+        assert!(app.disassemble_window(0, 2).is_empty());
+
+        app.add_file(
+            "Main.vm".to_owned(),
+            "push constant 10\npop temp 0\nlabel LOOP\ngoto LOOP".to_owned(),
+        );
+        app.load_files().unwrap();
+
+        assert_eq!(
+            vec!["push constant 10", "pop temp 0"],
+            app.disassemble_window(0, 1)
+        );
+        assert_eq!(
+            vec!["push constant 10", "pop temp 0", "goto 2"],
+            app.disassemble_window(1, 5)
+        );
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_load_zip() {
+        use std::io::{Cursor, Write};
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        let files = [
+            (
+                "Main.vm",
+                "function Main.main 0\npush constant 7\npop temp 0\nlabel LOOP\ngoto LOOP",
+            ),
+            (
+                "Sys.vm",
+                "function Sys.init 0\ncall Main.main 0\nlabel LOOP\ngoto LOOP",
+            ),
+        ];
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let mut app = App::new();
+        app.add_file("Old.vm".to_owned(), "push constant 1".to_owned());
+        app.load_zip(&bytes).unwrap();
+        assert_eq!(2, app.programs.len());
+
+        app.step_times(100);
+        assert_eq!(Some(7), app.memory_at(5));
+    }
+
+    #[test]
+    fn test_run_until_file_breakpoint() {
+        let mut app = App::new();
+        app.add_file(
+            "Main.vm".to_owned(),
+            "function Main.main 0\npush constant 7\npop temp 0\nlabel LOOP\ngoto LOOP".to_owned(),
+        );
+        app.add_file(
+            "Sys.vm".to_owned(),
+            "function Sys.init 0\ncall Main.main 0\nlabel LOOP\ngoto LOOP".to_owned(),
+        );
+        app.load_files().unwrap();
+
+        app.add_file_breakpoint("Main.vm", 2).unwrap();
+        assert_eq!(
+            Some(FilePosition {
+                file_name: "Main.vm".to_owned(),
+                offset: 2
+            }),
+            app.run_until_breakpoint(100).unwrap()
+        );
+        // stopped right before pop temp 0
+        assert_eq!(Some(0), app.memory_at(5));
+    }
+
+    #[test]
+    fn test_step_times_reports_partial_progress() {
+        let bytecode = r#"
+            function Main.main 0
+            push constant 1
+            pop temp 0
+            call Sys.halt 0
+            "#;
+
+        let programs = vec![bytecode::SourceFile::new("Main.vm", bytecode)];
+        let program = BytecodeParser::with_stdlib(programs, Stdlib::new())
+            .parse()
+            .unwrap();
+        let mut vm = VM::new(Stdlib::new());
+        vm.load(program);
+        vm.set_entry_function("Main.main").unwrap();
+        let mut sim = Simulator::VM(vm.into());
+
+        // jump to Main.main, function, push, pop and then halt
+        let report = sim.step_times(100);
+        assert_eq!(4, report.executed);
+        assert_eq!(StopReason::Halted, report.reason);
+        assert_eq!(None, report.error);
+
+        if let Simulator::VM(vm) = &mut sim {
+            vm.reset();
+            vm.add_cycle_breakpoint(2);
+        }
+        let report = sim.step_times(100);
+        assert_eq!(2, report.executed);
+        assert_eq!(StopReason::Breakpoint, report.reason);
+
+        let report = Simulator::None.step_times(10);
+        assert_eq!(0, report.executed);
+        assert_eq!(StopReason::Error, report.reason);
+        assert!(report.error.is_some());
+    }
+}
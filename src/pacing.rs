@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+/// Keeps the desktop run loop at a fixed number of frames per second
+///
+/// After every frame the steps per tick are scaled so that the work of one frame fits into the
+/// frame time, but never above the steps per tick that were passed on the command line
+pub struct FramePacer {
+    frame_time: Duration,
+    max_steps_per_tick: usize,
+    steps_per_tick: usize,
+}
+
+impl FramePacer {
+    pub fn new(fps: u32, max_steps_per_tick: usize) -> Self {
+        Self {
+            frame_time: Duration::from_secs(1) / fps.max(1),
+            max_steps_per_tick,
+            steps_per_tick: max_steps_per_tick,
+        }
+    }
+
+    pub fn steps_per_tick(&self) -> usize {
+        self.steps_per_tick
+    }
+
+    /// Adjust the steps per tick to the time the last frame took
+    /// Returns how long to sleep before starting the next frame
+    pub fn end_frame(&mut self, elapsed: Duration) -> Duration {
+        if !elapsed.is_zero() {
+            let scale = self.frame_time.as_secs_f64() / elapsed.as_secs_f64();
+            let steps = (self.steps_per_tick as f64 * scale) as usize;
+            self.steps_per_tick = steps.clamp(1, self.max_steps_per_tick);
+        }
+
+        self.frame_time.saturating_sub(elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fast_frames_sleep_for_the_rest_of_the_frame() {
+        let mut pacer = FramePacer::new(50, 1000);
+
+        assert_eq!(
+            Duration::from_millis(15),
+            pacer.end_frame(Duration::from_millis(5))
+        );
+        // the steps are never increased above the maximum
+        assert_eq!(1000, pacer.steps_per_tick());
+    }
+
+    #[test]
+    fn test_slow_frames_reduce_the_steps_per_tick() {
+        let mut pacer = FramePacer::new(50, 1000);
+
+        assert_eq!(Duration::ZERO, pacer.end_frame(Duration::from_millis(40)));
+        assert_eq!(500, pacer.steps_per_tick());
+
+        // once the frames are fast enough again, the steps go back up
+        pacer.end_frame(Duration::from_millis(10));
+        assert_eq!(1000, pacer.steps_per_tick());
+
+        // there is always at least one step per tick
+        pacer.end_frame(Duration::from_secs(100));
+        assert_eq!(1, pacer.steps_per_tick());
+    }
+}
@@ -222,20 +222,30 @@ impl<'src> Iterator for Lexer<'src> {
 }
 
 pub struct SourceFile<'src> {
+    source: &'src str,
     lexer: Peekable<Lexer<'src>>,
 }
 
 impl<'src> SourceFile<'src> {
     pub fn new(source: &'src str) -> Self {
         Self {
+            source,
             lexer: Lexer::new(source).peekable(),
         }
     }
 }
 
+/// The comment at the end of the given line (starting at 1) including the //
+fn trailing_comment<'src>(lines: &[&'src str], line_nr: u32) -> Option<&'src str> {
+    let line = lines.get(line_nr.checked_sub(1)? as usize)?;
+    line.find("//").map(|start| line[start..].trim_end())
+}
+
 pub struct AssemblyParser<'src> {
     source: SourceFile<'src>,
     symbols: SymbolTable,
+    // the line of the last token returned by next_token
+    line_nr: u32,
 }
 
 impl<'src> AssemblyParser<'src> {
@@ -266,15 +276,21 @@ impl<'src> AssemblyParser<'src> {
         symbols.set("SCREEN", SCREEN_START as Symbol);
         symbols.set("KBD", KBD as Symbol);
 
-        Self { source, symbols }
+        Self {
+            source,
+            symbols,
+            line_nr: 0,
+        }
     }
 
     fn next_token(&mut self) -> ParseResult<Token<'src>> {
-        self.source
+        let spanned = self
+            .source
             .lexer
             .next()
-            .ok_or(AssemblyParseError::EndOfFile)?
-            .map(|s| s.content)
+            .ok_or(AssemblyParseError::EndOfFile)??;
+        self.line_nr = spanned.line_nr;
+        Ok(spanned.content)
     }
 
     fn consume_token(&mut self, token: Token<'src>) -> ParseResult<Token<'src>> {
@@ -412,12 +428,32 @@ impl<'src> AssemblyParser<'src> {
     }
 
     pub fn parse(&mut self) -> ParseResult<Vec<Instruction>> {
+        self.parse_with_lines()
+            .map(|(instructions, _)| instructions)
+    }
+
+    /// Like parse, but also returns the trailing comment of the line each instruction is on
+    /// e.g. the comment of 'M=1 // i=1' is '// i=1'
+    pub fn parse_with_comments(&mut self) -> ParseResult<(Vec<Instruction>, Vec<Option<String>>)> {
+        let (instructions, lines) = self.parse_with_lines()?;
+        let source_lines: Vec<&str> = self.source.source.lines().collect();
+        let comments = lines
+            .into_iter()
+            .map(|line_nr| trailing_comment(&source_lines, line_nr).map(str::to_owned))
+            .collect();
+        Ok((instructions, comments))
+    }
+
+    /// Parse the instructions and the line of the source each instruction was on
+    fn parse_with_lines(&mut self) -> ParseResult<(Vec<Instruction>, Vec<u32>)> {
         enum CodeEntry<'src> {
             Instruction(Instruction),
             WaitingForLabel(&'src str, Instruction),
         }
 
         let mut code: Vec<CodeEntry<'src>> = Vec::with_capacity(128);
+        let mut lines: Vec<u32> = Vec::with_capacity(128);
+        let mut statement_line = 0;
 
         fn push_instr(code: &mut Vec<CodeEntry>, value: Instruction) {
             code.push(CodeEntry::Instruction(value));
@@ -439,10 +475,14 @@ impl<'src> AssemblyParser<'src> {
         }
 
         loop {
+            // the instruction of the last iteration is in the line of its first token
+            lines.resize(code.len(), statement_line);
+
             let token = self.next_token();
             if let Err(AssemblyParseError::EndOfFile) = token {
                 break;
             }
+            statement_line = self.line_nr;
 
             match token? {
                 Token::AConst(value) => push_instr(&mut code, Instruction::A(value)),
@@ -513,7 +553,7 @@ impl<'src> AssemblyParser<'src> {
             }
         }
 
-        Ok(instructions)
+        Ok((instructions, lines))
     }
 }
 
@@ -1,8 +1,8 @@
 use super::symbols::SymbolTable;
 use super::{Spanned, StringLexer};
-use crate::definitions::Symbol;
+use crate::definitions::{Address, Symbol};
 use crate::simulators::vm::command::{ByteCodeParseError, Instruction, Segment};
-use crate::simulators::vm::meta::{FunctionInfo, MetaInfo};
+use crate::simulators::vm::meta::{FileInfo, FunctionInfo, MetaInfo};
 use crate::simulators::vm::stdlib::Stdlib;
 use crate::simulators::vm::ProgramInfo;
 use std::num::ParseIntError;
@@ -10,6 +10,7 @@ use std::num::ParseIntError;
 use std::collections::{HashMap, HashSet};
 use std::error;
 use std::fmt;
+use std::ops::Range;
 use std::str::FromStr;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -25,6 +26,7 @@ pub enum BytecodeParseError {
     ExpectedIdent,
     ExpectedSegment,
     ExpectedInt,
+    NegativeLocalCount(i16),
     InvalidToken,
     UnresolvedLocalLabel {
         label: String,
@@ -58,6 +60,13 @@ impl fmt::Display for BytecodeParseError {
             Self::ExpectedIdent => write!(f, "Expected identifier"),
             Self::ExpectedSegment => write!(f, "Expected segment"),
             Self::ExpectedInt => write!(f, "Expected integer"),
+            Self::NegativeLocalCount(count) => {
+                write!(
+                    f,
+                    "A function can't have a negative number of locals: {}",
+                    count
+                )
+            }
             Self::InvalidToken => write!(f, "Invalid token"),
             Self::UnresolvedLocalLabel {
                 label,
@@ -86,6 +95,7 @@ enum Token<'src> {
     IntLiteral(i16),
 }
 
+#[derive(Clone)]
 struct Lexer<'src> {
     walker: StringLexer<'src>,
 }
@@ -97,6 +107,47 @@ impl<'src> Lexer<'src> {
         }
     }
 
+    /// Check if the next token is an int without consuming it
+    fn next_is_int(&self) -> bool {
+        let mut lookahead = self.clone();
+        matches!(
+            lookahead.scan_token(),
+            Ok(Spanned {
+                content: Token::IntLiteral(_),
+                ..
+            })
+        )
+    }
+
+    /// The names in a "// @locals name1 name2" comment, if that comment is the next thing in the
+    /// source. This is an optional annotation after a function to label its locals in the UI
+    fn locals_annotation(&self) -> Vec<&'src str> {
+        let mut lookahead = self.walker.clone();
+        lookahead.take_chars_while(char::is_whitespace);
+        if !lookahead.current_eq('/') {
+            return Vec::new();
+        }
+
+        lookahead
+            .take_chars_while(|c| c != '\n')
+            .and_then(|comment| comment.content.strip_prefix("//"))
+            .and_then(|comment| comment.trim_start().strip_prefix("@locals"))
+            .filter(|names| names.starts_with(char::is_whitespace))
+            .map(|names| names.split_whitespace().collect())
+            .unwrap_or_default()
+    }
+
+    /// Consume a '-' in front of the next int, ints themselves are always positive
+    /// This is only used to give a better error for negative local counts
+    fn consume_minus(&mut self) -> bool {
+        self.walker.take_chars_while(char::is_whitespace);
+        let minus = self.walker.current_eq('-');
+        if minus {
+            self.walker.advance();
+        }
+        minus
+    }
+
     fn consume_ident(&mut self) -> ParseResult<Spanned<&'src str>> {
         self.walker
             .take_chars_while(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '-')
@@ -169,6 +220,15 @@ pub struct BytecodeParser<'src> {
     global_symbols: SymbolTable,
     // every entry represents the symbols in the current function (labels)
     function_symbols: Vec<SymbolTable>,
+    // if this is set, a missing argument count on call and local count on function
+    // default to 0. This is not allowed by the spec, but some compilers generate it
+    lenient_counts: bool,
+    // if this is set, "call-indirect <n_args>" calls the function whose address is on the stack
+    // This is not part of the spec, so it's disabled by default
+    indirect_calls: bool,
+    // the addresses of the static variables of each file
+    // statics are allocated while parsing a file, so the addresses of a single file are contiguous
+    static_ranges: HashMap<String, Range<Address>>,
     stdlib: Stdlib,
 }
 
@@ -179,10 +239,21 @@ impl<'src> BytecodeParser<'src> {
             sources,
             global_symbols: SymbolTable::default(),
             function_symbols: vec![SymbolTable::default()],
+            lenient_counts: false,
+            indirect_calls: false,
+            static_ranges: HashMap::new(),
             stdlib,
         }
     }
 
+    pub fn set_lenient_counts(&mut self, lenient: bool) {
+        self.lenient_counts = lenient;
+    }
+
+    pub fn set_indirect_calls(&mut self, enabled: bool) {
+        self.indirect_calls = enabled;
+    }
+
     // TODO: refactor those 3 functions
     fn function_symbols(&mut self) -> ParseResult<&mut SymbolTable> {
         self.function_symbols
@@ -205,6 +276,10 @@ impl<'src> BytecodeParser<'src> {
     }
 
     fn next_token(&mut self) -> ParseResult<Token<'src>> {
+        self.next_spanned_token().map(|tok| tok.content)
+    }
+
+    fn next_spanned_token(&mut self) -> ParseResult<Spanned<Token<'src>>> {
         let current_lexer = self.lexer()?;
         match current_lexer.scan_token() {
             Err(BytecodeParseError::EndOfFile) => {
@@ -214,11 +289,10 @@ impl<'src> BytecodeParser<'src> {
                     // no more files, so just return the error
                     Err(BytecodeParseError::EndOfFile)
                 } else {
-                    self.next_token()
+                    self.next_spanned_token()
                 }
             }
-            Err(err) => Err(err),
-            Ok(tok) => Ok(tok.content),
+            result => result,
         }
     }
 
@@ -239,15 +313,33 @@ impl<'src> BytecodeParser<'src> {
         }
     }
 
+    /// The argument count of a call or the local count of a function
+    fn consume_count(&mut self) -> ParseResult<i16> {
+        // only look at the current file, the count can't be in the next one
+        if self.lenient_counts && !self.lexer()?.next_is_int() {
+            return Ok(0);
+        }
+        self.consume_int()
+    }
+
     fn consume_segment_with_index(&mut self) -> ParseResult<(Segment, i16)> {
         let segment = self.consume_segment()?;
         let mut index = self.consume_int()?;
 
         if segment == Segment::Static {
-            let file_name = self.filename()?;
+            let file_name = self.filename()?.to_owned();
             let symbol = format!("{}.{}", file_name, index);
 
-            index = self.global_symbols.lookup_or_insert(symbol) as i16;
+            let address = self.global_symbols.lookup_or_insert(symbol) as Address;
+            self.static_ranges
+                .entry(file_name)
+                .and_modify(|range| {
+                    range.start = range.start.min(address);
+                    range.end = range.end.max(address + 1);
+                })
+                .or_insert(address..(address + 1));
+
+            index = address as i16;
         }
 
         Ok((segment, index))
@@ -295,6 +387,8 @@ impl<'src> BytecodeParser<'src> {
         }
 
         let mut code: Vec<CodeEntry<'src>> = Vec::with_capacity(128);
+        // the source line of every entry in code
+        let mut source_lines = Vec::with_capacity(128);
         let mut debug_symbols = HashMap::new();
 
         fn push_instr(code: &mut Vec<CodeEntry>, value: Instruction) {
@@ -330,7 +424,7 @@ impl<'src> BytecodeParser<'src> {
 
         loop {
             let last_module_index = self.module_index;
-            let token = self.next_token();
+            let token = self.next_spanned_token();
             if let Err(BytecodeParseError::EndOfFile) = token {
                 break;
             }
@@ -339,7 +433,13 @@ impl<'src> BytecodeParser<'src> {
                 file_start = code.len();
             }
 
-            match token? {
+            let Spanned {
+                content: token,
+                line_nr,
+                ..
+            } = token?;
+
+            match token {
                 Token::Identifier("push") => {
                     let (segment, index) = self.consume_segment_with_index()?;
                     push_instr(&mut code, Instruction::Push { segment, index });
@@ -364,12 +464,23 @@ impl<'src> BytecodeParser<'src> {
                 Token::Identifier("function") => {
                     let symbol = code.len() as Symbol;
                     let label = self.consume_label(symbol, false)?;
-                    let n_locals = self.consume_int()?;
-
-                    debug_symbols.insert(
-                        code.len() as u16,
-                        FunctionInfo::vm(label.to_owned(), n_locals, self.module_index, file_start),
-                    );
+                    let negative = self.lexer()?.consume_minus();
+                    let n_locals = self.consume_count()?;
+                    if negative {
+                        return Err(BytecodeParseError::NegativeLocalCount(-n_locals));
+                    }
+                    let n_locals = n_locals as u16;
+
+                    let mut info =
+                        FunctionInfo::vm(label.to_owned(), n_locals, self.module_index, file_start);
+                    info.local_names = self
+                        .lexer()?
+                        .locals_annotation()
+                        .into_iter()
+                        .map(str::to_owned)
+                        .collect();
+
+                    debug_symbols.insert(code.len() as u16, info);
                     function_addresses.insert(label.to_owned(), symbol);
                     self.function_symbols.push(SymbolTable::default());
 
@@ -384,12 +495,16 @@ impl<'src> BytecodeParser<'src> {
                 }
                 Token::Identifier("call") => {
                     let target = self.consume_symbol()?;
-                    let n_args = self.consume_int()?;
+                    let n_args = self.consume_count()?;
 
                     // placeholder
                     let function = 0;
                     push_target(&mut code, target, Instruction::Call { function, n_args });
                 }
+                Token::Identifier("call-indirect") if self.indirect_calls => {
+                    let n_args = self.consume_count()?;
+                    push_instr(&mut code, Instruction::CallIndirect { n_args });
+                }
                 Token::Identifier("add") => push_instr(&mut code, Instruction::Add),
                 Token::Identifier("sub") => push_instr(&mut code, Instruction::Sub),
                 Token::Identifier("eq") => push_instr(&mut code, Instruction::Eq),
@@ -401,6 +516,8 @@ impl<'src> BytecodeParser<'src> {
                 Token::Identifier("neg") => push_instr(&mut code, Instruction::Neg),
                 _ => return Err(BytecodeParseError::InvalidToken),
             };
+
+            source_lines.resize(code.len(), line_nr);
         }
 
         // TODO: check program length cannot be larger than u16::MAX - NUMBER_OF_STDLIB_FUNCTIONS
@@ -470,11 +587,10 @@ impl<'src> BytecodeParser<'src> {
         }
 
         if unresolved.is_empty() {
-            Ok(ParsedProgram::new(
-                instructions,
-                debug_symbols,
-                function_addresses,
-            ))
+            let mut program = ParsedProgram::new(instructions, debug_symbols, function_addresses);
+            program.meta.static_ranges = std::mem::take(&mut self.static_ranges);
+            program.meta.source_lines = source_lines;
+            Ok(program)
         } else {
             Err(BytecodeParseError::UnresolvedSymbols(HashSet::from_iter(
                 unresolved.iter().copied().map(str::to_owned),
@@ -502,6 +618,35 @@ impl ParsedProgram {
     }
 }
 
+/// The size of a program, so that it can be shown before the program is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramStats {
+    pub instructions: usize,
+    /// only the functions defined in the bytecode, not the builtin ones
+    pub functions: usize,
+    pub statics: usize,
+}
+
+impl ParsedProgram {
+    pub fn stats(&self) -> ProgramStats {
+        let functions = self
+            .meta
+            .function_meta
+            .values()
+            .filter(|f| matches!(f.file, FileInfo::VM { .. }))
+            .count();
+
+        // static addresses are handed out file by file, so the ranges don't have any gaps
+        let statics = self.meta.static_ranges.values().map(|r| r.len()).sum();
+
+        ProgramStats {
+            instructions: self.instructions.len(),
+            functions,
+            statics,
+        }
+    }
+}
+
 impl ProgramInfo for ParsedProgram {
     fn take_instructions(&mut self) -> Vec<Instruction> {
         std::mem::take(&mut self.instructions)
@@ -768,6 +913,25 @@ mod tests {
         assert_eq!(parsed_bytecode.instructions, expected_bytecode);
     }
 
+    #[test]
+    fn test_parse_locals_annotation() {
+        let bytecode = "function Main.a 2 // @locals sum count
+            return
+            function Main.b 1
+            //   @locals   i
+            return
+            function Main.c 1 // @localsX i
+            return";
+
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let program = BytecodeParser::new(programs).parse().unwrap();
+        let names = |address: Symbol| &program.meta.function_meta[&address].local_names;
+
+        assert_eq!(&vec!["sum".to_owned(), "count".to_owned()], names(0));
+        assert_eq!(&vec!["i".to_owned()], names(2));
+        assert!(names(4).is_empty());
+    }
+
     #[test]
     fn test_parse_basic_loop() {
         let bytecode = r#"
@@ -1371,4 +1535,155 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_lenient_counts_default_to_zero() {
+        let foo = "
+            function Foo.bar
+            push constant 1
+            return";
+
+        let main = "
+            function Main.main 0
+            call Foo.bar
+            return
+            function Main.end
+            call Foo.bar";
+
+        let sources = || {
+            vec![
+                SourceFile::new("Foo.vm", foo),
+                SourceFile::new("Main.vm", main),
+            ]
+        };
+
+        // strict by default
+        assert!(BytecodeParser::new(sources()).parse().is_err());
+
+        let mut parser = BytecodeParser::new(sources());
+        parser.set_lenient_counts(true);
+        let result = parser.parse().unwrap();
+
+        assert_eq!(
+            result.instructions,
+            vec![
+                Instruction::Function { n_locals: 0 },
+                Instruction::Push {
+                    segment: Segment::Constant,
+                    index: 1
+                },
+                Instruction::Return,
+                Instruction::Function { n_locals: 0 },
+                Instruction::Call {
+                    function: 0,
+                    n_args: 0
+                },
+                Instruction::Return,
+                Instruction::Function { n_locals: 0 },
+                Instruction::Call {
+                    function: 0,
+                    n_args: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indirect_calls_are_opt_in() {
+        let main = "
+            function Main.main 0
+            push constant 0
+            call-indirect 0
+            return";
+
+        let sources = || vec![SourceFile::new("Main.vm", main)];
+
+        assert_eq!(
+            Err(BytecodeParseError::InvalidToken),
+            BytecodeParser::new(sources()).parse().map(|_| ())
+        );
+
+        let mut parser = BytecodeParser::new(sources());
+        parser.set_indirect_calls(true);
+        let result = parser.parse().unwrap();
+
+        assert_eq!(
+            result.instructions,
+            vec![
+                Instruction::Function { n_locals: 0 },
+                Instruction::Push {
+                    segment: Segment::Constant,
+                    index: 0
+                },
+                Instruction::CallIndirect { n_args: 0 },
+                Instruction::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_negative_local_count() {
+        let programs = vec![SourceFile::new("Foo.vm", "function Foo.bar -1\nreturn")];
+        let result = BytecodeParser::new(programs).parse();
+
+        assert!(matches!(
+            result,
+            Err(BytecodeParseError::NegativeLocalCount(-1))
+        ));
+    }
+
+    #[test]
+    fn test_negative_ints_are_rejected() {
+        for source in [
+            "function Foo.bar 0\npush constant -1",
+            "function Foo.bar 0\npop local -3",
+            "function Foo.bar 0\ncall X.y -1",
+        ] {
+            let programs = vec![SourceFile::new("Foo.vm", source)];
+            assert_eq!(
+                Err(BytecodeParseError::UnexpectedCharacter('-')),
+                BytecodeParser::new(programs).parse().map(|_| ())
+            );
+        }
+    }
+
+    #[test]
+    fn test_program_stats() {
+        let main = r#"
+            function Main.main 1
+            push static 0
+            push static 1
+            call Main.helper 2
+            pop static 0
+            return
+
+            function Main.helper 0
+            push argument 0
+            return
+            "#;
+
+        let other = r#"
+            function Other.get 0
+            push static 0
+            call Math.abs 1
+            return
+            "#;
+
+        let programs = vec![
+            SourceFile::new("Main.vm", main),
+            SourceFile::new("Other.vm", other),
+        ];
+        let program = BytecodeParser::with_stdlib(programs, Stdlib::new())
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            ProgramStats {
+                instructions: 13,
+                functions: 3,
+                statics: 3
+            },
+            program.stats()
+        );
+    }
 }
@@ -0,0 +1,75 @@
+use crate::definitions::Word;
+
+use std::fmt::Write;
+
+/// Builds the source of a test script in code
+///
+/// Every command is terminated with a ';', so the result can be passed to the ScriptParser
+/// like any .tst file, e.g. `ScriptBuilder::new().set("RAM[0]", 42).vmstep().build()`
+#[derive(Debug, Default, Clone)]
+pub struct ScriptBuilder {
+    source: String,
+}
+
+impl ScriptBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn command(mut self, command: &str) -> Self {
+        self.source.push_str(command);
+        self.source.push_str(";\n");
+        self
+    }
+
+    pub fn load(self, path: &str) -> Self {
+        self.command(&format!("load {}", path))
+    }
+
+    pub fn output_file(self, path: &str) -> Self {
+        self.command(&format!("output-file {}", path))
+    }
+
+    pub fn compare_to(self, path: &str) -> Self {
+        self.command(&format!("compare-to {}", path))
+    }
+
+    /// The entries use the same syntax as in a .tst file, e.g. "RAM[0]%D1.6.1"
+    pub fn output_list(self, entries: &[&str]) -> Self {
+        self.command(&format!("output-list {}", entries.join(" ")))
+    }
+
+    /// The target uses the same syntax as in a .tst file, e.g. "RAM[256]" or "local[1]"
+    pub fn set(self, target: &str, value: Word) -> Self {
+        self.command(&format!("set {} {}", target, value))
+    }
+
+    pub fn vmstep(self) -> Self {
+        self.command("vmstep")
+    }
+
+    pub fn ticktock(self) -> Self {
+        self.command("ticktock")
+    }
+
+    pub fn output(self) -> Self {
+        self.command("output")
+    }
+
+    pub fn echo(self, text: &str) -> Self {
+        let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+        self.command(&format!("echo \"{}\"", escaped))
+    }
+
+    /// Repeat the commands added by body
+    pub fn repeat(mut self, times: usize, body: impl FnOnce(Self) -> Self) -> Self {
+        let inner = body(Self::new());
+        // the ; after the closing brace is optional, so it is left out
+        write!(self.source, "repeat {} {{\n{}}}\n", times, inner.source).unwrap();
+        self
+    }
+
+    pub fn build(self) -> String {
+        self.source
+    }
+}
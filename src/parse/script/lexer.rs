@@ -14,6 +14,8 @@ pub enum Keyword {
     While,
     Echo,
     ClearEcho,
+    ClearScreen,
+    ClearOutput,
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
@@ -68,6 +70,7 @@ impl<'tst> Lexer<'tst> {
                 || c == '.'
                 || c == ':'
                 || c == '%'
+                || c == '+'
                 || c == '['
                 || c == ']'
         })
@@ -113,6 +116,8 @@ impl<'tst> Lexer<'tst> {
                     "output" => Some(Keyword::Output),
                     "echo" => Some(Keyword::Echo),
                     "clear-echo" => Some(Keyword::ClearEcho),
+                    "clear-screen" => Some(Keyword::ClearScreen),
+                    "clear-output" => Some(Keyword::ClearOutput),
                     "breakpoint" => Some(Keyword::Breakpoint),
                     "clear-breakpoints" => Some(Keyword::ClearBreakpoints),
                     "repeat" => Some(Keyword::Repeat),
@@ -125,20 +130,21 @@ impl<'tst> Lexer<'tst> {
                 // ... otherwise just return it as an identifier
                 Some(spanned.with_new_content(Token::Identifier(ident.to_string())))
             }
-            c if c.is_numeric() => {
-                let spanned = self.walker.take_chars_while(char::is_numeric)?;
+            c if c.is_ascii_digit() => {
+                // always decimal, leading zeros (e.g. 007) don't mean octal
+                let spanned = self.walker.take_chars_while(|c| c.is_ascii_digit())?;
                 let parsed_int = spanned.content.parse::<i32>().ok()?;
                 Some(spanned.with_new_content(Token::IntLiteral(parsed_int)))
             }
             '-' => {
                 let minus = self.walker.advance()?;
-                let spanned = self.walker.take_chars_while(char::is_numeric)?;
+                let spanned = self.walker.take_chars_while(|c| c.is_ascii_digit())?;
                 let parsed_int = spanned.content.parse::<i32>().ok()?;
                 Some(Spanned::new(
                     minus.start_idx,
                     spanned.end_idx,
                     minus.line_nr,
-                    Token::IntLiteral(parsed_int),
+                    Token::IntLiteral(-parsed_int),
                 ))
             }
             '%' => {
@@ -172,13 +178,34 @@ impl<'tst> Lexer<'tst> {
             }
             '"' => {
                 self.walker.advance()?;
-                let spanned = self.walker.take_chars_while(|c| c != '"')?;
-                let closing = self.walker.advance()?;
-                if closing.content != '"' {
-                    return None;
-                }
+                let start = self.walker.current_char()?;
+                let mut literal = String::new();
 
-                Some(spanned.with_new_content(Token::StringLiteral(spanned.content.to_owned())))
+                loop {
+                    let current = self.walker.advance()?;
+                    match current.content {
+                        '"' => {
+                            return Some(Spanned::new(
+                                start.start_idx,
+                                current.start_idx,
+                                start.line_nr,
+                                Token::StringLiteral(literal),
+                            ));
+                        }
+                        '\\' => {
+                            // escape sequences like \n or \"
+                            let escaped = match self.walker.advance()?.content {
+                                'n' => '\n',
+                                't' => '\t',
+                                '"' => '"',
+                                '\\' => '\\',
+                                _ => return None,
+                            };
+                            literal.push(escaped);
+                        }
+                        c => literal.push(c),
+                    }
+                }
             }
             _ => {
                 let spanned = self.walker.advance()?;
@@ -206,3 +233,73 @@ impl<'tst> Iterator for Lexer<'tst> {
         self.scan_token()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_string_literal_without_escapes() {
+        let mut lexer = Lexer::new(r#"echo "hello world";"#);
+        assert_eq!(
+            Some(Spanned::new(0, 4, 1, Token::Keyword(Keyword::Echo))),
+            lexer.next()
+        );
+        assert_eq!(
+            Some(Spanned::new(
+                6,
+                17,
+                1,
+                Token::StringLiteral("hello world".to_owned())
+            )),
+            lexer.next()
+        );
+        assert_eq!(
+            Some(Spanned::new(18, 19, 1, Token::Symbol(Symbol::Semicolon))),
+            lexer.next()
+        );
+    }
+
+    #[test]
+    fn test_string_literal_with_escaped_quote_and_newline() {
+        let mut lexer = Lexer::new(r#""say \"hi\"\nline2""#);
+        assert_eq!(
+            Some(Spanned::new(
+                1,
+                18,
+                1,
+                Token::StringLiteral("say \"hi\"\nline2".to_owned())
+            )),
+            lexer.next()
+        );
+        assert_eq!(None, lexer.next());
+    }
+
+    #[test]
+    fn test_unterminated_string_literal() {
+        let mut lexer = Lexer::new(r#""hello\""#);
+        assert_eq!(None, lexer.next());
+    }
+
+    #[test]
+    fn test_int_literals_with_leading_zeros() {
+        let mut lexer = Lexer::new("007 -007 0 000");
+        assert_eq!(
+            Some(Spanned::new(0, 3, 1, Token::IntLiteral(7))),
+            lexer.next()
+        );
+        assert_eq!(
+            Some(Spanned::new(4, 8, 1, Token::IntLiteral(-7))),
+            lexer.next()
+        );
+        assert_eq!(
+            Some(Spanned::new(9, 10, 1, Token::IntLiteral(0))),
+            lexer.next()
+        );
+        assert_eq!(
+            Some(Spanned::new(11, 14, 1, Token::IntLiteral(0))),
+            lexer.next()
+        );
+        assert_eq!(None, lexer.next());
+    }
+}
@@ -1,8 +1,10 @@
+pub mod builder;
 pub mod lexer;
 pub mod parser;
 pub mod tst;
 
 use super::Spanned;
+use crate::definitions::Word;
 use lexer::Token;
 use tst::{Command, SimulatorCommand};
 
@@ -22,6 +24,8 @@ pub enum ParseError {
     CouldNotParseOutputListEntry,
     EmptyOutputList,
     InvalidSetTarget(String),
+    ValueOutOfRange { command: String, value: i32 },
+    NegativeRepeatCount(i32),
 }
 
 impl fmt::Display for ParseError {
@@ -40,3 +44,35 @@ pub type CmdResult<SimulatorCommand> = ParseResult<Spanned<Command<SimulatorComm
 pub trait SimulatorCommandParser<SimCmd: SimulatorCommand> {
     fn parse_simulator_command(&mut self, ident: Spanned<&str>) -> CmdResult<SimCmd>;
 }
+
+/// Convert the value of a set command into a Word
+/// Like in the official emulators, unsigned 16 bit values (e.g. %XFFFF) wrap around to negative
+pub fn set_value(value: i32) -> Option<Word> {
+    (-32768..=65535)
+        .contains(&value)
+        .then_some(value as u16 as Word)
+}
+
+/// Parse the index of a set target like RAM[256] or RAM[256+4]
+/// The index can be a simple sum/difference of decimal numbers, which is evaluated left to right
+pub fn parse_index(index: &str) -> Option<usize> {
+    let mut value: isize = 0;
+    let mut sign = 1;
+    let mut rest = index;
+
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = isize::try_from(rest[..end].parse::<usize>().ok()?).ok()?;
+        value = value.checked_add(term.checked_mul(sign)?)?;
+
+        rest = &rest[end..];
+        sign = match rest.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => break,
+        };
+        rest = &rest[1..];
+    }
+
+    usize::try_from(value).ok()
+}
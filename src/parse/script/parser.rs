@@ -1,5 +1,7 @@
 use super::lexer::{ident_kind, int_kind, string_kind, Keyword, Lexer, Symbol, Token};
-use super::tst::{Command, CommandKind, OutputListEntry, SimulatorCommand, Terminator};
+use super::tst::{
+    Command, CommandKind, NumberFormat, OutputListEntry, SimulatorCommand, Terminator,
+};
 use super::{CmdResult, ParseError, ParseResult, SimulatorCommandParser, Spanned, TokResult};
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -37,7 +39,9 @@ where
     }
 
     fn controller_command(&mut self, kw: Spanned<Keyword>) -> CmdResult<SimCmd> {
-        use CommandKind::{CompareTo, Echo, Output, OutputFile, OutputList, Repeat};
+        use CommandKind::{
+            ClearOutput, ClearScreen, CompareTo, Echo, Output, OutputFile, OutputList, Repeat,
+        };
 
         match kw.content {
             Keyword::Repeat => {
@@ -55,7 +59,9 @@ where
                         ..
                     } = self.consume_token_kind(int_kind())?
                     {
-                        count as usize
+                        // a count of 0 is fine and just skips the block
+                        usize::try_from(count)
+                            .map_err(|_| ParseError::NegativeRepeatCount(count))?
                     } else {
                         unreachable!()
                     }
@@ -110,6 +116,14 @@ where
                 let cmd = Command::new(Output);
                 self.consume_terminator(kw.with_new_content(cmd))
             }
+            Keyword::ClearScreen => {
+                let cmd = Command::new(ClearScreen);
+                self.consume_terminator(kw.with_new_content(cmd))
+            }
+            Keyword::ClearOutput => {
+                let cmd = Command::new(ClearOutput);
+                self.consume_terminator(kw.with_new_content(cmd))
+            }
             Keyword::OutputList => {
                 let mut entries = Vec::new();
                 loop {
@@ -185,7 +199,7 @@ where
     fn ident_to_outputlist_entry(ident: Spanned<Token>) -> ParseResult<Spanned<OutputListEntry>> {
         lazy_static! {
             static ref RE: Regex =
-                Regex::new("(?P<name>[a-zA-Z-_]+(\\[\\d+\\])?)%(?P<format>[BDXS])(?P<left_pad>\\d+)\\.(?P<length>\\d+)\\.(?P<right_pad>\\d+)").unwrap();
+                Regex::new("(?P<name>[a-zA-Z-_]+(\\[\\d+\\])?)%(?:(?P<format>[BDXS])|F(?P<divisor>\\d+):)(?:(?P<left_pad>\\d+)\\.(?P<length>\\d+|auto)\\.(?P<right_pad>\\d+)|(?P<auto>auto))").unwrap();
         }
 
         if let Token::Identifier(lexeme) = &ident.content {
@@ -200,20 +214,32 @@ where
             };
 
             let name = caps["name"].to_string();
-            let format = caps["format"]
-                .try_into()
-                .or(Err(ParseError::CouldNotParseOutputListEntry))?;
+            let format = match caps.name("divisor") {
+                Some(divisor) => match divisor.as_str().parse() {
+                    Ok(divisor) if divisor > 0 => NumberFormat::Fixed(divisor),
+                    _ => return Err(ParseError::CouldNotParseOutputListEntry),
+                },
+                None => caps["format"]
+                    .try_into()
+                    .or(Err(ParseError::CouldNotParseOutputListEntry))?,
+            };
+
+            // the short form 'auto' uses the default padding of 1 on each side
+            if caps.name("auto").is_some() {
+                return Ok(ident.with_new_content(OutputListEntry::auto(name, format, 1, 1)));
+            }
+
             let left_padding = extract_num("left_pad")?;
-            let length = extract_num("length")?;
             let right_padding = extract_num("right_pad")?;
 
-            return Ok(ident.with_new_content(OutputListEntry::new(
-                name,
-                format,
-                left_padding,
-                length,
-                right_padding,
-            )));
+            let entry = if &caps["length"] == "auto" {
+                OutputListEntry::auto(name, format, left_padding, right_padding)
+            } else {
+                let length = extract_num("length")?;
+                OutputListEntry::new(name, format, left_padding, length, right_padding)
+            };
+
+            return Ok(ident.with_new_content(entry));
         }
 
         Err(ParseError::CouldNotParseOutputListEntry)
@@ -350,6 +376,35 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parser_consume_repeat_zero_and_negative() {
+        let mut parser = ScriptParser::<MockSimulatorParser, MockSimulatorCommand>::new(
+            Path::new("Test.tst"),
+            "repeat 0 {}",
+        );
+        assert_eq!(
+            Ok(Spanned::new(
+                0,
+                11,
+                1,
+                Command::new(CommandKind::Repeat {
+                    times: 0,
+                    block: vec![]
+                },)
+            )),
+            parser.next_command()
+        );
+
+        let mut parser = ScriptParser::<MockSimulatorParser, MockSimulatorCommand>::new(
+            Path::new("Test.tst"),
+            "repeat -1 {}",
+        );
+        assert_eq!(
+            Err(ParseError::NegativeRepeatCount(-1)),
+            parser.next_command()
+        );
+    }
+
     #[test]
     fn test_parser_consume_repeat_without_closing() {
         let mut parser = ScriptParser::<MockSimulatorParser, MockSimulatorCommand>::new(
@@ -412,4 +467,70 @@ mod test {
             )))
         );
     }
+
+    #[test]
+    fn test_parse_output_list_auto_width() {
+        let mut parser = ScriptParser::<MockSimulatorParser, MockSimulatorCommand>::new(
+            Path::new("Test.tst"),
+            "output-list a%Dauto b%X2.auto.3;",
+        );
+
+        let result = parser.next().unwrap().unwrap();
+        assert_eq!(
+            CommandKind::OutputList(vec![
+                OutputListEntry::auto("a".to_string(), NumberFormat::Decimal, 1, 1),
+                OutputListEntry::auto("b".to_string(), NumberFormat::Hex, 2, 3),
+            ]),
+            result.content.kind
+        );
+    }
+
+    #[test]
+    fn test_parse_output_list_fixed_point() {
+        let mut parser = ScriptParser::<MockSimulatorParser, MockSimulatorCommand>::new(
+            Path::new("Test.tst"),
+            "output-list a%F100:1.6.1 b%F10:auto;",
+        );
+
+        let result = parser.next().unwrap().unwrap();
+        assert_eq!(
+            CommandKind::OutputList(vec![
+                OutputListEntry::new("a".to_string(), NumberFormat::Fixed(100), 1, 6, 1),
+                OutputListEntry::auto("b".to_string(), NumberFormat::Fixed(10), 1, 1),
+            ]),
+            result.content.kind
+        );
+
+        let mut parser = ScriptParser::<MockSimulatorParser, MockSimulatorCommand>::new(
+            Path::new("Test.tst"),
+            "output-list a%F0:1.6.1;",
+        );
+        assert!(parser.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_clear_screen_and_clear_output() {
+        let parser = ScriptParser::<MockSimulatorParser, MockSimulatorCommand>::new(
+            Path::new("Test.tst"),
+            "clear-screen;\nclear-output,",
+        );
+
+        assert_eq!(
+            vec![
+                Ok(Spanned::new(
+                    0,
+                    13,
+                    1,
+                    Command::terminated(CommandKind::ClearScreen, Terminator::SingleStep)
+                )),
+                Ok(Spanned::new(
+                    14,
+                    27,
+                    2,
+                    Command::terminated(CommandKind::ClearOutput, Terminator::MiniStep)
+                )),
+            ],
+            parser.collect::<Vec<_>>()
+        );
+    }
 }
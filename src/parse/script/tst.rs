@@ -12,6 +12,9 @@ pub enum NumberFormat {
     Decimal,
     Hex,
     String,
+    /// fixed-point: the value divided by the divisor, e.g. 123 with divisor 100 is "1.23"
+    /// the number of decimals is the number of digits needed for divisor - 1
+    Fixed(u32),
 }
 
 impl convert::TryFrom<&str> for NumberFormat {
@@ -34,17 +37,48 @@ impl NumberFormat {
         }
 
         let i = s.parse::<i64>()?;
+        // like the official emulator, binary and hex show the 16 bit two's complement
+        // with leading zeros
+        let word = i as Word as u16;
         Ok(match self {
-            Self::Binary => format!("{i:b}"),
+            Self::Binary => format!("{word:016b}"),
             Self::Decimal => format!("{i:}"),
-            Self::Hex => format!("{i:x}"),
+            Self::Hex => format!("{word:04X}"),
+            Self::Fixed(divisor) => format_fixed(i, *divisor),
             Self::String => unreachable!(),
         })
     }
+
+    pub fn format_word(&self, word: Word) -> String {
+        self.format_string(&word.to_string())
+            .expect("a word is always a valid number")
+    }
+}
+
+fn format_fixed(value: i64, divisor: u32) -> String {
+    let divisor = divisor.max(1) as u64;
+    let sign = if value < 0 { "-" } else { "" };
+    let value = value.unsigned_abs();
+
+    let decimals = (divisor - 1).to_string().len() as u32;
+    if divisor == 1 {
+        return format!("{sign}{value}");
+    }
+
+    let fraction = (value % divisor) * 10u64.pow(decimals) / divisor;
+    format!(
+        "{sign}{}.{fraction:0width$}",
+        value / divisor,
+        width = decimals as usize
+    )
 }
 
 /// the output list entries are formatted as <name>%<format><left-padding>.<length>.<right-padding>
-/// where format is on of ['B', 'D', 'X', 'S']
+/// where format is on of ['B', 'D', 'X', 'S'] or F<divisor>: for fixed-point values
+/// (e.g. %F100:1.6.1)
+///
+/// The length can also be 'auto' (e.g. RAM[256]%D1.auto.1 or just RAM[256]%Dauto), in which case
+/// the length is the width of the widest value that is output in this column
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct OutputListEntry {
     pub name: VarName,
@@ -52,6 +86,7 @@ pub struct OutputListEntry {
     pub left_padding: usize,
     pub length: usize,
     pub right_padding: usize,
+    pub auto_width: bool,
 }
 
 impl OutputListEntry {
@@ -68,6 +103,20 @@ impl OutputListEntry {
             left_padding,
             length,
             right_padding,
+            auto_width: false,
+        }
+    }
+
+    /// An entry whose length is computed from the values that are output
+    pub fn auto(
+        name: VarName,
+        format: NumberFormat,
+        left_padding: usize,
+        right_padding: usize,
+    ) -> Self {
+        Self {
+            auto_width: true,
+            ..Self::new(name, format, left_padding, 0, right_padding)
         }
     }
 }
@@ -124,6 +173,9 @@ pub enum CommandKind<SimCmd: SimulatorCommand> {
         block: Vec<Command<SimCmd>>,
     },
     Echo(String),
+    // reset the peripherals of the simulator
+    ClearScreen,
+    ClearOutput,
     // Breakpoint,
     // ClearBreakpoints,
     // EndScript,
@@ -162,3 +214,65 @@ impl<SimCmd: SimulatorCommand> Command<SimCmd> {
         Self { kind, terminator }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_negative_one() {
+        assert_eq!(
+            Ok("1111111111111111".to_owned()),
+            NumberFormat::Binary.format_string("-1")
+        );
+        assert_eq!(Ok("FFFF".to_owned()), NumberFormat::Hex.format_string("-1"));
+        assert_eq!(
+            Ok("-1".to_owned()),
+            NumberFormat::Decimal.format_string("-1")
+        );
+    }
+
+    #[test]
+    fn test_format_pads_binary_and_hex_to_16_bit() {
+        assert_eq!(
+            Ok("0000000000000101".to_owned()),
+            NumberFormat::Binary.format_string("5")
+        );
+        assert_eq!(
+            Ok("00AB".to_owned()),
+            NumberFormat::Hex.format_string("171")
+        );
+        assert_eq!(
+            Ok("8000".to_owned()),
+            NumberFormat::Hex.format_string("-32768")
+        );
+        assert_eq!(
+            Ok("171".to_owned()),
+            NumberFormat::Decimal.format_string("171")
+        );
+    }
+
+    #[test]
+    fn test_format_fixed_point() {
+        let hundredths = NumberFormat::Fixed(100);
+        assert_eq!(Ok("1.23".to_owned()), hundredths.format_string("123"));
+        assert_eq!(Ok("-1.23".to_owned()), hundredths.format_string("-123"));
+        assert_eq!(Ok("-0.05".to_owned()), hundredths.format_string("-5"));
+        assert_eq!(Ok("0.00".to_owned()), hundredths.format_string("0"));
+        assert_eq!(Ok("327.67".to_owned()), hundredths.format_string("32767"));
+
+        assert_eq!(
+            Ok("1.2".to_owned()),
+            NumberFormat::Fixed(10).format_string("12")
+        );
+        assert_eq!(
+            Ok("12".to_owned()),
+            NumberFormat::Fixed(1).format_string("12")
+        );
+        // other divisors are rounded towards zero
+        assert_eq!(
+            Ok("0.50".to_owned()),
+            NumberFormat::Fixed(16).format_string("8")
+        );
+    }
+}
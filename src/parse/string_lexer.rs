@@ -36,6 +36,7 @@ where
 }
 
 /// A generic base Lexer similar to something like javas StreamTokenizer
+#[derive(Clone)]
 pub struct StringLexer<'src> {
     source: &'src str,
     line_nr: u32,
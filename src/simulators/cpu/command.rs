@@ -1,3 +1,5 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Register {
     A,
@@ -82,6 +84,22 @@ impl Default for Destination {
     }
 }
 
+impl Destination {
+    /// The destination as written in assembly (e.g. "AM"), None has no mnemonic
+    pub fn mnemonic(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::A => Some("A"),
+            Self::D => Some("D"),
+            Self::M => Some("M"),
+            Self::AD => Some("AD"),
+            Self::AM => Some("AM"),
+            Self::DM => Some("DM"),
+            Self::ADM => Some("ADM"),
+        }
+    }
+}
+
 impl TryFrom<&str> for Destination {
     type Error = ();
     fn try_from(s: &str) -> Result<Self, Self::Error> {
@@ -121,9 +139,226 @@ pub enum Jump {
     Unconditional,
 }
 
+impl Register {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::A => "A",
+            Self::D => "D",
+            Self::M => "M",
+        }
+    }
+}
+
+impl Computation {
+    /// The computation as written in assembly (e.g. "D+M")
+    pub fn mnemonic(&self) -> String {
+        match self {
+            Self::ConstZero => "0".to_owned(),
+            Self::ConstOne => "1".to_owned(),
+            Self::ConstNegOne => "-1".to_owned(),
+            Self::UnaryNone(r) => r.mnemonic().to_owned(),
+            Self::UnaryBoolNeg(r) => format!("!{}", r.mnemonic()),
+            Self::UnaryIntNeg(r) => format!("-{}", r.mnemonic()),
+            Self::BinaryInc(r) => format!("{}+1", r.mnemonic()),
+            Self::BinaryDec(r) => format!("{}-1", r.mnemonic()),
+            Self::BinaryAdd(l, r) => format!("{}+{}", l.mnemonic(), r.mnemonic()),
+            Self::BinarySub(l, r) => format!("{}-{}", l.mnemonic(), r.mnemonic()),
+            Self::BinaryAnd(l, r) => format!("{}&{}", l.mnemonic(), r.mnemonic()),
+            Self::BinaryOr(l, r) => format!("{}|{}", l.mnemonic(), r.mnemonic()),
+        }
+    }
+}
+
+impl Computation {
+    /// The a-bit and the six c-bits of the hack machine code
+    /// Computations that the hack ALU cannot perform (e.g. A+M) have no encoding
+    pub fn encode(&self) -> Option<u16> {
+        use Register::D;
+
+        // the a-bit selects M instead of A as the second ALU input
+        let a = |r: Register| (r == Register::M) as u16;
+
+        let (a_bit, c_bits) = match *self {
+            Self::ConstZero => (0, 0b101010),
+            Self::ConstOne => (0, 0b111111),
+            Self::ConstNegOne => (0, 0b111010),
+            Self::UnaryNone(D) => (0, 0b001100),
+            Self::UnaryNone(r) => (a(r), 0b110000),
+            Self::UnaryBoolNeg(D) => (0, 0b001101),
+            Self::UnaryBoolNeg(r) => (a(r), 0b110001),
+            Self::UnaryIntNeg(D) => (0, 0b001111),
+            Self::UnaryIntNeg(r) => (a(r), 0b110011),
+            Self::BinaryInc(D) => (0, 0b011111),
+            Self::BinaryInc(r) => (a(r), 0b110111),
+            Self::BinaryDec(D) => (0, 0b001110),
+            Self::BinaryDec(r) => (a(r), 0b110010),
+            Self::BinaryAdd(D, r) | Self::BinaryAdd(r, D) if r != D => (a(r), 0b000010),
+            Self::BinarySub(D, r) if r != D => (a(r), 0b010011),
+            Self::BinarySub(r, D) if r != D => (a(r), 0b000111),
+            Self::BinaryAnd(D, r) | Self::BinaryAnd(r, D) if r != D => (a(r), 0b000000),
+            Self::BinaryOr(D, r) | Self::BinaryOr(r, D) if r != D => (a(r), 0b010101),
+            _ => return None,
+        };
+        Some(a_bit << 6 | c_bits)
+    }
+}
+
+impl Destination {
+    fn encode(&self) -> u16 {
+        let (a, d, m) = self.as_bools();
+        (a as u16) << 2 | (d as u16) << 1 | m as u16
+    }
+}
+
+impl Jump {
+    fn encode(&self) -> u16 {
+        match self {
+            Self::Next => 0b000,
+            Self::Gt => 0b001,
+            Self::Eq => 0b010,
+            Self::Ge => 0b011,
+            Self::Lt => 0b100,
+            Self::Ne => 0b101,
+            Self::Le => 0b110,
+            Self::Unconditional => 0b111,
+        }
+    }
+}
+
+impl Jump {
+    /// The jump as written in assembly (e.g. "JGT"), Next has no mnemonic
+    pub fn mnemonic(&self) -> Option<&'static str> {
+        match self {
+            Self::Next => None,
+            Self::Gt => Some("JGT"),
+            Self::Eq => Some("JEQ"),
+            Self::Ge => Some("JGE"),
+            Self::Lt => Some("JLT"),
+            Self::Ne => Some("JNE"),
+            Self::Le => Some("JLE"),
+            Self::Unconditional => Some("JMP"),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Instruction {
     /// The A-instruction is used to set the A register to a 15-bit value:
     A(u16),
     C(Destination, Computation, Jump),
 }
+
+impl Instruction {
+    /// The 16 bit hack machine code of this instruction
+    /// Only C-instructions with a computation that the ALU doesn't support have no encoding
+    pub fn encode(&self) -> Option<u16> {
+        match self {
+            Self::A(value) => Some(value & 0x7FFF),
+            Self::C(dest, comp, jump) => {
+                Some(0b111 << 13 | comp.encode()? << 6 | dest.encode() << 3 | jump.encode())
+            }
+        }
+    }
+}
+
+/// Serialized as { "op": "a", "value": 5 } or { "op": "c", "dest": "M", "comp": "D+1", "jump": null }
+impl Serialize for Instruction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::A(value) => {
+                let mut state = serializer.serialize_struct("Instruction", 2)?;
+                state.serialize_field("op", "a")?;
+                state.serialize_field("value", value)?;
+                state.end()
+            }
+            Self::C(dest, comp, jump) => {
+                let mut state = serializer.serialize_struct("Instruction", 4)?;
+                state.serialize_field("op", "c")?;
+                state.serialize_field("dest", &dest.mnemonic())?;
+                state.serialize_field("comp", &comp.mnemonic())?;
+                state.serialize_field("jump", &jump.mnemonic())?;
+                state.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_instructions() {
+        assert_eq!(Some(0b0000000000010001), Instruction::A(17).encode());
+        // AM=D+M
+        assert_eq!(
+            Some(0b1111000010101000),
+            Instruction::C(
+                Destination::AM,
+                Computation::BinaryAdd(Register::D, Register::M),
+                Jump::Next
+            )
+            .encode()
+        );
+        // 0;JMP
+        assert_eq!(
+            Some(0b1110101010000111),
+            Instruction::C(
+                Destination::None,
+                Computation::ConstZero,
+                Jump::Unconditional
+            )
+            .encode()
+        );
+        // A-D
+        assert_eq!(
+            Some(0b1110000111010000),
+            Instruction::C(
+                Destination::D,
+                Computation::BinarySub(Register::A, Register::D),
+                Jump::Next
+            )
+            .encode()
+        );
+        // the ALU cannot add A and M
+        assert_eq!(
+            None,
+            Instruction::C(
+                Destination::D,
+                Computation::BinaryAdd(Register::A, Register::M),
+                Jump::Next
+            )
+            .encode()
+        );
+    }
+
+    #[test]
+    fn test_serialize_instructions() {
+        assert_eq!(
+            json!({ "op": "a", "value": 17 }),
+            serde_json::to_value(Instruction::A(17)).unwrap()
+        );
+        assert_eq!(
+            json!({ "op": "c", "dest": "AM", "comp": "D+M", "jump": null }),
+            serde_json::to_value(Instruction::C(
+                Destination::AM,
+                Computation::BinaryAdd(Register::D, Register::M),
+                Jump::Next
+            ))
+            .unwrap()
+        );
+        assert_eq!(
+            json!({ "op": "c", "dest": null, "comp": "0", "jump": "JMP" }),
+            serde_json::to_value(Instruction::C(
+                Destination::None,
+                Computation::ConstZero,
+                Jump::Unconditional
+            ))
+            .unwrap()
+        );
+    }
+}
@@ -6,6 +6,8 @@ use std::fmt;
 pub enum CpuError {
     IllegalProgramCounter(usize),
     IllegalMemoryAddress(Address),
+    IllegalRomAddress(Address),
+    UnencodableInstruction(Address),
 }
 
 impl fmt::Display for CpuError {
@@ -13,6 +15,10 @@ impl fmt::Display for CpuError {
         match self {
             Self::IllegalProgramCounter(pc) => write!(f, "Program counter out of bounds: {}", pc),
             Self::IllegalMemoryAddress(a) => write!(f, "Illegal memory address: {}", a),
+            Self::IllegalRomAddress(a) => write!(f, "Illegal ROM address: {}", a),
+            Self::UnencodableInstruction(a) => {
+                write!(f, "The instruction at ROM[{}] has no binary encoding", a)
+            }
         }
     }
 }
@@ -1,6 +1,7 @@
-use crate::definitions::{Address, Word, KBD, MEM_SIZE, SCREEN_END, SCREEN_START};
+use crate::definitions::{Address, Word, KBD, MEM_SIZE, ROM_SIZE, SCREEN_END, SCREEN_START};
 use command::{Computation, Instruction, Jump, Register};
 pub use error::CpuError;
+use std::collections::VecDeque;
 
 pub mod command;
 pub mod error;
@@ -8,13 +9,41 @@ pub mod script;
 
 pub type CpuResult<T = ()> = Result<T, CpuError>;
 
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum RunResult {
+    /// the program is stuck in a loop that doesn't change any state, e.g. (END) @END 0;JMP
+    TerminalLoop,
+    /// max_steps instructions were executed without reaching a terminal loop
+    StepLimitReached,
+}
+
+/// Everything a single step overwrote, so that it can be undone
+/// A C-instruction writes at most one memory cell, so the old value of that cell is enough
+#[derive(Debug, Clone, Copy)]
+struct StepDelta {
+    pc: usize,
+    a: Word,
+    d: Word,
+    // (address, old value)
+    memory_write: Option<(Address, Word)>,
+}
+
 pub struct Cpu {
     pc: usize,
     program: Vec<Instruction>,
+    // the source comments of the instructions, empty if the program was loaded without them
+    comments: Vec<Option<String>>,
 
     a: Word,
     d: Word,
     memory: Box<[Word; MEM_SIZE]>,
+
+    // the deltas of the most recent steps, only recorded if this is Some
+    history: Option<VecDeque<StepDelta>>,
+    history_capacity: usize,
+
+    // copy the encoded program into the memory, so that it can be read like data
+    rom_readable: bool,
 }
 
 impl Default for Cpu {
@@ -22,9 +51,13 @@ impl Default for Cpu {
         Self {
             pc: 0,
             program: Vec::new(),
+            comments: Vec::new(),
             a: 0,
             d: 0,
             memory: Box::new([0; MEM_SIZE]),
+            history: None,
+            history_capacity: 0,
+            rom_readable: false,
         }
     }
 }
@@ -56,16 +89,113 @@ impl Cpu {
     }
 
     pub fn load(&mut self, program: Vec<Instruction>) {
-        self.pc = 0;
         self.program = program;
+        self.comments.clear();
+        self.reset();
+    }
+
+    /// Run the loaded program again from the start, with cleared registers and memory
+    pub fn reset(&mut self) {
+        self.pc = 0;
         self.a = 0;
         self.d = 0;
         for i in 0..self.memory.len() {
             self.memory[i] = 0;
         }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        if self.rom_readable {
+            self.mirror_rom();
+        }
+    }
+
+    /// Mirror the machine code of the program into the memory starting at address 0, so that
+    /// e.g. @3 D=M reads the encoded fourth instruction
+    /// This is not part of the hack platform, but some course variants store data in the ROM
+    /// The mirror is written when loading (or immediately if a program is already loaded) and
+    /// is just normal memory afterwards
+    pub fn set_rom_readable(&mut self, readable: bool) {
+        self.rom_readable = readable;
+        if readable {
+            self.mirror_rom();
+        }
+    }
+
+    fn mirror_rom(&mut self) {
+        for (cell, instr) in self.memory.iter_mut().zip(&self.program) {
+            // instructions which the hack ALU can't execute have no encoding
+            *cell = instr.encode().unwrap_or(0) as Word;
+        }
+    }
+
+    /// Load a program together with the comments returned by AssemblyParser::parse_with_comments
+    pub fn load_with_comments(&mut self, program: Vec<Instruction>, comments: Vec<Option<String>>) {
+        self.load(program);
+        self.comments = comments;
+    }
+
+    /// Record the last capacity steps so that they can be undone with step_back
+    pub fn enable_history(&mut self, capacity: usize) {
+        // the capacity comes from the frontend, so it is only enforced when pushing instead of
+        // allocating it all up front
+        self.history = Some(VecDeque::new());
+        self.history_capacity = capacity;
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+        self.history_capacity = 0;
+    }
+
+    /// Undo the last recorded step
+    /// Returns false if there is nothing to undo
+    pub fn step_back(&mut self) -> bool {
+        let delta = match self.history.as_mut().and_then(|h| h.pop_back()) {
+            Some(delta) => delta,
+            None => return false,
+        };
+
+        self.pc = delta.pc;
+        self.a = delta.a;
+        self.d = delta.d;
+        if let Some((address, value)) = delta.memory_write {
+            self.memory[address] = value;
+        }
+        true
     }
 
     pub fn step(&mut self) -> CpuResult {
+        if self.history.is_none() || self.history_capacity == 0 {
+            return self.execute_instruction();
+        }
+
+        let memory_write = if self.current_instruction_writes_memory() {
+            let address = self.a as Address;
+            Some((address, self.mem(address)?))
+        } else {
+            None
+        };
+
+        let delta = StepDelta {
+            pc: self.pc,
+            a: self.a,
+            d: self.d,
+            memory_write,
+        };
+
+        self.execute_instruction()?;
+
+        if let Some(history) = &mut self.history {
+            if history.len() == self.history_capacity {
+                history.pop_front();
+            }
+            history.push_back(delta);
+        }
+        Ok(())
+    }
+
+    fn execute_instruction(&mut self) -> CpuResult {
         macro_rules! binary {
             ( $r1:expr, $op:tt, $r2:expr) => {{
                 // cast up to i32 so that no overflow checks get triggered in debug mode
@@ -137,6 +267,41 @@ impl Cpu {
 
         Ok(())
     }
+
+    fn current_instruction_writes_memory(&self) -> bool {
+        match self.program.get(self.pc) {
+            Some(Instruction::C(dest, _, _)) => dest.as_bools().2,
+            _ => false,
+        }
+    }
+
+    /// Execute up to max_steps instructions, returning early if the program reaches a terminal loop
+    ///
+    /// A terminal loop is detected by the pc revisiting an address within the last two steps
+    /// with the same A and D registers and without any memory writes in between.
+    /// Since the cpu is deterministic, it will never leave such a loop on its own
+    pub fn run(&mut self, max_steps: usize) -> CpuResult<RunResult> {
+        // the (pc, a, d) states of the last two steps, cleared on every memory write
+        let mut history: [Option<(usize, Word, Word)>; 2] = [None, None];
+
+        for _ in 0..max_steps {
+            let state = (self.pc, self.a, self.d);
+            if history.contains(&Some(state)) {
+                return Ok(RunResult::TerminalLoop);
+            }
+
+            let writes_memory = self.current_instruction_writes_memory();
+            self.step()?;
+
+            history = if writes_memory {
+                [None, None]
+            } else {
+                [history[1], Some(state)]
+            };
+        }
+
+        Ok(RunResult::StepLimitReached)
+    }
 }
 
 // UI interaction
@@ -146,17 +311,66 @@ impl Cpu {
         self.set_mem(KBD, key)
     }
 
+    /// The value of the keyboard register, 0 if no key is pressed
+    pub fn current_key(&self) -> Word {
+        self.memory[KBD]
+    }
+
     pub fn display(&self) -> &[Word] {
         &self.memory[SCREEN_START..=SCREEN_END]
     }
 
+    pub fn clear_screen(&mut self) {
+        self.memory[SCREEN_START..=SCREEN_END].fill(0);
+    }
+
     pub fn memory_at(&self, address: Address) -> Option<Word> {
         self.mem(address).ok()
     }
 
+    pub fn memory(&self) -> &[Word] {
+        &self.memory[..]
+    }
+
     pub fn current_file_offset(&self) -> usize {
         self.pc
     }
+
+    /// The A and D registers and M (the memory at A)
+    /// M is 0 if A isn't a valid memory address
+    pub fn registers(&self) -> (Word, Word, Word) {
+        let m = self.memory_at(self.a as Address).unwrap_or(0);
+        (self.a, self.d, m)
+    }
+
+    /// The encoded instruction at the given ROM address
+    /// The ROM behind the end of the program is filled with zeroes
+    pub fn rom_at(&self, address: Address) -> CpuResult<Word> {
+        if address >= ROM_SIZE {
+            return Err(CpuError::IllegalRomAddress(address));
+        }
+
+        match self.program.get(address) {
+            Some(instr) => instr
+                .encode()
+                .map(|word| word as Word)
+                .ok_or(CpuError::UnencodableInstruction(address)),
+            None => Ok(0),
+        }
+    }
+
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        self.program.get(self.pc).copied()
+    }
+
+    /// The source comment of the instruction at the given address
+    pub fn comment_at(&self, address: usize) -> Option<&str> {
+        self.comments.get(address)?.as_deref()
+    }
+
+    pub fn current_comment(&self) -> Option<&str> {
+        self.comment_at(self.pc)
+    }
 }
 
 #[cfg(test)]
@@ -165,9 +379,7 @@ mod tests {
 
     use crate::parse::assembly::{AssemblyParser, SourceFile};
 
-    #[test]
-    fn test_sum_1_to_100() {
-        let src = r#"
+    const SUM_1_TO_100: &str = r#"
             // Adds 1+...+100.
             @i // i refers to some mem. location.
             M=1 // i=1
@@ -192,7 +404,9 @@ mod tests {
             @END
             0;JMP // Infinite loop"#;
 
-        let mut parser = AssemblyParser::new(SourceFile::new(src));
+    #[test]
+    fn test_sum_1_to_100() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
         let program = parser.parse().unwrap();
 
         let mut cpu = Cpu::default();
@@ -204,4 +418,154 @@ mod tests {
 
         assert_eq!(Ok(5050), cpu.mem(17));
     }
+
+    #[test]
+    fn test_reset() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let program = parser.parse().unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load(program);
+        let (pc, registers, memory) = (cpu.pc, cpu.registers(), cpu.memory.clone());
+
+        for _ in 0..100 {
+            cpu.step().unwrap();
+        }
+        cpu.set_input_key(65).unwrap();
+
+        cpu.reset();
+        assert_eq!(pc, cpu.pc);
+        assert_eq!(registers, cpu.registers());
+        assert_eq!(memory, cpu.memory);
+        assert!(!cpu.program.is_empty());
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let program = parser.parse().unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load(program);
+        assert_eq!((0, 0, 0), cpu.registers());
+
+        // @i, M=1, @sum, M=0, @i, D=M
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((16, 1, 1), cpu.registers());
+
+        // @100
+        cpu.step().unwrap();
+        assert_eq!((100, 1, 0), cpu.registers());
+    }
+
+    #[test]
+    fn test_rom_readable() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let program = parser.parse().unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load(program.clone());
+        assert_eq!(Ok(0), cpu.mem(1));
+
+        cpu.set_rom_readable(true);
+        cpu.load(program);
+        // @i
+        assert_eq!(Ok(16), cpu.mem(0));
+        // M=1
+        assert_eq!(Ok(0b1110111111001000u16 as Word), cpu.mem(1));
+    }
+
+    #[test]
+    fn test_run_detects_terminal_loop() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let program = parser.parse().unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load(program);
+
+        assert_eq!(Ok(RunResult::TerminalLoop), cpu.run(10000));
+        assert_eq!(Ok(5050), cpu.mem(17));
+        // the loop was detected long before the step limit
+        assert_eq!(18, cpu.pc);
+    }
+
+    #[test]
+    fn test_run_stops_at_step_limit() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let program = parser.parse().unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load(program);
+
+        assert_eq!(Ok(RunResult::StepLimitReached), cpu.run(100));
+    }
+
+    #[test]
+    fn test_comments_are_associated_with_their_instruction() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let (program, comments) = parser.parse_with_comments().unwrap();
+        assert_eq!(program.len(), comments.len());
+
+        let mut cpu = Cpu::default();
+        cpu.load_with_comments(program, comments);
+
+        assert_eq!(
+            cpu.current_comment(),
+            Some("// i refers to some mem. location.")
+        );
+        assert_eq!(cpu.comment_at(1), Some("// i=1"));
+        // the label in between doesn't shift the comments
+        assert_eq!(cpu.comment_at(4), None);
+        assert_eq!(cpu.comment_at(5), Some("// D=i"));
+        assert_eq!(cpu.comment_at(17), Some("// Goto LOOP"));
+        assert_eq!(cpu.comment_at(18), None);
+        assert_eq!(cpu.comment_at(19), Some("// Infinite loop"));
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.current_comment(), Some("// i=1"));
+    }
+
+    #[test]
+    fn test_step_back_restores_the_previous_state() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let program = parser.parse().unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load(program);
+        cpu.enable_history(100);
+
+        let mut states = Vec::new();
+        for _ in 0..50 {
+            states.push((cpu.pc, cpu.a, cpu.d, cpu.memory.clone()));
+            cpu.step().unwrap();
+        }
+
+        while let Some((pc, a, d, memory)) = states.pop() {
+            assert!(cpu.step_back());
+            assert_eq!((pc, a, d), (cpu.pc, cpu.a, cpu.d));
+            assert_eq!(memory, cpu.memory);
+        }
+        assert!(!cpu.step_back());
+    }
+
+    #[test]
+    fn test_history_is_limited_to_its_capacity() {
+        let mut parser = AssemblyParser::new(SourceFile::new(SUM_1_TO_100));
+        let program = parser.parse().unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load(program);
+        cpu.enable_history(2);
+
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+
+        assert!(cpu.step_back());
+        assert!(cpu.step_back());
+        assert!(!cpu.step_back());
+        assert_eq!(8, cpu.pc);
+    }
 }
@@ -1,8 +1,9 @@
-use crate::definitions::Word;
 use crate::parse::script::lexer::{ident_kind, int_kind, Token};
 use crate::parse::script::parser::ScriptParser;
 use crate::parse::script::tst::{Command, CommandKind, CpuEmulatorCommand, CpuSetTarget};
-use crate::parse::script::{CmdResult, ParseError, ParseResult, SimulatorCommandParser};
+use crate::parse::script::{
+    parse_index, set_value, CmdResult, ParseError, ParseResult, SimulatorCommandParser,
+};
 use crate::parse::Spanned;
 
 mod run;
@@ -59,7 +60,10 @@ impl<'tst> SimulatorCommandParser<CpuEmulatorCommand>
 
                 let value = self.consume_token_kind(int_kind())?;
                 let value = if let Token::IntLiteral(value) = value.content {
-                    value as Word
+                    set_value(value).ok_or_else(|| ParseError::ValueOutOfRange {
+                        command: ident.content.to_string(),
+                        value,
+                    })?
                 } else {
                     unreachable!()
                 };
@@ -89,9 +93,8 @@ pub fn parse_set_target(ident: &str) -> ParseResult<CpuSetTarget> {
                 return Err(ParseError::InvalidSetTarget(ident.to_string()));
             }
 
-            (&ident[(opening_index + 1)..closing_index])
-                .parse::<usize>()
-                .map_err(|_| ParseError::InvalidSetTarget(ident.to_string()))?
+            parse_index(&ident[(opening_index + 1)..closing_index])
+                .ok_or_else(|| ParseError::InvalidSetTarget(ident.to_string()))?
         }};
     }
 
@@ -121,6 +124,33 @@ pub fn parse_set_target(ident: &str) -> ParseResult<CpuSetTarget> {
         "pc" => no_index!(CpuSetTarget::PC),
         "ram" => req_index!(CpuSetTarget::Ram),
         "rom" => req_index!(CpuSetTarget::Rom),
-        _ => todo!(),
+        _ => Err(ParseError::InvalidSetTarget(ident.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_set_target_with_index_expression() {
+        assert_eq!(Ok(CpuSetTarget::Ram(260)), parse_set_target("RAM[256+4]"));
+        assert_eq!(Ok(CpuSetTarget::Rom(6)), parse_set_target("ROM[10-4]"));
+        assert_eq!(
+            Err(ParseError::InvalidSetTarget("RAM[+4]".to_string())),
+            parse_set_target("RAM[+4]")
+        );
+    }
+
+    #[test]
+    fn test_parser_reports_unknown_set_target() {
+        let mut parser = ScriptParser::<CpuEmulatorCommandParser, CpuEmulatorCommand>::new(
+            std::path::Path::new("Test.tst"),
+            "set bogus 5;",
+        );
+        assert_eq!(
+            Some(Err(ParseError::InvalidSetTarget("bogus".to_string()))),
+            parser.next()
+        );
     }
 }
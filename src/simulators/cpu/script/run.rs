@@ -16,7 +16,7 @@ impl SimulatorExecutor<CpuEmulatorCommand> for Cpu {
             CpuSetTarget::D => self.d as i64,
             CpuSetTarget::PC => self.pc as i64,
             CpuSetTarget::Ram(address) => self.mem(address)? as i64,
-            CpuSetTarget::Rom(_address) => unimplemented!("no rom access yet"),
+            CpuSetTarget::Rom(address) => self.rom_at(address)? as i64,
         })
     }
 
@@ -40,4 +40,13 @@ impl SimulatorExecutor<CpuEmulatorCommand> for Cpu {
         };
         Ok(())
     }
+
+    fn clear_screen(&mut self) -> ExecResult {
+        Cpu::clear_screen(self);
+        Ok(())
+    }
+
+    fn clear_output(&mut self) -> ExecResult {
+        Err("clear-output is only supported by the VM emulator".into())
+    }
 }
@@ -1,15 +1,18 @@
+use crate::definitions::{Address, Word, BITS_PER_WORD, SCREEN_HEIGHT, SCREEN_WIDTH};
 use crate::parse::script::parser::ScriptParser;
 use crate::parse::script::tst::{
     Command, CommandKind, NumberFormat, OutputListEntry, SimulatorCommand,
 };
-use crate::parse::script::SimulatorCommandParser;
+use crate::parse::script::{CmdResult, ParseError, SimulatorCommandParser};
+use crate::parse::Spanned;
 
 use std::error::Error;
 use std::fmt;
 use std::fs::{read_to_string, File, OpenOptions};
-use std::io::Write;
+use std::io::{BufRead, BufReader, Lines, Write};
 use std::marker::PhantomData;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 pub mod cpu;
 pub mod vm;
@@ -27,9 +30,67 @@ impl Error for Halt {}
 
 pub type ExecResult<T = ()> = Result<T, Box<dyn Error>>;
 
+/// Compare two memory images and return every address where they differ as
+/// (address, value in a, value in b)
+///
+/// If one image is longer than the other, the additional addresses are ignored
+pub fn diff_memory(a: &[Word], b: &[Word]) -> Vec<(Address, Word, Word)> {
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(address, (&a, &b))| (address, a, b))
+        .collect()
+}
+
+/// One "address: value" line per memory cell with the value in the given format
+///
+/// Without a range only the non-zero cells are included, otherwise every cell in the range
+pub fn dump_memory(memory: &[Word], range: Option<Range<Address>>, format: NumberFormat) -> String {
+    let only_non_zero = range.is_none();
+    let range = range.unwrap_or(0..memory.len());
+    let end = range.end.min(memory.len());
+    let start = range.start.min(end);
+
+    memory[start..end]
+        .iter()
+        .enumerate()
+        .filter(|(_, &value)| !only_non_zero || value != 0)
+        .map(|(offset, &value)| format!("{:5}: {}\n", start + offset, format.format_word(value)))
+        .collect()
+}
+
+/// The pixels of the screen memory row by row, true for black pixels
+pub fn display_pixels(display: &[Word]) -> impl Iterator<Item = bool> + '_ {
+    display
+        .iter()
+        .flat_map(|&word| (0..BITS_PER_WORD).map(move |pixel_idx| word & (1 << pixel_idx) != 0))
+}
+
+// plain PBM files should not contain lines longer than 70 characters
+// 64 splits every pixel row into 8 lines of the same length
+const PBM_LINE_LENGTH: usize = 64;
+
+/// The screen as a plain (P1) PBM image with 1 for black pixels
+/// Every pixel row starts on a new line and is wrapped after PBM_LINE_LENGTH pixels
+pub fn display_pbm(display: &[Word]) -> String {
+    let mut pbm = format!("P1\n{} {}\n", SCREEN_WIDTH, SCREEN_HEIGHT);
+    let pixels = display_pixels(display).collect::<Vec<_>>();
+
+    for line in pixels.chunks(PBM_LINE_LENGTH) {
+        pbm.extend(line.iter().map(|&black| if black { '1' } else { '0' }));
+        pbm.push('\n');
+    }
+    pbm
+}
+
 pub trait SimulatorExecutor<CMD> {
     fn get_value(&self, name: &str) -> ExecResult<i64>;
     fn exec_sim(&mut self, cmd: CMD) -> ExecResult;
+    /// Set every pixel of the screen to white
+    fn clear_screen(&mut self) -> ExecResult;
+    /// Move the cursor of the Output class back to the top left corner
+    fn clear_output(&mut self) -> ExecResult;
 }
 
 pub trait ScriptExecutor<CMD>
@@ -39,6 +100,39 @@ where
     fn exec(&mut self, cmd: Command<CMD>) -> ExecResult;
 }
 
+#[derive(Debug, Clone)]
+pub struct ScriptOptions {
+    /// compare every output line against the compare file as soon as it is written
+    /// instead of comparing the whole files after the script finished
+    pub streaming_compare: bool,
+    /// stop the script with an OutputLimitError once it would write more than this many bytes
+    pub max_output_bytes: Option<usize>,
+    /// characters in the compare file that match any output character
+    pub wildcard: char,
+}
+
+impl Default for ScriptOptions {
+    fn default() -> Self {
+        Self {
+            streaming_compare: false,
+            max_output_bytes: None,
+            wildcard: '*',
+        }
+    }
+}
+
+/// A single column of the output table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSpec {
+    /// the name of the variable in the output-list
+    pub name: String,
+    /// the name as written in the header line, which is cut off if it is wider than the column
+    pub title: String,
+    pub format: NumberFormat,
+    /// the width of the column without the '|' separators (left padding + length + right padding)
+    pub width: usize,
+}
+
 pub struct BaseScriptExecutor<'w, CMD, SIM>
 where
     CMD: SimulatorCommand,
@@ -53,6 +147,16 @@ where
     output_file: Option<(PathBuf, File)>,
     compare_file: Option<PathBuf>,
     output_list: Vec<OutputListEntry>,
+    options: ScriptOptions,
+    // the line in the script of the command that is currently executed
+    current_line: u32,
+    // the compare file and the number of lines already compared (only used when streaming)
+    streamed_compare_file: Option<(Lines<BufReader<File>>, usize)>,
+    // the number of bytes written by all output commands so far
+    output_bytes: usize,
+    // the formatted values of every output command while the output-list has an auto width column
+    // these can only be written once all values are known
+    buffered_rows: Vec<Vec<String>>,
     phantom: PhantomData<CMD>,
 }
 
@@ -61,7 +165,7 @@ where
     CMD: SimulatorCommand,
     SIM: SimulatorExecutor<CMD>,
 {
-    fn new(sim: SIM, writer: impl Into<Option<&'w mut dyn Write>>) -> Self {
+    fn new(sim: SIM, writer: impl Into<Option<&'w mut dyn Write>>, options: ScriptOptions) -> Self {
         BaseScriptExecutor {
             sim,
             writer: writer.into(),
@@ -69,10 +173,66 @@ where
             output_file: None,
             compare_file: None,
             output_list: Vec::new(),
+            options,
+            current_line: 0,
+            streamed_compare_file: None,
+            output_bytes: 0,
+            buffered_rows: Vec::new(),
             phantom: PhantomData,
         }
     }
 
+    /// Write a single line of the output table (the content is surrounded by '|' and '\n')
+    fn write_output_line(&mut self, content: &str) -> ExecResult {
+        let line = format!("|{}", content);
+
+        // + 1 for the newline
+        self.output_bytes += line.len() + 1;
+        if let Some(limit) = self.options.max_output_bytes {
+            if self.output_bytes > limit {
+                return Err(Box::new(OutputLimitError { limit }));
+            }
+        }
+
+        writeln!(self.writer()?, "{}", line)?;
+
+        if self.options.streaming_compare {
+            self.compare_streamed_line(&line)?;
+        }
+        Ok(())
+    }
+
+    fn compare_streamed_line(&mut self, line: &str) -> ExecResult {
+        let cmp_path = match &self.compare_file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if self.streamed_compare_file.is_none() {
+            let file = File::open(cmp_path)
+                .map_err(|e| MissingFileError::new(cmp_path, "compare-to", e))?;
+            self.streamed_compare_file = Some((BufReader::new(file).lines(), 0));
+        }
+
+        let (lines, line_count) = self.streamed_compare_file.as_mut().unwrap();
+        let expected = lines.next().transpose()?.unwrap_or_default();
+        *line_count += 1;
+
+        let expected = expected.trim_end_matches('\r');
+        let mismatch = compare_line(expected, line, self.options.wildcard);
+
+        if let Some(col) = mismatch {
+            return Err(Box::new(ComparisonError {
+                cmp_file_name: cmp_path.to_string_lossy().into_owned(),
+                line: *line_count,
+                col,
+                script_line: Some(self.current_line),
+            }));
+        }
+
+        Ok(())
+    }
+
     fn writer(&mut self) -> ExecResult<&mut dyn Write> {
         if let Some(w) = &mut self.writer {
             return Ok(w);
@@ -81,12 +241,16 @@ where
         if let Some((_, output_file)) = &mut self.output_file {
             Ok(output_file)
         } else {
-            Err("Trying to output without an output file".into())
+            Err(Box::new(ScriptError::NoOutputFile))
         }
     }
 
     fn set_output_file(&mut self, path: PathBuf) -> ExecResult {
-        let file = OpenOptions::new().create(true).write(true).open(&path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| MissingFileError::new(&path, "output-file", e))?;
         self.output_file = Some((path, file));
         Ok(())
     }
@@ -97,11 +261,74 @@ where
     }
 
     fn set_output_list(&mut self, list: Vec<OutputListEntry>) -> ExecResult {
+        // the rows of the previous list still need to be written with its widths
+        self.flush_buffered_rows()?;
         self.output_list = list;
         self.print_output_header_line = true;
         Ok(())
     }
 
+    /// The columns of the current output-list, e.g. for rendering the output as a table
+    /// Auto width columns report the width of their widest value that is not written yet
+    pub fn output_columns(&self) -> Vec<ColumnSpec> {
+        self.output_list
+            .iter()
+            .enumerate()
+            .map(|(column, entry)| {
+                let width = entry.left_padding + self.column_length(column) + entry.right_padding;
+                let title = if entry.name.len() > width {
+                    entry.name[0..width].to_owned()
+                } else {
+                    entry.name.clone()
+                };
+
+                ColumnSpec {
+                    name: entry.name.clone(),
+                    title,
+                    format: entry.format,
+                    width,
+                }
+            })
+            .collect()
+    }
+
+    /// The length of the values in a column, which for auto width columns is the length of the
+    /// widest buffered value, but at least the length of the column name
+    fn column_length(&self, column: usize) -> usize {
+        let entry = &self.output_list[column];
+        if !entry.auto_width {
+            return entry.length;
+        }
+
+        self.buffered_rows
+            .iter()
+            .map(|row| row[column].len())
+            .max()
+            .unwrap_or(entry.length)
+            .max(entry.name.len())
+    }
+
+    /// Fail with an OutputLimitError if writing the buffered rows would exceed max_output_bytes
+    fn check_buffered_output_limit(&self) -> ExecResult {
+        let limit = match self.options.max_output_bytes {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        // the header and every row are equally wide: '|' + (width + '|') per column + '\n'
+        let line_len: usize = 2 + self
+            .output_columns()
+            .iter()
+            .map(|column| column.width + 1)
+            .sum::<usize>();
+        let lines = self.buffered_rows.len() + self.print_output_header_line as usize;
+
+        if self.output_bytes + line_len * lines > limit {
+            return Err(Box::new(OutputLimitError { limit }));
+        }
+        Ok(())
+    }
+
     fn print_output_header_if_needed(&mut self) -> ExecResult {
         if !self.print_output_header_line {
             return Ok(());
@@ -112,19 +339,9 @@ where
         // because the loop already borrows the outputlist immutably
         let mut temp_writer = Vec::new();
 
-        for entry in self.output_list.iter() {
-            let name = &entry.name;
-            let length = entry.length;
-            let left_padding = entry.left_padding;
-            let right_padding = entry.right_padding;
-
-            let space = left_padding + length + right_padding;
-            let name = if name.len() > space {
-                &name[0..space]
-            } else {
-                name
-            };
-
+        for column in self.output_columns() {
+            let name = &column.title;
+            let space = column.width;
             let left_space = (space - name.len()) / 2;
             let right_space = space - left_space - name.len();
 
@@ -140,14 +357,74 @@ where
             write!(temp_writer, "|")?;
         }
 
-        let real_writer = self.writer()?;
-        write!(real_writer, "|")?;
         // actually write the content here
-        write!(real_writer, "{}", String::from_utf8(temp_writer)?)?;
-        writeln!(real_writer)?;
+        self.write_output_line(&String::from_utf8(temp_writer)?)?;
         self.print_output_header_line = false;
         Ok(())
     }
+
+    /// The current values of all variables in the output-list in their format
+    fn output_values(&self) -> ExecResult<Vec<String>> {
+        self.output_list
+            .iter()
+            .map(|entry| {
+                // like in the official emulator, the string format shows the value as it is
+                let value = self.sim.get_value(&entry.name)?.to_string();
+                Ok(entry.format.format_string(&value)?)
+            })
+            .collect()
+    }
+
+    /// Write the formatted values padded to the widths of the output-list
+    fn write_output_row(&mut self, values: &[String]) -> ExecResult {
+        // see: print_output_header_if_needed
+        let mut temp_writer = Vec::new();
+
+        for (entry, value_string) in self.output_list.iter().zip(values) {
+            let length = entry.length;
+
+            // like the official emulators, values that are too wide only show their last characters
+            let value_string = if value_string.len() > length {
+                &value_string[(value_string.len() - length)..]
+            } else {
+                value_string
+            };
+
+            let left_space = entry.left_padding + (length - value_string.len());
+            let right_space = entry.right_padding;
+
+            for _ in 0..left_space {
+                write!(temp_writer, " ")?;
+            }
+
+            write!(temp_writer, "{}", value_string)?;
+
+            for _ in 0..right_space {
+                write!(temp_writer, " ")?;
+            }
+            write!(temp_writer, "|")?;
+        }
+
+        self.write_output_line(&String::from_utf8(temp_writer)?)
+    }
+
+    /// Size the auto width columns to their widest value and write the header and all buffered rows
+    fn flush_buffered_rows(&mut self) -> ExecResult {
+        if self.buffered_rows.is_empty() {
+            return Ok(());
+        }
+
+        for column in 0..self.output_list.len() {
+            self.output_list[column].length = self.column_length(column);
+        }
+        let rows = std::mem::take(&mut self.buffered_rows);
+
+        self.print_output_header_if_needed()?;
+        for row in rows.iter() {
+            self.write_output_row(row)?;
+        }
+        Ok(())
+    }
 }
 
 impl<'w, CMD, SIM> ScriptExecutor<CMD> for BaseScriptExecutor<'w, CMD, SIM>
@@ -165,52 +442,18 @@ where
                 println!("{}", message);
                 Ok(())
             }
+            CommandKind::ClearScreen => self.sim.clear_screen(),
+            CommandKind::ClearOutput => self.sim.clear_output(),
             CommandKind::Output => {
-                self.print_output_header_if_needed()?;
-
-                // see: print_output_header_if_needed
-                let mut temp_writer = Vec::new();
-
-                for entry in self.output_list.iter() {
-                    let name = &entry.name;
-                    let length = entry.length;
-                    let left_padding = entry.left_padding;
-                    let right_padding = entry.right_padding;
-                    let format = entry.format;
-
-                    if let NumberFormat::String = format {
-                        todo!("support string formats");
-                    }
+                let values = self.output_values()?;
 
-                    let value = self.sim.get_value(name)?.to_string();
-                    let value_string = format.format_string(&value)?;
-
-                    let value_string = if value_string.len() > length {
-                        &value_string[0..(value_string.len() - length)]
-                    } else {
-                        &value_string
-                    };
-
-                    let left_space = left_padding + (length - value_string.len());
-                    let right_space = right_padding;
-
-                    for _ in 0..left_space {
-                        write!(temp_writer, " ")?;
-                    }
-
-                    write!(temp_writer, "{}", value_string)?;
-
-                    for _ in 0..right_space {
-                        write!(temp_writer, " ")?;
-                    }
-                    write!(temp_writer, "|")?;
+                if self.output_list.iter().any(|entry| entry.auto_width) {
+                    self.buffered_rows.push(values);
+                    return self.check_buffered_output_limit();
                 }
 
-                let real_writer = self.writer()?;
-                write!(real_writer, "|")?;
-                write!(real_writer, "{}", String::from_utf8(temp_writer)?)?;
-                writeln!(real_writer)?;
-                Ok(())
+                self.print_output_header_if_needed()?;
+                self.write_output_row(&values)
             }
             CommandKind::Repeat { times, block } => {
                 for _ in 0..times {
@@ -229,6 +472,8 @@ pub struct ComparisonError {
     cmp_file_name: String,
     line: usize,
     col: usize,
+    // the line of the output command in the script (only known when streaming)
+    script_line: Option<u32>,
 }
 
 impl fmt::Display for ComparisonError {
@@ -237,96 +482,499 @@ impl fmt::Display for ComparisonError {
             f,
             "Error at {}:{}:{}",
             self.cmp_file_name, self.line, self.col
-        )
+        )?;
+
+        if let Some(script_line) = self.script_line {
+            write!(f, " (output command in line {} of the script)", script_line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare a single line of output against the expected line
+/// Returns the column of the first difference
+fn compare_line(expected: &str, actual: &str, wildcard: char) -> Option<usize> {
+    for (col, (cmp_c, out_c)) in expected.chars().zip(actual.chars()).enumerate() {
+        if cmp_c != out_c && cmp_c != wildcard {
+            return Some(col);
+        }
     }
+
+    let (expected_len, actual_len) = (expected.chars().count(), actual.chars().count());
+    if expected_len != actual_len {
+        return Some(expected_len.min(actual_len));
+    }
+
+    None
 }
 
 impl Error for ComparisonError {}
 
+/// A file referenced by a script command (e.g. compare-to) could not be opened
+#[derive(Debug)]
+pub struct MissingFileError {
+    path: PathBuf,
+    command: &'static str,
+    cause: std::io::Error,
+}
+
+impl MissingFileError {
+    fn new(path: &Path, command: &'static str, cause: std::io::Error) -> Self {
+        Self {
+            path: path.to_owned(),
+            command,
+            cause,
+        }
+    }
+}
+
+impl fmt::Display for MissingFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Could not open '{}' referenced by {}: {}",
+            self.path.display(),
+            self.command,
+            self.cause
+        )
+    }
+}
+
+impl Error for MissingFileError {}
+
+/// The script tried to write more than ScriptOptions::max_output_bytes
+#[derive(Debug)]
+pub struct OutputLimitError {
+    limit: usize,
+}
+
+impl fmt::Display for OutputLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The output exceeded the limit of {} bytes", self.limit)
+    }
+}
+
+impl Error for OutputLimitError {}
+
+/// Everything that can make execute_script fail
+#[derive(Debug)]
+pub enum ScriptError {
+    /// an output command was executed before an output-file command
+    NoOutputFile,
+    MissingFile(MissingFileError),
+    Comparison(ComparisonError),
+    OutputLimit(OutputLimitError),
+    /// the program halted, execute_script itself treats this as a success
+    Halt,
+    Parse(ParseError),
+    /// an error of the simulator (e.g. an illegal memory access) or of the file system
+    Simulator(Box<dyn Error>),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoOutputFile => write!(f, "Trying to output without an output file"),
+            Self::MissingFile(e) => e.fmt(f),
+            Self::Comparison(e) => e.fmt(f),
+            Self::OutputLimit(e) => e.fmt(f),
+            Self::Halt => Halt.fmt(f),
+            Self::Parse(e) => e.fmt(f),
+            Self::Simulator(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ScriptError {}
+
+impl From<MissingFileError> for ScriptError {
+    fn from(e: MissingFileError) -> Self {
+        Self::MissingFile(e)
+    }
+}
+
+impl From<ParseError> for ScriptError {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<Box<dyn Error>> for ScriptError {
+    fn from(e: Box<dyn Error>) -> Self {
+        // the executors use boxed errors internally, so the known ones have to be recovered
+        let e = match e.downcast::<ScriptError>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<MissingFileError>() {
+            Ok(e) => return Self::MissingFile(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<ComparisonError>() {
+            Ok(e) => return Self::Comparison(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<OutputLimitError>() {
+            Ok(e) => return Self::OutputLimit(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<ParseError>() {
+            Ok(e) => return Self::Parse(*e),
+            Err(e) => e,
+        };
+        if e.is::<Halt>() {
+            return Self::Halt;
+        }
+        Self::Simulator(e)
+    }
+}
+
+impl From<std::io::Error> for ScriptError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Simulator(Box::new(e))
+    }
+}
+
 pub fn execute_script<'tst, 'w, P, X, C>(
     p: ScriptParser<'tst, P, C>,
     sim_executor: X,
     writer: impl Into<Option<&'w mut dyn Write>>,
-) -> ExecResult
+) -> Result<(), ScriptError>
 where
     C: SimulatorCommand,
     X: SimulatorExecutor<C>,
     ScriptParser<'tst, P, C>: SimulatorCommandParser<C>,
 {
-    let writer = writer.into();
-    let mut use_outfile = writer.is_none();
+    execute_script_with_options(p, sim_executor, writer, ScriptOptions::default())
+}
+
+pub fn execute_script_with_options<'tst, 'w, P, X, C>(
+    p: ScriptParser<'tst, P, C>,
+    sim_executor: X,
+    writer: impl Into<Option<&'w mut dyn Write>>,
+    options: ScriptOptions,
+) -> Result<(), ScriptError>
+where
+    C: SimulatorCommand,
+    X: SimulatorExecutor<C>,
+    ScriptParser<'tst, P, C>: SimulatorCommandParser<C>,
+{
+    ScriptRunner::new(p, sim_executor, writer, options).finish()
+}
 
-    let mut executor = BaseScriptExecutor::new(sim_executor, writer);
-    for cmd in p {
-        let result = executor.exec(cmd?.content);
+/// Executes a script one command at a time, e.g. to step through it in a debugger
+///
+/// Iterating yields every executed command (including its terminator and line)
+/// finish runs the rest of the script and compares the output against the compare file
+pub struct ScriptRunner<'tst, 'w, P, X, C>
+where
+    C: SimulatorCommand,
+    X: SimulatorExecutor<C>,
+{
+    parser: ScriptParser<'tst, P, C>,
+    executor: BaseScriptExecutor<'w, C, X>,
+    // the command returned by peek, which will be executed next
+    upcoming: Option<CmdResult<C>>,
+}
+
+impl<'tst, 'w, P, X, C> ScriptRunner<'tst, 'w, P, X, C>
+where
+    C: SimulatorCommand,
+    X: SimulatorExecutor<C>,
+    ScriptParser<'tst, P, C>: SimulatorCommandParser<C>,
+{
+    pub fn new(
+        p: ScriptParser<'tst, P, C>,
+        sim_executor: X,
+        writer: impl Into<Option<&'w mut dyn Write>>,
+        options: ScriptOptions,
+    ) -> Self {
+        Self {
+            parser: p,
+            executor: BaseScriptExecutor::new(sim_executor, writer, options),
+            upcoming: None,
+        }
+    }
+
+    /// The command that will be executed next, None if the script is done
+    /// If the next command can't be parsed, this is the error that next will return
+    pub fn peek(&mut self) -> Option<&CmdResult<C>> {
+        if self.upcoming.is_none() {
+            self.upcoming = self.parser.next();
+        }
+        self.upcoming.as_ref()
+    }
+
+    pub fn simulator(&self) -> &X {
+        &self.executor.sim
+    }
+
+    /// Execute the remaining commands and compare the output
+    pub fn finish(mut self) -> Result<(), ScriptError> {
+        while let Some(cmd) = self.next() {
+            if let Err(e) = cmd {
+                // still write the rows that were output before the error, so they can be inspected
+                // the original error is more important than one while writing them
+                let _ = self.executor.flush_buffered_rows();
+                return Err(e);
+            }
+        }
+
+        let mut executor = self.executor;
+        executor.flush_buffered_rows()?;
+        let wildcard = executor.options.wildcard;
+        let mut use_outfile = executor.writer.is_none();
+
+        if let Ok(writer) = executor.writer() {
+            writer.flush()?;
+        } else {
+            // if there is no output writer, we definitely don't want to use an outfile
+            use_outfile = false;
+        }
+
+        let (cmp_name, cmp_content) = if let Some(cmp) = &executor.compare_file {
+            (
+                cmp.to_string_lossy(),
+                read_to_string(cmp)
+                    .map_err(|e| MissingFileError::new(cmp, "compare-to", e))?
+                    .replace("\r\n", "\n"),
+            )
+        } else {
+            ("".into(), "".to_owned())
+        };
+
+        let out_content = if use_outfile {
+            let out_file = executor.output_file.ok_or(ScriptError::NoOutputFile)?.0;
+            read_to_string(&out_file)
+                .map_err(|e| MissingFileError::new(&out_file, "output-file", e))?
+                .replace("\r\n", "\n")
+        } else {
+            "".to_owned()
+        };
+
+        // this also ignores a missing (or additional) final newline in either file
+        let cmp_content = cmp_content.trim();
+        let out_content = out_content.trim();
+
+        if use_outfile {
+            let mut cmp_lines = cmp_content.lines();
+            let mut out_lines = out_content.lines();
+
+            for line in 1.. {
+                let (expected, actual) = match (cmp_lines.next(), out_lines.next()) {
+                    (None, None) => break,
+                    // like when streaming, a missing line is compared as an empty one
+                    (expected, actual) => {
+                        (expected.unwrap_or_default(), actual.unwrap_or_default())
+                    }
+                };
+
+                if let Some(col) = compare_line(expected, actual, wildcard) {
+                    return Err(ScriptError::Comparison(ComparisonError {
+                        cmp_file_name: cmp_name.into_owned(),
+                        line,
+                        col,
+                        script_line: None,
+                    }));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'tst, 'w, P, X, C> Iterator for ScriptRunner<'tst, 'w, P, X, C>
+where
+    C: SimulatorCommand,
+    X: SimulatorExecutor<C>,
+    ScriptParser<'tst, P, C>: SimulatorCommandParser<C>,
+{
+    type Item = Result<Spanned<Command<C>>, ScriptError>;
+
+    /// Execute the next command and return it
+    fn next(&mut self) -> Option<Self::Item> {
+        let cmd = match self.upcoming.take().or_else(|| self.parser.next())? {
+            Ok(cmd) => cmd,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        self.executor.current_line = cmd.line_nr;
+        let result = self.executor.exec(cmd.content.clone());
         // check if the simulator is done
         // this is not an actual error
-        if let Err(e) = &result {
+        if let Err(e) = result {
             if !e.is::<Halt>() {
                 // only report actual errors, not halting
-                result?;
+                return Some(Err(e.into()));
             }
         }
+
+        Some(Ok(cmd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::script::tst::VMEmulatorCommand;
+    use crate::simulators::vm::stdlib::Stdlib;
+    use crate::simulators::vm::VM;
+
+    #[test]
+    fn test_output_columns() {
+        let mut executor: BaseScriptExecutor<VMEmulatorCommand, VM> =
+            BaseScriptExecutor::new(VM::new(Stdlib::new()), None, ScriptOptions::default());
+        assert!(executor.output_columns().is_empty());
+
+        executor
+            .set_output_list(vec![
+                OutputListEntry::new("RAM[256]".to_owned(), NumberFormat::Decimal, 1, 6, 1),
+                OutputListEntry::new("RAM[16384]".to_owned(), NumberFormat::Binary, 0, 4, 0),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                ColumnSpec {
+                    name: "RAM[256]".to_owned(),
+                    title: "RAM[256]".to_owned(),
+                    format: NumberFormat::Decimal,
+                    width: 8,
+                },
+                ColumnSpec {
+                    name: "RAM[16384]".to_owned(),
+                    title: "RAM[".to_owned(),
+                    format: NumberFormat::Binary,
+                    width: 4,
+                },
+            ],
+            executor.output_columns()
+        );
     }
 
-    if let Ok(writer) = executor.writer() {
-        writer.flush()?;
-    } else {
-        // if there is no output writer, we definitely don't want to use an outfile
-        use_outfile = false;
+    #[test]
+    fn test_output_columns_report_pending_auto_width() {
+        use crate::parse::script::tst::VMSetTarget;
+
+        let mut executor: BaseScriptExecutor<VMEmulatorCommand, VM> =
+            BaseScriptExecutor::new(VM::new(Stdlib::new()), None, ScriptOptions::default());
+
+        executor
+            .set_output_list(vec![OutputListEntry::auto(
+                "RAM[0]".to_owned(),
+                NumberFormat::Binary,
+                1,
+                1,
+            )])
+            .unwrap();
+        // the column is at least as wide as its name
+        assert_eq!(8, executor.output_columns()[0].width);
+
+        let set = VMEmulatorCommand::Set(VMSetTarget::Ram(0), 12345);
+        executor
+            .exec(Command::new(CommandKind::Simulator(set)))
+            .unwrap();
+        executor.exec(Command::new(CommandKind::Output)).unwrap();
+        assert_eq!(18, executor.output_columns()[0].width);
     }
 
-    let (cmp_name, cmp_content) = if let Some(cmp) = &executor.compare_file {
-        (
-            cmp.to_str().ok_or("Illegal compare file path")?,
-            read_to_string(cmp)?.replace("\r\n", "\n"),
-        )
-    } else {
-        ("", "".to_owned())
-    };
-
-    let out_content = if use_outfile {
-        let out_file = executor.output_file.ok_or("missing output file")?.0;
-        read_to_string(out_file)?.replace("\r\n", "\n")
-    } else {
-        "".to_owned()
-    };
-
-    let cmp_content = cmp_content.trim();
-    let out_content = out_content.trim();
-
-    if use_outfile {
-        let mut line = 1;
-        let mut col = 0;
-
-        for (cmp_c, out_c) in cmp_content.chars().zip(out_content.chars()) {
-            // '*' are placeholders
-            if cmp_c != out_c && cmp_c != '*' {
-                let cmp_file_name = cmp_name.to_owned();
-                return Err(Box::new(ComparisonError {
-                    cmp_file_name,
-                    line,
-                    col,
-                }));
-            }
+    #[test]
+    fn test_script_runner_steps_command_by_command() {
+        use crate::parse::script::tst::{Terminator, VMSetTarget};
+        use crate::simulators::vm::script::VMEmulatorCommandParser;
+
+        let script = "set RAM[256] 1, set RAM[257] 2;\nset RAM[256] 3!";
+        let parser = ScriptParser::<VMEmulatorCommandParser, VMEmulatorCommand>::new(
+            Path::new("Test.tst"),
+            script,
+        );
+        let mut runner = ScriptRunner::new(
+            parser,
+            VM::new(Stdlib::new()),
+            None,
+            ScriptOptions::default(),
+        );
+
+        let upcoming = runner.peek().unwrap().as_ref().unwrap();
+        assert_eq!(
+            CommandKind::Simulator(VMEmulatorCommand::Set(VMSetTarget::Ram(256), 1)),
+            upcoming.content.kind
+        );
+        // peeking doesn't execute anything
+        assert_eq!(Some(0), runner.simulator().memory_at(256));
+
+        let cmd = runner.next().unwrap().unwrap();
+        assert_eq!(Terminator::MiniStep, cmd.content.terminator);
+        assert_eq!(Some(1), runner.simulator().memory_at(256));
+        assert_eq!(Some(0), runner.simulator().memory_at(257));
+
+        let cmd = runner.next().unwrap().unwrap();
+        assert_eq!(Terminator::SingleStep, cmd.content.terminator);
+        assert_eq!(Some(2), runner.simulator().memory_at(257));
+
+        let cmd = runner.next().unwrap().unwrap();
+        assert_eq!(Terminator::Stop, cmd.content.terminator);
+        assert_eq!(2, cmd.line_nr);
+        assert_eq!(Some(3), runner.simulator().memory_at(256));
+
+        assert!(runner.peek().is_none());
+        assert!(runner.next().is_none());
+        runner.finish().unwrap();
+    }
 
-            col += 1;
+    #[test]
+    fn test_script_runner_peek_reports_parse_errors() {
+        use crate::simulators::vm::script::VMEmulatorCommandParser;
+
+        let script = "set RAM[256] 1;\nset RAM[256];";
+        let parser = ScriptParser::<VMEmulatorCommandParser, VMEmulatorCommand>::new(
+            Path::new("Test.tst"),
+            script,
+        );
+        let mut runner = ScriptRunner::new(
+            parser,
+            VM::new(Stdlib::new()),
+            None,
+            ScriptOptions::default(),
+        );
+
+        runner.next().unwrap().unwrap();
+        assert!(matches!(runner.peek(), Some(Err(_))));
+        // the error is not lost by peeking
+        assert!(runner.next().unwrap().is_err());
+    }
 
-            if cmp_c == '\n' {
-                line += 1;
-                col = 0;
-            }
-        }
+    #[test]
+    fn test_diff_memory() {
+        let a = [0, 1, 2, 3, 4, 5];
+        let b = [0, 1, -2, 3, 4, 42];
+        assert_eq!(vec![(2, 2, -2), (5, 5, 42)], diff_memory(&a, &b));
+        assert_eq!(vec![(2, -2, 2), (5, 42, 5)], diff_memory(&b, &a));
+    }
 
-        if cmp_content.len() != out_content.len() {
-            let cmp_file_name = cmp_name.to_owned();
-            return Err(Box::new(ComparisonError {
-                cmp_file_name,
-                line,
-                col,
-            }));
-        }
+    #[test]
+    fn test_dump_memory() {
+        let memory = [256, 0, 0, -1, 0, 10];
+        assert_eq!(
+            "    0: 0100\n    3: FFFF\n    5: 000A\n",
+            dump_memory(&memory, None, NumberFormat::Hex)
+        );
+        assert_eq!(
+            "    2: 0\n    3: -1\n",
+            dump_memory(&memory, Some(2..4), NumberFormat::Decimal)
+        );
+        assert_eq!(
+            "    5: 0000000000001010\n",
+            dump_memory(&memory, Some(5..100), NumberFormat::Binary)
+        );
     }
 
-    Ok(())
+    #[test]
+    fn test_diff_memory_without_differences() {
+        let a = [7; 16];
+        assert!(diff_memory(&a, &a).is_empty());
+        // only the common prefix is compared
+        assert!(diff_memory(&a, &a[0..4]).is_empty());
+    }
 }
@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+/// Where the vm should stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum BreakpointTarget {
+    /// stop once the cycle count reaches this value
+    Cycle(u64),
+    /// stop whenever the pc reaches this instruction
+    Instruction(usize),
+    /// stop whenever the pc reaches this offset inside of the given file (the index of the file
+    /// in the loaded program, see VM::current_file_offset), so that a UI can set it from the
+    /// displayed source line
+    FileOffset { file: usize, offset: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BreakpointInfo {
+    pub target: BreakpointTarget,
+    pub enabled: bool,
+    /// how often the vm stopped at this breakpoint since it was added
+    pub hits: u32,
+}
+
+impl BreakpointInfo {
+    pub fn new(target: BreakpointTarget) -> Self {
+        Self {
+            target,
+            enabled: true,
+            hits: 0,
+        }
+    }
+
+    /// file_offset is the (file, offset) pair of the pc and should be None inside of builtin
+    /// functions
+    pub fn matches(&self, pc: usize, file_offset: Option<(usize, usize)>, cycles: u64) -> bool {
+        self.enabled
+            && match self.target {
+                BreakpointTarget::Cycle(cycle) => cycle == cycles,
+                BreakpointTarget::Instruction(instruction) => instruction == pc,
+                BreakpointTarget::FileOffset { file, offset } => {
+                    Some((file, offset)) == file_offset
+                }
+            }
+    }
+}
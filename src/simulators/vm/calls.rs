@@ -69,3 +69,25 @@ impl CallStackEntry {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallEventKind {
+    Enter,
+    Exit,
+}
+
+/// Emitted whenever a function frame is pushed or popped (see VM::take_call_events)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallEvent {
+    pub kind: CallEventKind,
+    pub function: String,
+}
+
+impl CallEvent {
+    pub fn new(kind: CallEventKind, function: impl Into<String>) -> Self {
+        Self {
+            kind,
+            function: function.into(),
+        }
+    }
+}
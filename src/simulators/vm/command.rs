@@ -1,3 +1,4 @@
+use serde::Serialize;
 use std::fmt;
 use std::str::FromStr;
 
@@ -14,7 +15,8 @@ impl fmt::Display for ByteCodeParseError {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Segment {
     Argument,
     Local,
@@ -26,6 +28,22 @@ pub enum Segment {
     Temp,
 }
 
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Segment::Argument => "argument",
+            Segment::Local => "local",
+            Segment::Static => "static",
+            Segment::Constant => "constant",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl FromStr for Segment {
     type Err = ByteCodeParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -43,7 +61,9 @@ impl FromStr for Segment {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+/// Serialized as e.g. { "op": "push", "segment": "constant", "index": 10 }
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
 pub enum Instruction {
     // arithmetic commands (no arguments)
     Add,
@@ -63,7 +83,151 @@ pub enum Instruction {
     // jump commands
     Goto { instruction: Symbol },
     IfGoto { instruction: Symbol },
-    Function { n_locals: Word },
+    Function { n_locals: u16 },
     Call { function: Symbol, n_args: Word },
+    // calls the function at the address on top of the stack
+    // this is not part of the spec, so it's only parsed if indirect calls are enabled
+    CallIndirect { n_args: Word },
     Return,
 }
+
+impl Instruction {
+    /// The name of the command without its arguments
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add => "add",
+            Instruction::Sub => "sub",
+            Instruction::Eq => "eq",
+            Instruction::Gt => "gt",
+            Instruction::Lt => "lt",
+            Instruction::And => "and",
+            Instruction::Or => "or",
+            Instruction::Not => "not",
+            Instruction::Neg => "neg",
+            Instruction::Push { .. } => "push",
+            Instruction::Pop { .. } => "pop",
+            Instruction::Goto { .. } => "goto",
+            Instruction::IfGoto { .. } => "if-goto",
+            Instruction::Function { .. } => "function",
+            Instruction::Call { .. } => "call",
+            Instruction::CallIndirect { .. } => "call-indirect",
+            Instruction::Return => "return",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Add => write!(f, "add"),
+            Instruction::Sub => write!(f, "sub"),
+            Instruction::Eq => write!(f, "eq"),
+            Instruction::Gt => write!(f, "gt"),
+            Instruction::Lt => write!(f, "lt"),
+            Instruction::And => write!(f, "and"),
+            Instruction::Or => write!(f, "or"),
+            Instruction::Not => write!(f, "not"),
+            Instruction::Neg => write!(f, "neg"),
+            Instruction::Push { segment, index } => write!(f, "push {} {}", segment, index),
+            Instruction::Pop { segment, index } => write!(f, "pop {} {}", segment, index),
+            // labels and functions are already resolved, so only their addresses are left
+            Instruction::Goto { instruction } => write!(f, "goto {}", instruction),
+            Instruction::IfGoto { instruction } => write!(f, "if-goto {}", instruction),
+            Instruction::Function { n_locals } => write!(f, "function {}", n_locals),
+            Instruction::Call { function, n_args } => write!(f, "call {} {}", function, n_args),
+            Instruction::CallIndirect { n_args } => write!(f, "call-indirect {}", n_args),
+            Instruction::Return => write!(f, "return"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_instructions() {
+        let cases = [
+            (Instruction::Add, "add"),
+            (Instruction::Sub, "sub"),
+            (Instruction::Eq, "eq"),
+            (Instruction::Gt, "gt"),
+            (Instruction::Lt, "lt"),
+            (Instruction::And, "and"),
+            (Instruction::Or, "or"),
+            (Instruction::Not, "not"),
+            (Instruction::Neg, "neg"),
+            (
+                Instruction::Push {
+                    segment: Segment::Constant,
+                    index: 10,
+                },
+                "push constant 10",
+            ),
+            (
+                Instruction::Pop {
+                    segment: Segment::Local,
+                    index: 2,
+                },
+                "pop local 2",
+            ),
+            (Instruction::Goto { instruction: 7 }, "goto 7"),
+            (Instruction::IfGoto { instruction: 12 }, "if-goto 12"),
+            (Instruction::Function { n_locals: 3 }, "function 3"),
+            (
+                Instruction::Call {
+                    function: 4,
+                    n_args: 2,
+                },
+                "call 4 2",
+            ),
+            (Instruction::CallIndirect { n_args: 1 }, "call-indirect 1"),
+            (Instruction::Return, "return"),
+        ];
+
+        for (instruction, expected) in cases {
+            assert_eq!(expected, instruction.to_string());
+        }
+    }
+
+    #[test]
+    fn test_display_segments() {
+        let segments = [
+            Segment::Argument,
+            Segment::Local,
+            Segment::Static,
+            Segment::Constant,
+            Segment::This,
+            Segment::That,
+            Segment::Pointer,
+            Segment::Temp,
+        ];
+
+        // displaying a segment should produce the same string it was parsed from
+        for segment in segments {
+            assert_eq!(Ok(segment), Segment::from_str(&segment.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_serialize_instructions() {
+        use serde_json::json;
+
+        assert_eq!(
+            json!({ "op": "push", "segment": "constant", "index": 10 }),
+            serde_json::to_value(Instruction::Push {
+                segment: Segment::Constant,
+                index: 10
+            })
+            .unwrap()
+        );
+        assert_eq!(
+            json!({ "op": "if-goto", "instruction": 3 }),
+            serde_json::to_value(Instruction::IfGoto { instruction: 3 }).unwrap()
+        );
+        assert_eq!(
+            json!({ "op": "add" }),
+            serde_json::to_value(Instruction::Add).unwrap()
+        );
+    }
+}
@@ -1,12 +1,25 @@
+use super::command::{Instruction, Segment};
 use super::stdlib::StdlibError;
-use crate::definitions::Address;
+use crate::definitions::{Address, Word};
 use std::{error, fmt};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VMError {
     IllegalProgramCounter(usize),
     IllegalMemoryAddress(Address),
-    CannotGetAddressOfConstant,
+    // a push outside of the stack (256..2048), e.g. because of endless recursion
+    StackOverflow(Address),
+    CannotGetAddressOfConstant(Instruction),
+    // only reported if strict this/that checking is enabled
+    UninitializedThisThat { segment: Segment, base: Word },
+    // only reported if strict static checking is enabled
+    UnwrittenStatic(Address),
+    // only reported if strict bool index checking is enabled
+    BooleanInAddress(Instruction),
+    DivisionByZero { dividend: Word, divisor: Word },
+    UnknownFunction(String),
+    // the address popped by call-indirect is not the start of a function
+    IllegalCallTarget(Word),
 
     // function call (stdlib) errors
     IllegalCallStackIndex,
@@ -14,6 +27,8 @@ pub enum VMError {
     TryingToContinueVMFunction,
     TryingToContinueTopLevelCode,
     NonExistingStdlibFunction,
+    // a builtin called again while it is still running on the rust stack
+    ReentrantBuiltin(String),
     StdlibError(StdlibError),
 }
 
@@ -32,7 +47,45 @@ impl fmt::Display for VMError {
         match self {
             Self::IllegalProgramCounter(pc) => write!(f, "Program counter out of bounds: {}", pc),
             Self::IllegalMemoryAddress(a) => write!(f, "Illegal memory address: {}", a),
-            Self::CannotGetAddressOfConstant => write!(f, "Trying to get address of constant"),
+            Self::StackOverflow(a) => write!(
+                f,
+                "Stack overflow: pushing to {} which is outside of the stack (256-2047)",
+                a
+            ),
+            Self::CannotGetAddressOfConstant(instr) => {
+                write!(f, "Trying to get address of constant in '{}'", instr)
+            }
+            Self::UninitializedThisThat { segment, base } => write!(
+                f,
+                "Accessing the {} segment with a base pointer outside of the heap: {}",
+                if *segment == Segment::This {
+                    "this"
+                } else {
+                    "that"
+                },
+                base
+            ),
+            Self::UnwrittenStatic(address) => write!(
+                f,
+                "Reading the static variable at {} before anything was popped into it",
+                address
+            ),
+            Self::BooleanInAddress(instr) => write!(
+                f,
+                "The result of a comparison (true = -1) is used as an address or index in '{}'",
+                instr
+            ),
+            Self::DivisionByZero { dividend, divisor } => {
+                write!(f, "Division by zero: {} / {}", dividend, divisor)
+            }
+            Self::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            Self::IllegalCallTarget(address) => {
+                write!(
+                    f,
+                    "call-indirect to an address that is not a function: {}",
+                    address
+                )
+            }
             Self::IllegalCallStackIndex => write!(f, "Illegal call stack index"),
             Self::AccessingEmptyCallStack => write!(f, "Trying to access empty call stack"),
             Self::TryingToContinueVMFunction => write!(f, "Trying to continue VM Function"),
@@ -40,6 +93,13 @@ impl fmt::Display for VMError {
             Self::NonExistingStdlibFunction => {
                 write!(f, "Trying to call non existing stdlib function")
             }
+            Self::ReentrantBuiltin(name) => {
+                write!(
+                    f,
+                    "The builtin function {} cannot be called while it is running",
+                    name
+                )
+            }
             Self::StdlibError(error) => write!(f, "{}", error),
         }
     }
@@ -0,0 +1,80 @@
+use crate::definitions::{Address, Word, HEAP_START};
+
+/// A segment in the free list of the builtin Memory implementation
+/// Every segment starts with its capacity followed by the address of the next segment.
+/// Allocated segments have a capacity of 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapSegment {
+    pub address: Address,
+    pub capacity: usize,
+    pub free: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStats {
+    pub total: usize,
+    pub free: usize,
+    pub largest_free_block: usize,
+    pub free_blocks: usize,
+}
+
+/// Walk the segment list starting at HEAP_START up to heap_end (inclusive)
+///
+/// This only makes sense for the builtin Memory implementation (after Memory.init was called).
+/// The walk stops as soon as a segment points backwards, so garbage can't cause endless loops
+pub fn heap_segments(memory: &[Word], heap_end: Address) -> Vec<HeapSegment> {
+    let mut segments = Vec::new();
+    let mut address = HEAP_START;
+
+    while address < heap_end {
+        let (capacity, next) = match (memory.get(address), memory.get(address + 1)) {
+            (Some(&capacity), Some(&next)) => (capacity, next as Address),
+            _ => break,
+        };
+
+        if capacity < 0 {
+            break;
+        }
+
+        // allocated segments have a capacity of 0, but their size can be derived from the
+        // address of the next segment
+        let free = capacity > 0;
+        let capacity = if free {
+            capacity as usize
+        } else {
+            next.saturating_sub(address + 2)
+        };
+
+        segments.push(HeapSegment {
+            address,
+            capacity,
+            free,
+        });
+
+        if next <= address {
+            break;
+        }
+        address = next;
+    }
+
+    segments
+}
+
+pub fn heap_stats(memory: &[Word], heap_end: Address) -> HeapStats {
+    let free_segments = heap_segments(memory, heap_end)
+        .into_iter()
+        .filter(|s| s.free);
+
+    let mut stats = HeapStats {
+        total: heap_end + 1 - HEAP_START,
+        ..Default::default()
+    };
+
+    for segment in free_segments {
+        stats.free += segment.capacity;
+        stats.largest_free_block = stats.largest_free_block.max(segment.capacity);
+        stats.free_blocks += 1;
+    }
+
+    stats
+}
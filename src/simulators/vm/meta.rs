@@ -1,5 +1,6 @@
-use crate::definitions::{Symbol, Word};
+use crate::definitions::{Address, Symbol};
 use std::collections::HashMap;
+use std::ops::Range;
 
 #[derive(Debug, Copy, Clone)]
 pub enum FileInfo {
@@ -28,19 +29,22 @@ impl FileInfo {
 pub struct FunctionInfo {
     pub file: FileInfo,
     pub name: String,
-    pub n_locals: Word,
+    pub n_locals: u16,
+    // the names of the locals from a "// @locals" annotation, empty if there is none
+    pub local_names: Vec<String>,
 }
 
 impl FunctionInfo {
-    pub fn builtin(name: String, n_locals: Word, filename: &'static str) -> Self {
+    pub fn builtin(name: String, n_locals: u16, filename: &'static str) -> Self {
         Self {
             file: FileInfo::Builtin(filename),
             name,
             n_locals,
+            local_names: Vec::new(),
         }
     }
 
-    pub fn vm(name: String, n_locals: Word, module_index: usize, start_line: usize) -> Self {
+    pub fn vm(name: String, n_locals: u16, module_index: usize, start_line: usize) -> Self {
         Self {
             file: FileInfo::VM {
                 module_index,
@@ -48,6 +52,7 @@ impl FunctionInfo {
             },
             name,
             n_locals,
+            local_names: Vec::new(),
         }
     }
 }
@@ -61,6 +66,10 @@ pub struct MetaInfo {
     pub function_meta: HashMap<Symbol, FunctionInfo>,
     // the vm should be able to call functions by their names. This is needed for the stdlib
     pub function_by_name: HashMap<String, Symbol>,
+    // the static variable addresses of every file (by filename), used to label the memory view
+    pub static_ranges: HashMap<String, Range<Address>>,
+    // the line in its source file of every instruction (starting at 1), empty if unknown
+    pub source_lines: Vec<u32>,
 }
 
 impl MetaInfo {
@@ -71,6 +80,8 @@ impl MetaInfo {
         Self {
             function_meta,
             function_by_name,
+            static_ranges: HashMap::new(),
+            source_lines: Vec::new(),
         }
     }
 
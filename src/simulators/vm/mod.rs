@@ -1,21 +1,29 @@
 pub mod command;
 pub mod error;
+pub mod heap;
 pub mod meta;
 pub mod script;
 pub mod stdlib;
 
+mod breakpoints;
 mod calls;
+mod replay;
 
+pub use breakpoints::{BreakpointInfo, BreakpointTarget};
+pub use calls::{CallEvent, CallEventKind};
 pub use error::VMError;
+pub use replay::{ReplayEvent, ReplayInput, ReplayLog};
 
 use crate::definitions::{
-    Address, Symbol, Word, ARG, INIT_SP, KBD, LCL, MEM_SIZE, SCREEN_END, SCREEN_START, SP, THAT,
-    THIS,
+    Address, Symbol, Word, ARG, HEAP_END, HEAP_START, INIT_SP, KBD, LCL, MEM_SIZE, NEWLINE_KEY,
+    SCREEN_END, SCREEN_START, SP, THAT, THIS,
 };
 use calls::*;
 use command::{Instruction, Segment};
 use meta::{FileInfo, FunctionInfo, MetaInfo};
-use stdlib::{BuiltinFunction, State, Stdlib, StdlibError, StdlibOk, VMCallOk};
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
+use stdlib::{BuiltinFunction, State, StdResult, Stdlib, StdlibError, StdlibOk, VMCallOk};
 
 pub trait ProgramInfo {
     fn take_instructions(&mut self) -> Vec<Instruction>;
@@ -35,6 +43,47 @@ pub struct VM {
     stdlib: Stdlib,
     // if this is set to Some(address) the vm will jump to Sys.init on the next step
     sys_init: Option<Symbol>,
+    // report accesses to this/that while their base pointer doesn't point into the heap
+    strict_this_that: bool,
+    // report reads of static variables which were never popped into
+    strict_statics: bool,
+    // the addresses of all statics that were written by a pop since loading the program
+    written_statics: HashSet<Address>,
+    // the first bytecode instruction of the program (see VM::entry_point)
+    entry_point: usize,
+    // report using true (-1) from a comparison in an address calculation (e.g. as array index)
+    strict_bool_index: bool,
+    // the stack address of the most recent comparison result, as long as it's still on the stack
+    // the bool is set if the slot holds the sum of true and something else (e.g. arr + (i < j))
+    comparison_slot: Option<(Address, bool)>,
+    // function enters/exits for tracing UIs, only recorded if this is Some
+    call_events: Option<Vec<CallEvent>>,
+    // keys which are consumed by Keyboard.readChar before waiting for a real key press
+    input_queue: VecDeque<Word>,
+    // the value returned by the most recently finished function
+    last_return_value: Option<Word>,
+    // the last address that the builtin Memory implementation treats as part of the heap
+    heap_end: Address,
+    // the number of steps since the program was loaded
+    cycles: u64,
+    // the value of cycles at the last reset_cycle_count, cycle_count is relative to this
+    // cycles itself keeps counting, because replays are timed by it
+    cycles_at_reset: u64,
+    // the steps that executed a bytecode instruction / continued a builtin function
+    instructions_executed: u64,
+    builtin_steps: u64,
+    // stepping stops once one of these is reached
+    breakpoints: Vec<BreakpointInfo>,
+    // how many steps a released key stays in KBD
+    key_release_delay: u32,
+    // the steps left until a released key is actually cleared from KBD
+    pending_key_release: Option<u32>,
+    // all input since the program was loaded, only recorded if this is Some
+    replay_recording: Option<ReplayLog>,
+    // input from an imported replay log which is applied once its cycle is reached
+    pending_replay: VecDeque<ReplayEvent>,
+    // the recoverable stdlib errors since the last take_stdlib_log
+    stdlib_log: Vec<StdlibError>,
 
     // 0-15        virtual registers
     // 16-255      static variables
@@ -102,6 +151,16 @@ macro_rules! tos_unary {
     }};
 }
 
+/// Every division in the vm (currently only Math.divide) should go through this, so that there
+/// is only a single error for dividing by zero
+pub(crate) fn checked_divide(dividend: Word, divisor: Word) -> VMResult<Word> {
+    if divisor == 0 {
+        return Err(VMError::DivisionByZero { dividend, divisor });
+    }
+    // -32768 / -1 overflows, the hack platform just wraps around
+    Ok(dividend.wrapping_div(divisor))
+}
+
 impl VM {
     pub fn new(stdlib: Stdlib) -> Self {
         Self {
@@ -112,7 +171,192 @@ impl VM {
             memory: Box::new([0; MEM_SIZE]),
             stdlib,
             sys_init: None,
+            strict_this_that: false,
+            strict_statics: false,
+            written_statics: HashSet::new(),
+            entry_point: 0,
+            strict_bool_index: false,
+            comparison_slot: None,
+            call_events: None,
+            input_queue: VecDeque::new(),
+            last_return_value: None,
+            heap_end: HEAP_END,
+            cycles: 0,
+            cycles_at_reset: 0,
+            instructions_executed: 0,
+            builtin_steps: 0,
+            breakpoints: Vec::new(),
+            key_release_delay: 0,
+            pending_key_release: None,
+            replay_recording: None,
+            pending_replay: VecDeque::new(),
+            stdlib_log: Vec::new(),
+        }
+    }
+
+    /// Create a vm that starts with the given memory image instead of zeroed memory
+    /// load zeroes the memory again, use load_preserving_memory to run a program on the image
+    pub fn with_memory(stdlib: Stdlib, memory: Box<[Word; MEM_SIZE]>) -> Self {
+        Self {
+            memory,
+            ..Self::new(stdlib)
+        }
+    }
+
+    /// Make a host function callable from the bytecode under the given name (e.g. "Host.log")
+    ///
+    /// The parser resolves calls with its own copy of the stdlib, so this has to happen before
+    /// parsing and the program has to be parsed with a clone of this VMs stdlib (see VM::stdlib)
+    pub fn register_builtin(
+        &mut self,
+        name: &'static str,
+        n_args: usize,
+        function: &'static dyn Fn(&mut VM, State, &[Word]) -> StdResult,
+    ) -> Symbol {
+        let file = name.split('.').next().unwrap_or(name);
+        self.stdlib.register(name, file, n_args, function)
+    }
+
+    pub fn stdlib(&self) -> &Stdlib {
+        &self.stdlib
+    }
+
+    /// Start or stop recording function enters/exits
+    /// Disabling the recording also drops all events that weren't taken yet
+    pub fn set_record_call_events(&mut self, record: bool) {
+        self.call_events = if record { Some(Vec::new()) } else { None };
+    }
+
+    /// All events recorded since the last call of this function
+    pub fn take_call_events(&mut self) -> Vec<CallEvent> {
+        self.call_events
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Start or stop recording all input for a later replay
+    /// Disabling the recording drops the recorded log
+    pub fn set_record_replay(&mut self, record: bool) {
+        self.replay_recording = if record {
+            Some(ReplayLog::default())
+        } else {
+            None
+        };
+    }
+
+    /// The input recorded since the program was loaded
+    pub fn replay_log(&self) -> Option<&ReplayLog> {
+        self.replay_recording.as_ref()
+    }
+
+    /// Apply the input of a recorded log at the same cycles as during the recording
+    /// This should be called right after loading the same program that was recorded
+    pub fn replay(&mut self, log: ReplayLog) {
+        self.pending_replay = log.events.into();
+    }
+
+    fn apply_pending_replay(&mut self) -> VMResult {
+        while let Some(event) = self.pending_replay.front() {
+            if event.cycle > self.cycles {
+                break;
+            }
+
+            match self.pending_replay.pop_front().map(|e| e.input) {
+                Some(ReplayInput::Key(key)) => self.set_input_key(key)?,
+                Some(ReplayInput::Text(text)) => self.set_input_string(&text),
+                Some(ReplayInput::Clear) => self.clear_input(),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn record_input(&mut self, input: ReplayInput) {
+        if let Some(log) = &mut self.replay_recording {
+            log.events.push(ReplayEvent {
+                cycle: self.cycles,
+                input,
+            });
+        }
+    }
+
+    /// The value returned by the most recent vm or builtin function
+    /// None if no function has returned since the program was loaded
+    pub fn last_return_value(&self) -> Option<Word> {
+        self.last_return_value
+    }
+
+    /// Make the builtin Memory implementation use only the heap up to end (inclusive)
+    /// The limit is clamped to the real heap and only takes effect when Memory.init is called
+    pub fn set_heap_limit(&mut self, end: Address) {
+        self.heap_end = end.clamp(HEAP_START + 2, HEAP_END);
+    }
+
+    pub fn heap_end(&self) -> Address {
+        self.heap_end
+    }
+
+    /// Report an error when the this/that segments are accessed while THIS/THAT point outside
+    /// of the heap (e.g. calling a method before constructing the object)
+    /// This is opt-in, because hand written vm code can use them for other memory areas
+    pub fn set_strict_this_that(&mut self, strict: bool) {
+        self.strict_this_that = strict;
+    }
+
+    /// Report pushing a static variable that was never written by a pop
+    /// Since every static index is valid, a typo in the index would otherwise just read a 0
+    pub fn set_strict_statics(&mut self, strict: bool) {
+        self.strict_statics = strict;
+    }
+
+    /// Report popping true (-1) from a comparison, or a sum/difference with it, into a pointer
+    /// This is a heuristic for booleans that are accidentally used as an array index
+    /// Plain arithmetic with a comparison result (e.g. x - (a < b)) is not reported
+    pub fn set_strict_bool_index(&mut self, strict: bool) {
+        self.strict_bool_index = strict;
+        self.comparison_slot = None;
+    }
+
+    /// Track comparison results on the stack and check if instr would use one as an address
+    fn check_bool_index(&mut self, instr: Instruction) -> VMResult {
+        let sp = self.mem(SP)? as Address;
+        if self.comparison_slot.is_some_and(|(slot, _)| slot >= sp) {
+            // the result was already consumed
+            self.comparison_slot = None;
+        }
+
+        // Some(is_true) if the comparison result is one of the top n_operands stack values
+        let operand = |n_operands: Address| -> VMResult<Option<bool>> {
+            match self.comparison_slot {
+                Some((slot, derived)) if slot + n_operands >= sp => {
+                    Ok(Some(derived || self.mem(slot)? == -1))
+                }
+                _ => Ok(None),
+            }
+        };
+
+        match instr {
+            Instruction::Add | Instruction::Sub => match operand(2)? {
+                // the sum is not an address yet, but it might be popped into a pointer next
+                Some(true) => self.comparison_slot = sp.checked_sub(2).map(|slot| (slot, true)),
+                Some(false) => self.comparison_slot = None,
+                None => {}
+            },
+            Instruction::Pop {
+                segment: Segment::Pointer,
+                ..
+            } if operand(1)? == Some(true) => {
+                return Err(VMError::BooleanInAddress(instr));
+            }
+            Instruction::Eq | Instruction::Gt | Instruction::Lt => {
+                // both operands are replaced by the result
+                self.comparison_slot = sp.checked_sub(2).map(|slot| (slot, false));
+            }
+            _ => {}
         }
+
+        Ok(())
     }
 
     #[inline]
@@ -129,6 +373,11 @@ impl VM {
             .memory
             .get_mut(address)
             .ok_or(VMError::IllegalMemoryAddress(address))? = value;
+        // statics can also be written by scripts and builtins, not just by pop static
+        // they live in RAM[16..256], below that are the pointers and the temp segment
+        if self.strict_statics && (16..INIT_SP as Address).contains(&address) {
+            self.written_statics.insert(address);
+        }
         Ok(())
     }
 
@@ -140,10 +389,35 @@ impl VM {
 
     #[inline]
     fn push(&mut self, value: Word) -> VMResult {
+        self.check_stack_space(1)?;
         self.set_mem_indirect(SP, 0, value)?;
         self.add_to_mem(SP, 1)
     }
 
+    /// Make sure that n words can be pushed without leaving the stack (INIT_SP..HEAP_START)
+    /// Without this, deep recursion would silently overwrite the heap
+    fn check_stack_space(&self, n: usize) -> VMResult {
+        let sp = self.mem(SP)?;
+        let stack = INIT_SP as Address..HEAP_START;
+        match usize::try_from(sp) {
+            Ok(sp) if !stack.contains(&sp) => Err(VMError::StackOverflow(sp)),
+            // the first address after the stack would be written
+            Ok(sp) if sp + n > stack.end => Err(VMError::StackOverflow(stack.end)),
+            Ok(_) => Ok(()),
+            // a negative SP is an illegal address anyway
+            Err(_) => Err(VMError::IllegalMemoryAddress(sp as u16 as Address)),
+        }
+    }
+
+    /// Used by the builtins: recoverable errors are logged, all others are returned
+    fn report_stdlib_error(&mut self, error: StdlibError) -> Result<(), StdlibError> {
+        if !error.is_recoverable() {
+            return Err(error);
+        }
+        self.stdlib_log.push(error);
+        Ok(())
+    }
+
     fn call(&mut self, name: &str, params: &[Word]) -> VMResult<VMCallOk> {
         trace_calls!({
             println!("Calling {} by name", name);
@@ -199,68 +473,186 @@ impl VM {
         self.mem(sp - 1)
     }
 
-    fn get_seg_address(&self, segment: Segment, index: i16) -> VMResult<Address> {
+    fn this_that_base(&self, segment: Segment, register: Address) -> VMResult<Word> {
+        let base = self.mem(register)?;
+        let heap = HEAP_START as i32..=self.heap_end as i32;
+        if self.strict_this_that && !heap.contains(&(base as i32)) {
+            return Err(VMError::UninitializedThisThat { segment, base });
+        }
+        Ok(base)
+    }
+
+    /// The address of index in segment, instr is the executed instruction for error messages
+    fn get_seg_address(
+        &self,
+        instr: Instruction,
+        segment: Segment,
+        index: i16,
+    ) -> VMResult<Address> {
         let offset = match segment {
             Segment::Local => self.mem(LCL)?,
             Segment::Argument => self.mem(ARG)?,
-            Segment::This => self.mem(THIS)?,
-            Segment::That => self.mem(THAT)?,
+            Segment::This => self.this_that_base(segment, THIS)?,
+            Segment::That => self.this_that_base(segment, THAT)?,
             Segment::Temp => 5,
             Segment::Pointer => 3,
             // Static memory segments are actually resolved in the ByteCode BytecodeParser
             // The parser will simply set the index to an offset unique for the source file
             // it is currently parsing.
             Segment::Static => 0,
-            Segment::Constant => return Err(VMError::CannotGetAddressOfConstant),
+            // constants are handled in get_value, so this can only be a pop
+            Segment::Constant => return Err(VMError::CannotGetAddressOfConstant(instr)),
         };
         Ok(offset as Address + index as Address)
     }
 
-    fn get_value(&self, segment: Segment, index: i16) -> VMResult<Word> {
+    fn get_value(&self, instr: Instruction, segment: Segment, index: i16) -> VMResult<Word> {
         if segment == Segment::Constant {
             Ok(index)
         } else {
-            let addr = self.get_seg_address(segment, index)?;
+            let addr = self.get_seg_address(instr, segment, index)?;
+            if self.strict_statics
+                && segment == Segment::Static
+                && !self.written_statics.contains(&addr)
+            {
+                return Err(VMError::UnwrittenStatic(addr));
+            }
             self.mem(addr)
         }
     }
 
-    pub fn load(&mut self, mut info: impl ProgramInfo) {
+    pub fn load(&mut self, info: impl ProgramInfo) {
+        self.clear_memory();
+        self.load_preserving_memory(info);
+    }
+
+    /// Like load, but the memory (including SP and the other registers) is left untouched
+    /// This is useful to reproduce a bug from a memory image (see VM::with_memory)
+    pub fn load_preserving_memory(&mut self, mut info: impl ProgramInfo) {
         self.program = info.take_instructions();
-        let meta = info.take_meta();
-        let sys_init = meta.sys_init_address();
-        self.meta = meta;
+        self.meta = info.take_meta();
+        self.restart();
+    }
 
-        self.pc = 0;
-        for i in 0..self.memory.len() {
-            self.memory[i] = 0;
-        }
+    /// Run the loaded program again from the start (with cleared memory) without parsing it again
+    /// A function set with set_entry_function is forgotten, so the program starts with Sys.init
+    pub fn reset(&mut self) {
+        self.clear_memory();
+        self.restart();
+    }
+
+    fn clear_memory(&mut self) {
+        self.memory.fill(0);
         // page 162 of the book:
         // the VM implementation c
         // an start by generating assembly code that sets SP=256
         self.set_mem(SP, INIT_SP).unwrap(); // cannot fail
+    }
+
+    /// Reset everything but the memory to the state right after loading the program
+    fn restart(&mut self) {
+        let sys_init = self.meta.sys_init_address();
+        self.pc = 0;
 
         self.call_stack.clear();
+        if let Some(events) = &mut self.call_events {
+            events.clear();
+        }
+        self.input_queue.clear();
+        self.written_statics.clear();
+        self.comparison_slot = None;
+        self.last_return_value = None;
+        self.cycles = 0;
+        self.cycles_at_reset = 0;
+        self.instructions_executed = 0;
+        self.builtin_steps = 0;
+        self.pending_key_release = None;
+        if let Some(log) = &mut self.replay_recording {
+            log.events.clear();
+        }
+        self.pending_replay.clear();
+        self.stdlib_log.clear();
         self.push_call(CallStackEntry::top_level());
 
         match sys_init {
             Some(sys_init_address) if sys_init_address != 0 => {
                 self.sys_init = Some(sys_init_address);
+                self.entry_point = self.entry_of(sys_init_address);
             }
             _ => {
                 // the vm must behave slightly differently if there is no Sys.init function
                 // in this case the execution will simply begin at the zero'th instruction, instead
                 // of calling Sys.init, which means that the top level function is a VM function
+                // a bytecode Sys.init at address 0 also ends up here, which is fine, because the
+                // execution starts inside of it anyway
                 self.push_call(CallStackEntry::top_level_vm());
+                self.entry_point = 0;
             }
         }
     }
 
+    /// The first bytecode instruction that is executed when the program starts with function
+    fn entry_of(&self, function: Symbol) -> usize {
+        if self.stdlib.by_address(function).is_none() {
+            return function as usize;
+        }
+
+        // the builtin Sys.init immediately calls Main.main
+        let sys_init = self.meta.function_by_name.get("Sys.init");
+        let main = self.meta.function_by_name.get("Main.main");
+        match (sys_init, main) {
+            (Some(&sys_init), Some(&main)) if sys_init == function => main as usize,
+            // other builtins don't call into the bytecode
+            _ => self.pc,
+        }
+    }
+
+    /// The first bytecode instruction that is executed after loading the program
+    /// This is the start of Sys.init (or of Main.main if Sys.init is builtin), the entry
+    /// function set with set_entry_function, or 0 if there is no Sys.init
+    pub fn entry_point(&self) -> usize {
+        self.entry_point
+    }
+
+    /// Start the loaded program by calling the given function (without arguments) instead of
+    /// Sys.init or the first instruction
+    /// This has to be called before the first step after loading the program
+    pub fn set_entry_function(&mut self, name: &str) -> VMResult {
+        let address = self
+            .meta
+            .function_by_name
+            .get(name)
+            .copied()
+            .ok_or_else(|| VMError::UnknownFunction(name.to_owned()))?;
+
+        self.pc = 0;
+        self.call_stack.clear();
+        self.push_call(CallStackEntry::top_level());
+        self.sys_init = Some(address);
+        self.entry_point = self.entry_of(address);
+        Ok(())
+    }
+
     fn function_meta(&self, function: Symbol) -> Option<&FunctionInfo> {
         self.meta.function_meta.get(&function)
     }
 
+    fn record_call_event(&mut self, kind: CallEventKind, function: Option<Symbol>) {
+        if self.call_events.is_none() {
+            return;
+        }
+
+        let name = function
+            .and_then(|f| self.function_meta(f))
+            .map(|f| f.name.clone());
+
+        if let (Some(events), Some(name)) = (&mut self.call_events, name) {
+            events.push(CallEvent::new(kind, name));
+        }
+    }
+
     fn push_call(&mut self, entry: CallStackEntry) -> usize {
+        self.record_call_event(CallEventKind::Enter, entry.function);
         let idx = self.call_stack.len();
         self.call_stack.push(entry);
         idx
@@ -305,9 +697,12 @@ impl VM {
     }
 
     fn pop_call(&mut self) -> VMResult<CallStackEntry> {
-        self.call_stack
+        let entry = self
+            .call_stack
             .pop()
-            .ok_or(VMError::AccessingEmptyCallStack)
+            .ok_or(VMError::AccessingEmptyCallStack)?;
+        self.record_call_event(CallEventKind::Exit, entry.function);
+        Ok(entry)
     }
 
     pub fn call_stack_names(&self) -> Vec<&str> {
@@ -320,9 +715,31 @@ impl VM {
             .collect()
     }
 
+    /// The same as call_stack_names().len(), but without allocating
+    pub fn call_stack_depth(&self) -> usize {
+        self.call_stack
+            .iter()
+            .filter_map(|c| c.function.and_then(|f| self.function_meta(f)))
+            .count()
+    }
+
+    /// The return addresses saved in the frames (LCL - 5) of the VM functions on the call stack,
+    /// starting with the outermost call
+    /// These are read from memory, so a corrupted frame shows up as a wrong address
+    pub fn return_address_chain(&self) -> Vec<usize> {
+        self.call_stack
+            .iter()
+            .filter(|c| c.state == CallState::VM && c.function.is_some())
+            .filter_map(|c| (c.base_pointer as Address).checked_sub(5))
+            .filter_map(|address| self.memory.get(address))
+            .map(|&ret| ret as u16 as usize)
+            .collect()
+    }
+
     fn handle_builtin_finished(&mut self, ret_val: Word) -> VMResult {
         let this_call = self.pop_call()?;
         self.push(ret_val)?;
+        self.last_return_value = Some(ret_val);
         if let ReturnAddress::VM(ret_addr) = this_call.ret_addr {
             // jump to the appropriate position
             self.pc = ret_addr as usize;
@@ -391,6 +808,19 @@ impl VM {
             println!("{:?}", self.call_stack_names());
         });
 
+        // a builtin that calls another builtin runs it synchronously on the rust stack, so a
+        // builtin that is called again by itself or by one of the builtins it called (A -> B -> A)
+        // would recurse on the rust stack without ever yielding back to the vm
+        let reentrant = self
+            .call_stack
+            .iter()
+            .rev()
+            .take_while(|call| matches!(call.state, CallState::Builtin(..)))
+            .any(|call| call.function == Some(function.virtual_address()));
+        if reentrant {
+            return Err(VMError::ReentrantBuiltin(function.name().to_owned()));
+        }
+
         let ret_addr = self.return_address()?;
         let init_state = 0;
         let index = self.push_call(CallStackEntry::builtin(
@@ -425,6 +855,9 @@ impl VM {
             println!("{:?}", self.call_stack_names());
         });
 
+        // check the whole frame, so that a failing call doesn't leave half of it on the stack
+        self.check_stack_space(5)?;
+
         let ret_addr = self.pc + 1;
         self.push(ret_addr as Word)?;
 
@@ -473,10 +906,25 @@ impl VM {
 
     pub fn step(&mut self) -> VMResult {
         use Instruction::{
-            Add, And, Call, Eq, Function, Goto, Gt, IfGoto, Lt, Neg, Not, Or, Pop, Push, Return,
-            Sub,
+            Add, And, Call, CallIndirect, Eq, Function, Goto, Gt, IfGoto, Lt, Neg, Not, Or, Pop,
+            Push, Return, Sub,
         };
 
+        if !self.pending_replay.is_empty() {
+            self.apply_pending_replay()?;
+        }
+
+        self.cycles += 1;
+
+        match self.pending_key_release {
+            Some(0) => {
+                self.pending_key_release = None;
+                self.set_mem(KBD, 0)?;
+            }
+            Some(remaining) => self.pending_key_release = Some(remaining - 1),
+            None => {}
+        }
+
         if let Some(sys_init_address) = self.sys_init {
             self.sys_init = None;
             if self.stdlib.by_address(sys_init_address).is_some() {
@@ -488,15 +936,8 @@ impl VM {
             return Ok(());
         }
 
-        let currently_in_builtin_f = matches!(
-            self.peek_call(),
-            Ok(CallStackEntry {
-                state: CallState::Builtin(_, _),
-                ..
-            })
-        );
-
-        if currently_in_builtin_f {
+        if self.in_builtin() {
+            self.builtin_steps += 1;
             let peeked = self.peek_call()?.clone();
             return self.continue_builtin_function(peeked);
         }
@@ -505,6 +946,11 @@ impl VM {
             .program
             .get(self.pc)
             .ok_or(VMError::IllegalProgramCounter(self.pc))?;
+        self.instructions_executed += 1;
+
+        if self.strict_bool_index {
+            self.check_bool_index(instr)?;
+        }
 
         match instr {
             Add => tos_binary!(self, +),
@@ -517,7 +963,7 @@ impl VM {
             Gt => tos_binary_bool!(self, >),
             Lt => tos_binary_bool!(self, <),
             Push { segment, index } => {
-                let value = self.get_value(segment, index)?;
+                let value = self.get_value(instr, segment, index)?;
 
                 trace_vm!({
                     println!("push {:?} {} {}", segment, index, value);
@@ -527,7 +973,7 @@ impl VM {
                 self.pc += 1;
             }
             Pop { segment, index } => {
-                let address = self.get_seg_address(segment, index)?;
+                let address = self.get_seg_address(instr, segment, index)?;
                 let value = self.pop()?;
 
                 trace_vm!({
@@ -589,6 +1035,7 @@ impl VM {
                 // reposition the return value for the caller
                 let return_value = self.pop()?;
                 self.set_mem_indirect(ARG, 0, return_value)?;
+                self.last_return_value = Some(return_value);
 
                 // restore the stack for the caller
                 self.set_mem(SP, self.mem(ARG)? + 1)?;
@@ -621,6 +1068,22 @@ impl VM {
             Call { function, n_args } => {
                 self.call_function(function, n_args)?;
             }
+            CallIndirect { n_args } => {
+                let target = self.pop()?;
+                let function =
+                    Symbol::try_from(target).map_err(|_| VMError::IllegalCallTarget(target))?;
+
+                let is_function = self.stdlib.by_address(function).is_some()
+                    || matches!(
+                        self.program.get(function as usize),
+                        Some(Instruction::Function { .. })
+                    );
+                if !is_function {
+                    return Err(VMError::IllegalCallTarget(target));
+                }
+
+                self.call_function(function, n_args)?;
+            }
         };
 
         trace_vm!({
@@ -646,6 +1109,9 @@ impl VM {
         let mut last_state = None;
         loop {
             self.step()?;
+            if self.hit_breakpoint() {
+                return Ok(());
+            }
             match self.call_stack.last() {
                 Some(CallStackEntry {
                     state: CallState::VM,
@@ -670,17 +1136,279 @@ impl VM {
         }
     }
 
+    /// Like step, but a call is executed until the called function returns, a breakpoint is hit
+    /// or a builtin function is waiting for input (like step_until_vm_instr)
+    /// Recursive calls don't stop it early, because only the depth of the call stack is compared
+    /// If the call doesn't return within max_steps steps, this stops inside of it
+    pub fn step_over(&mut self, max_steps: Option<u64>) -> VMResult {
+        let is_call = matches!(
+            self.current_instruction(),
+            Some(Instruction::Call { .. } | Instruction::CallIndirect { .. })
+        );
+        if self.in_builtin() || !is_call {
+            return self.step();
+        }
+
+        let depth = self.call_stack.len();
+        let max_steps = max_steps.unwrap_or(u64::MAX);
+        let mut steps = 0;
+        let mut last_state = None;
+        while steps < max_steps {
+            self.step()?;
+            steps += 1;
+            if self.call_stack.len() <= depth || self.hit_breakpoint() {
+                return Ok(());
+            }
+
+            match self.call_stack.last() {
+                Some(CallStackEntry {
+                    state: CallState::Builtin(state, _),
+                    ..
+                }) => {
+                    if Some(*state) == last_state {
+                        // the builtin is waiting for input, which would never arrive
+                        return Ok(());
+                    }
+                    last_state = Some(*state);
+                }
+                _ => last_state = None,
+            }
+        }
+        Ok(())
+    }
+
+    /// Step until the current function has returned to its caller, a breakpoint is hit or
+    /// max_steps steps were executed
+    /// Top level code has no caller, so nothing is executed and if the caller halts the
+    /// program (like Sys.init after Main.main), this returns the Halt error like step does
+    pub fn finish_function(&mut self, max_steps: Option<u64>) -> VMResult {
+        match self.call_stack.last() {
+            Some(CallStackEntry {
+                function: Some(_), ..
+            }) => {}
+            _ => return Ok(()),
+        }
+
+        let depth = self.call_stack.len();
+        let max_steps = max_steps.unwrap_or(u64::MAX);
+        let mut steps = 0;
+        while steps < max_steps {
+            self.step()?;
+            steps += 1;
+
+            if self.call_stack.len() < depth || self.hit_breakpoint() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Step the given number of times, but stop early when a breakpoint is reached
+    /// Returns true if the vm stopped at a breakpoint
+    pub fn step_times(&mut self, times: u32) -> VMResult<bool> {
+        for _ in 0..times {
+            self.step()?;
+            if self.hit_breakpoint() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Step until the function at the top of the call stack has the given name, the program halts
+    /// or a breakpoint is hit
+    /// Returns the number of executed steps
+    pub fn step_until_function(&mut self, name: &str) -> VMResult<u64> {
+        let exists =
+            self.meta.function_by_name.contains_key(name) || self.stdlib.lookup(name).is_some();
+        if !exists {
+            return Err(VMError::UnknownFunction(name.to_owned()));
+        }
+
+        let mut steps = 0;
+        while self.current_function_name() != Some(name) {
+            match self.step() {
+                Err(VMError::StdlibError(StdlibError::Halt)) => return Ok(steps + 1),
+                result => result?,
+            }
+            steps += 1;
+
+            if self.hit_breakpoint() {
+                break;
+            }
+        }
+        Ok(steps)
+    }
+
+    /// The number of steps since the program was loaded or reset_cycle_count was called
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles - self.cycles_at_reset
+    }
+
+    /// Start counting the cycles from 0 again, e.g. to measure a specific part of the program
+    /// Cycle breakpoints are relative to this, while recorded input keeps its original timing
+    pub fn reset_cycle_count(&mut self) {
+        self.cycles_at_reset = self.cycles;
+    }
+
+    /// The number of bytecode instructions executed since the program was loaded
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    /// The number of steps spent inside of builtin functions (e.g. Sys.wait) since the program
+    /// was loaded
+    pub fn builtin_steps(&self) -> u64 {
+        self.builtin_steps
+    }
+
+    /// Start counting the executed instructions and builtin steps from 0 again
+    pub fn reset_profiler(&mut self) {
+        self.instructions_executed = 0;
+        self.builtin_steps = 0;
+    }
+
+    /// Stop stepping once the cycle count reaches the given value
+    /// The breakpoint stays active, so it is hit again after the program is reloaded
+    pub fn add_cycle_breakpoint(&mut self, cycle: u64) {
+        self.add_breakpoint(BreakpointTarget::Cycle(cycle));
+    }
+
+    /// Adding an existing breakpoint again enables it
+    pub fn add_breakpoint(&mut self, target: BreakpointTarget) {
+        match self.breakpoints.iter_mut().find(|b| b.target == target) {
+            Some(breakpoint) => breakpoint.enabled = true,
+            None => self.breakpoints.push(BreakpointInfo::new(target)),
+        }
+    }
+
+    /// Returns false if there is no such breakpoint
+    pub fn set_breakpoint_enabled(&mut self, target: BreakpointTarget, enabled: bool) -> bool {
+        self.breakpoints
+            .iter_mut()
+            .find(|b| b.target == target)
+            .map(|b| b.enabled = enabled)
+            .is_some()
+    }
+
+    /// Returns false if there is no such breakpoint
+    pub fn remove_breakpoint(&mut self, target: BreakpointTarget) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.target != target);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> Vec<BreakpointInfo> {
+        self.breakpoints.clone()
+    }
+
+    /// Check if the vm is at an enabled breakpoint and count the hit
+    /// This should be called once after every step
+    /// The instruction at the breakpoint has not been executed yet, so stepping again resumes
+    /// the program from there
+    pub fn hit_breakpoint(&mut self) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+
+        let (pc, cycles) = (self.pc, self.cycle_count());
+        // the pc doesn't change while a builtin is running, so file offsets only match in the vm
+        let file_offset = if self.in_builtin() {
+            None
+        } else {
+            self.current_module_index().zip(self.current_file_offset())
+        };
+
+        let mut hit = false;
+        for breakpoint in self.breakpoints.iter_mut() {
+            if breakpoint.matches(pc, file_offset, cycles) {
+                breakpoint.hits += 1;
+                hit = true;
+            }
+        }
+        hit
+    }
+
+    /// The recoverable errors of builtin functions (e.g. printing a character without a glyph)
+    /// since the program was loaded or this was last called
+    pub fn take_stdlib_log(&mut self) -> Vec<StdlibError> {
+        std::mem::take(&mut self.stdlib_log)
+    }
+
     pub fn memory_at(&self, address: Address) -> Option<Word> {
         self.mem(address).ok()
     }
 
+    pub fn memory(&self) -> &[Word] {
+        &self.memory[..]
+    }
+
+    /// How often each kind of instruction occurs in the loaded program
+    /// The most common instructions come first
+    pub fn opcode_histogram(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<(&'static str, usize)> = Vec::new();
+        for instr in &self.program {
+            let mnemonic = instr.mnemonic();
+            match counts.iter_mut().find(|(name, _)| *name == mnemonic) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((mnemonic, 1)),
+            }
+        }
+
+        counts.sort_by(|(a_name, a_count), (b_name, b_count)| {
+            b_count.cmp(a_count).then(a_name.cmp(b_name))
+        });
+        counts
+    }
+
+    /// Whether the program is blocked in one of the builtin Keyboard.read* functions
+    /// While this is true, the program only continues once a key is pressed
+    pub fn is_waiting_for_input(&self) -> bool {
+        match self.call_stack.last() {
+            Some(CallStackEntry {
+                state: CallState::Builtin(_, _),
+                function: Some(function),
+                ..
+            }) => matches!(
+                self.stdlib.by_address(*function).map(|f| f.name()),
+                Some("Keyboard.readChar" | "Keyboard.readLine" | "Keyboard.readInt")
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether the vm is currently continuing a builtin function instead of executing bytecode
+    pub fn in_builtin(&self) -> bool {
+        matches!(
+            self.call_stack.last(),
+            Some(CallStackEntry {
+                state: CallState::Builtin(_, _),
+                ..
+            })
+        )
+    }
+
+    /// The name of the function at the top of the call stack
+    /// Builtin functions are resolved through the stdlib, if the program has no debug info for them
     pub fn current_function_name(&self) -> Option<&str> {
         let current_item = self.call_stack.last()?;
         let current_func = current_item.function?;
-        self.meta
+        let from_meta = self
+            .meta
             .function_meta
             .get(&current_func)
-            .map(|f| f.name.as_str())
+            .map(|f| f.name.as_str());
+
+        match current_item.state {
+            CallState::Builtin(_, _) => {
+                from_meta.or_else(|| self.stdlib.by_address(current_func).map(|f| f.name()))
+            }
+            _ => from_meta,
+        }
     }
 
     pub fn current_file_info(&self) -> Option<FileInfo> {
@@ -703,8 +1431,20 @@ impl VM {
         })
     }
 
+    /// The index of the file that contains the last VM function in the callstack
+    pub fn current_module_index(&self) -> Option<usize> {
+        match self.current_file_info()? {
+            FileInfo::VM { module_index, .. } => Some(module_index),
+            FileInfo::Builtin(_) => None,
+        }
+    }
+
     pub fn current_file_offset(&self) -> Option<usize> {
-        // find the last VM function in the callstack
+        Some(self.pc - self.current_file_start())
+    }
+
+    /// The bytecode offset of the file that contains the last VM function in the callstack
+    fn current_file_start(&self) -> usize {
         for call in self.call_stack.iter().rev() {
             if let CallStackEntry {
                 state: CallState::VM,
@@ -712,29 +1452,216 @@ impl VM {
                 ..
             } = call
             {
-                // get the bytecode offset of the file that contains this function
-                let file_start = self
+                return self
                     .meta
                     .function_meta
                     .get(function)
                     .and_then(|f| f.file.line_in_bytecode())
                     .unwrap_or_default();
-
-                return Some(self.pc - file_start);
             }
         }
 
-        Some(self.pc)
+        // there is no VM function, so this is almost certainly a single file program
+        0
+    }
+
+    /// The bytecode offset right after the file that contains the last VM function in the
+    /// callstack, which is either the start of the next file or the end of the program
+    fn current_file_end(&self) -> usize {
+        let file_start = self.current_file_start();
+        self.meta
+            .function_meta
+            .values()
+            .filter_map(|f| f.file.line_in_bytecode())
+            .filter(|&start| start > file_start)
+            .min()
+            .unwrap_or(self.program.len())
+    }
+
+    /// The line of the current instruction in its source file (starting at 1)
+    /// This is None inside of builtin functions and if the program has no line information
+    pub fn current_source_line(&self) -> Option<u32> {
+        if self.in_builtin() {
+            return None;
+        }
+        self.meta.source_lines.get(self.pc).copied()
+    }
+
+    /// The file and line of the source that the pc is on, None inside of builtins
+    fn current_source_position(&self) -> Option<(Option<usize>, u32)> {
+        let line = self.current_source_line()?;
+        Some((self.current_module_index(), line))
+    }
+
+    /// Step until the vm reaches an instruction on another source line (or a breakpoint)
+    /// Called builtin functions are run until they return (like step_until_vm_instr) and if no
+    /// other line is reached within max_steps steps, this stops wherever the vm is
+    /// Without line information, this is just a single step
+    pub fn step_line(&mut self, max_steps: Option<u64>) -> VMResult {
+        let (start_pc, start_position) = match self.current_source_position() {
+            Some(position) => (self.pc, position),
+            None => return self.step(),
+        };
+
+        let max_steps = max_steps.unwrap_or(u64::MAX);
+        let mut steps = 0;
+        let mut last_state = None;
+        while steps < max_steps {
+            self.step()?;
+            steps += 1;
+            if self.hit_breakpoint() {
+                return Ok(());
+            }
+
+            if let Some(CallStackEntry {
+                state: CallState::Builtin(state, _),
+                ..
+            }) = self.call_stack.last()
+            {
+                if Some(*state) == last_state {
+                    // the builtin is waiting for input, which would never arrive
+                    return Ok(());
+                }
+                last_state = Some(*state);
+                continue;
+            }
+            last_state = None;
+
+            // jumping back to the start of the line (e.g. an endless loop) also counts as a line
+            if self.pc == start_pc || self.current_source_position() != Some(start_position) {
+                return Ok(());
+            }
+        }
+        Ok(())
     }
 
     pub fn display(&self) -> &[Word] {
         &self.memory[SCREEN_START..=SCREEN_END]
     }
 
+    /// The screen as a plain PBM image, e.g. to compare it against a reference in tests
+    pub fn display_pbm(&self) -> String {
+        crate::simulators::display_pbm(self.display())
+    }
+
+    pub fn clear_screen(&mut self) {
+        self.memory[SCREEN_START..=SCREEN_END].fill(0);
+    }
+
+    /// Reset the cursor of the builtin Output class, the screen itself is not changed
+    pub fn clear_output(&mut self) -> VMResult {
+        Ok(stdlib::reset_output_cursor(self)?)
+    }
+
     fn mem_range(&self, range: std::ops::Range<Address>) -> Option<&[Word]> {
         self.memory.get(range)
     }
 
+    pub fn instruction_at(&self, pc: usize) -> Option<Instruction> {
+        self.program.get(pc).copied()
+    }
+
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        self.instruction_at(self.pc)
+    }
+
+    /// The instructions from around - context to around + context in the file of the current
+    /// function as (file_offset, disassembly, is_pc)
+    /// around is a file offset like the one returned by current_file_offset and the window is cut
+    /// off at the start and end of the file
+    pub fn disassembly_window(&self, around: usize, context: usize) -> Vec<(usize, String, bool)> {
+        let file_start = self.current_file_start();
+        let file_len = self.current_file_end() - file_start;
+        let start = around.saturating_sub(context);
+        let end = around
+            .saturating_add(context.saturating_add(1))
+            .min(file_len);
+
+        (start..end)
+            .filter_map(|offset| {
+                let address = file_start + offset;
+                let text = self.disassemble(address, file_start)?;
+                Some((offset, text, address == self.pc))
+            })
+            .collect()
+    }
+
+    /// The instruction at the given offset in the file of the current function as VM code
+    /// The offset is the one returned by current_file_offset and jump targets are shown relative
+    /// to the same file. Since the original index of a static is lost during parsing, statics
+    /// are shown with their resolved offset
+    /// Returns None if the offset is outside of the file
+    pub fn disassemble_at(&self, file_offset: usize) -> Option<String> {
+        let file_start = self.current_file_start();
+        let address = file_start.checked_add(file_offset)?;
+        if address >= self.current_file_end() {
+            return None;
+        }
+        self.disassemble(address, file_start)
+    }
+
+    /// The instruction at address as VM code, with jump targets relative to file_start
+    fn disassemble(&self, address: usize, file_start: usize) -> Option<String> {
+        let instruction = self.instruction_at(address)?;
+
+        let function_name = |function: Symbol| {
+            self.function_meta(function)
+                .map(|f| f.name.as_str())
+                .or_else(|| self.stdlib.by_address(function).map(|f| f.name()))
+        };
+
+        let text = match instruction {
+            Instruction::Goto { instruction } => {
+                format!("goto {}", (instruction as usize).saturating_sub(file_start))
+            }
+            Instruction::IfGoto { instruction } => {
+                format!(
+                    "if-goto {}",
+                    (instruction as usize).saturating_sub(file_start)
+                )
+            }
+            Instruction::Function { n_locals } => match function_name(address as Symbol) {
+                Some(name) => format!("function {} {}", name, n_locals),
+                None => instruction.to_string(),
+            },
+            Instruction::Call { function, n_args } => match function_name(function) {
+                Some(name) => format!("call {} {}", name, n_args),
+                None => instruction.to_string(),
+            },
+            _ => instruction.to_string(),
+        };
+
+        Some(text)
+    }
+
+    /// The disassembly of the whole program with the bytecode offset of every instruction and a
+    /// header comment before every function, e.g. for saving it as a file
+    /// Labels are not part of the debug info, so jumps only show the offset of their target
+    pub fn full_listing(&self) -> String {
+        let mut listing = String::new();
+        for address in 0..self.program.len() {
+            if let Some(function) = self.function_meta(address as Symbol) {
+                if !listing.is_empty() {
+                    listing.push('\n');
+                }
+                listing.push_str(&format!("// {}\n", function.name));
+            }
+
+            if let Some(text) = self.disassemble(address, 0) {
+                listing.push_str(&format!("{}: {}\n", address, text));
+            }
+        }
+        listing
+    }
+
+    /// Like mem_range, but addresses outside of the memory are returned as 0 instead of
+    /// failing, so that a memory viewer can always render a fixed size window
+    pub fn mem_range_padded(&self, range: std::ops::Range<Address>) -> Vec<Word> {
+        range
+            .map(|address| self.memory.get(address).copied().unwrap_or(0))
+            .collect()
+    }
+
     pub fn locals(&self) -> Option<&[Word]> {
         let entry = self.call_stack.last()?;
 
@@ -757,6 +1684,18 @@ impl VM {
         self.mem_range(bp..(bp + n_locals))
     }
 
+    /// The names of the locals of the current function from a "// @locals" annotation
+    /// The result is empty if the function has no annotation
+    pub fn local_names(&self) -> Option<&[String]> {
+        let entry = self.call_stack.last()?;
+        if entry.state != CallState::VM {
+            return None;
+        }
+
+        let function = self.meta.function_meta.get(&entry.function?)?;
+        Some(&function.local_names)
+    }
+
     pub fn args(&self) -> Option<&[Word]> {
         let entry = self.call_stack.last()?;
 
@@ -813,9 +1752,86 @@ impl VM {
         None
     }
 
+    /// The addresses of the static variables of a file (e.g. "Main.vm")
+    /// Files which don't use any statics don't have a range
+    pub fn statics_of(&self, file: &str) -> Option<Range<Address>> {
+        self.meta.static_ranges.get(file).cloned()
+    }
+
+    /// The number of arguments and locals of the current frame as (n_args, n_locals)
+    /// Builtin functions don't have locals, so their n_locals is always 0
+    pub fn current_frame_dims(&self) -> Option<(usize, usize)> {
+        let n_args = self.args()?.len();
+        let n_locals = self.locals().map(|l| l.len()).unwrap_or_default();
+        Some((n_args, n_locals))
+    }
+
+    /// The segments of the builtin Memory implementations free list
+    pub fn heap_segments(&self) -> Vec<heap::HeapSegment> {
+        heap::heap_segments(&self.memory[..], self.heap_end)
+    }
+
+    pub fn heap_stats(&self) -> heap::HeapStats {
+        heap::heap_stats(&self.memory[..], self.heap_end)
+    }
+
+    /// Releasing a key (key == 0) only clears KBD after the key release delay
     pub fn set_input_key(&mut self, key: i16) -> VMResult {
+        self.record_input(ReplayInput::Key(key));
+
+        if key == 0 && self.key_release_delay > 0 && self.mem(KBD)? != 0 {
+            self.pending_key_release = Some(self.key_release_delay);
+            return Ok(());
+        }
+
+        self.pending_key_release = None;
         self.set_mem(KBD, key)
     }
+
+    /// The value of the keyboard register, 0 if no key is pressed
+    pub fn current_key(&self) -> Word {
+        self.memory[KBD]
+    }
+
+    /// Keep a released key in KBD for the given number of steps
+    /// At high step rates a short key press could otherwise be missed by programs which poll
+    /// Keyboard.keyPressed
+    pub fn set_key_release_delay(&mut self, ticks: u32) {
+        self.key_release_delay = ticks;
+    }
+
+    /// Type a whole string followed by a newline
+    /// Only printable ascii characters have a key code, all other characters are skipped
+    pub fn set_input_string(&mut self, s: &str) {
+        self.record_input(ReplayInput::Text(s.to_owned()));
+
+        let keys = s
+            .chars()
+            .filter(|c| (' '..='~').contains(c))
+            .map(|c| c as Word)
+            .chain(std::iter::once(NEWLINE_KEY));
+        self.input_queue.extend(keys);
+    }
+
+    /// Release the current key (without the key release delay) and drop all queued input
+    pub fn clear_input(&mut self) {
+        self.record_input(ReplayInput::Clear);
+        self.input_queue.clear();
+        self.pending_key_release = None;
+        self.memory[KBD] = 0;
+    }
+
+    pub fn queued_input(&self) -> &VecDeque<Word> {
+        &self.input_queue
+    }
+
+    fn next_queued_key(&mut self) -> Option<Word> {
+        self.input_queue.pop_front()
+    }
+
+    fn has_queued_input(&self) -> bool {
+        !self.input_queue.is_empty()
+    }
 }
 
 impl Default for VM {
@@ -827,11 +1843,10 @@ impl Default for VM {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::definitions::HEAP_START;
-    use crate::definitions::KBD;
     use crate::definitions::SCREEN_START;
+    use crate::definitions::{BACKSPACE_KEY, KBD};
     use crate::parse::bytecode::{BytecodeParser, ParsedProgram, SourceFile};
-    use crate::simulators::vm::stdlib::{BuiltinFunction, StdResult};
+    use crate::simulators::vm::stdlib::BuiltinFunction;
     use std::collections::HashMap;
 
     #[test]
@@ -1103,333 +2118,1116 @@ mod tests {
     }
 
     #[test]
-    fn stack_test() {
-        let mut vm = VM::default();
-
-        let bytecode = r#"
-            push constant 17
-            push constant 17
-            eq
-            push constant 17
-            push constant 16
-            eq
-            push constant 16
-            push constant 17
-            eq
-            push constant 892
-            push constant 891
-            lt
-            push constant 891
-            push constant 892
-            lt
-            push constant 891
-            push constant 891
-            lt
-            push constant 32767
-            push constant 32766
-            gt
-            push constant 32766
-            push constant 32767
-            gt
-            push constant 32766
-            push constant 32766
-            gt
-            push constant 57
-            push constant 31
-            push constant 53
-            add
-            push constant 112
-            sub
-            neg
-            and
-            push constant 82
-            or
-            not"#;
-
-        let programs = vec![SourceFile::new("StackTest.vm", bytecode)];
-        let mut bytecode_parser = BytecodeParser::new(programs);
-        let program = bytecode_parser.parse().unwrap();
+    fn test_recoverable_stdlib_errors_are_logged() {
+        let mut vm = VM::new(Stdlib::new());
 
-        vm.load(program);
+        let src = r#"
+            function Main.main 0
+            push constant 200
+            call Output.printChar 1
+            pop temp 0
+            push constant 1
+            pop temp 1
+            label LOOP
+            goto LOOP
+            "#;
 
-        vm.set_mem(0, 256).unwrap();
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        vm.load(bytecode_parser.parse().unwrap());
 
-        for _ in 0..38 {
+        for _ in 0..100 {
             vm.step().unwrap();
         }
 
-        assert_eq!(Ok(266), vm.mem(0));
-        assert_eq!(Ok(-1), vm.mem(256));
-        assert_eq!(Ok(0), vm.mem(257));
-        assert_eq!(Ok(0), vm.mem(258));
-        assert_eq!(Ok(0), vm.mem(259));
-        assert_eq!(Ok(-1), vm.mem(260));
-        assert_eq!(Ok(0), vm.mem(261));
-        assert_eq!(Ok(-1), vm.mem(262));
-        assert_eq!(Ok(0), vm.mem(263));
-        assert_eq!(Ok(0), vm.mem(264));
-        assert_eq!(Ok(-91), vm.mem(265));
+        // the execution continued after printing the character
+        assert_eq!(Some(1), vm.memory_at(6));
+        assert_eq!(
+            vec![StdlibError::OutputCharWithoutGlyph(200)],
+            vm.take_stdlib_log()
+        );
+        assert!(vm.take_stdlib_log().is_empty());
     }
 
     #[test]
-    fn basic_loop() {
-        let mut vm = VM::default();
+    fn test_reset_restarts_the_program() {
+        let mut vm = VM::new(Stdlib::new());
 
-        let bytecode = r#"
-            // Computes the sum 1 + 2 + ... + argument[0] and pushes the
-            // result onto the stack. Argument[0] is initialized by the test
-            // script before this code starts running.
-            push constant 0
-            pop local 0         // initializes sum = 0
-            label LOOP_START
-            push argument 0
-            push local 0
-            add
-            pop local 0         // sum = sum + counter
-            push argument 0
-            push constant 1
-            sub
-            pop argument 0      // counter--
-            push argument 0
-            if-goto LOOP_START  // If counter != 0, goto LOOP_START
-            push local 0"#;
+        let src = r#"
+            function Main.main 0
+            push constant 10
+            pop static 0
+            label LOOP
+            goto LOOP
+            "#;
 
-        let programs = vec![SourceFile::new("BasicLoop.vm", bytecode)];
-        let mut bytecode_parser = BytecodeParser::new(programs);
-        let program = bytecode_parser.parse().unwrap();
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        vm.load(bytecode_parser.parse().unwrap());
+        let loaded = vm.memory().to_vec();
 
-        vm.load(program);
+        for _ in 0..100 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Some(10), vm.memory_at(16));
 
-        vm.set_mem(SP, 256).unwrap();
-        vm.set_mem(LCL, 300).unwrap();
-        vm.set_mem(ARG, 400).unwrap();
-        vm.set_mem_indirect(ARG, 0, 3).unwrap();
+        vm.reset();
+        assert_eq!(&loaded[..], vm.memory());
+        assert_eq!(0, vm.cycle_count());
+        assert_eq!(0, vm.call_stack_depth());
 
-        for _ in 0..33 {
+        // the builtin Sys.init is called again
+        for _ in 0..100 {
             vm.step().unwrap();
         }
-
-        assert_eq!(Ok(257), vm.mem(0));
-        assert_eq!(Ok(6), vm.mem(256));
+        assert_eq!(Some(10), vm.memory_at(16));
+        assert_eq!(vec!["Sys.init", "Main.main"], vm.call_stack_names());
     }
 
     #[test]
-    fn fibonacci_series_with_stdlib_loaded() {
-        let mut vm = VM::new(Stdlib::new());
-
-        let bytecode = r#"
-            // Puts the first argument[0] elements of the Fibonacci series
-            // in the memory, starting in the address given in argument[1].
-            // Argument[0] and argument[1] are initialized by the test script
-            // before this code starts running.
-
-            push argument 1
-            pop pointer 1           // that = argument[1]
-
+    fn test_entry_point() {
+        let main = r#"
+            function Main.main 0
             push constant 0
-            pop that 0              // first element in the series = 0
+            return"#;
+        let helper = r#"
+            function Helper.run 0
             push constant 1
-            pop that 1              // second element in the series = 1
+            return"#;
 
-            push argument 0
-            push constant 2
-            sub
-            pop argument 0          // num_of_elements -= 2 (first 2 elements are set)
+        let mut vm = VM::new(Stdlib::new());
 
-            label MAIN_LOOP_START
+        // no Sys.init, the program starts at the first instruction
+        let programs = vec![SourceFile::new("Helper.vm", helper)];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+        assert_eq!(0, vm.entry_point());
 
-            push argument 0
-            if-goto COMPUTE_ELEMENT // if num_of_elements > 0, goto COMPUTE_ELEMENT
-            goto END_PROGRAM        // otherwise, goto END_PROGRAM
+        // the builtin Sys.init calls Main.main
+        let programs = vec![
+            SourceFile::new("Helper.vm", helper),
+            SourceFile::new("Main.vm", main),
+        ];
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(3, vm.entry_point());
 
-            label COMPUTE_ELEMENT
+        vm.set_entry_function("Helper.run").unwrap();
+        assert_eq!(0, vm.entry_point());
 
-            push that 0
-            push that 1
-            add
-            pop that 2              // that[2] = that[0] + that[1]
-
-            push pointer 1
-            push constant 1
-            add
-            pop pointer 1           // that += 1
-
-            push argument 0
-            push constant 1
-            sub
-            pop argument 0          // num_of_elements--
-
-            goto MAIN_LOOP_START
-
-            label END_PROGRAM"#;
+        // a bytecode Sys.init
+        let sys = r#"
+            function Sys.init 0
+            call Main.main 0
+            label LOOP
+            goto LOOP"#;
+        let programs = vec![
+            SourceFile::new("Main.vm", main),
+            SourceFile::new("Sys.vm", sys),
+        ];
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(3, vm.entry_point());
 
-        let programs = vec![SourceFile::new("FibonacciSeries.vm", bytecode)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
-        let program = bytecode_parser.parse().unwrap();
+        vm.step().unwrap();
+        assert_eq!(
+            Some(Instruction::Function { n_locals: 0 }),
+            vm.current_instruction()
+        );
+        assert_eq!(3, vm.pc);
+    }
 
-        vm.load(program);
+    #[test]
+    fn test_call_stack_depth() {
+        let bytecode = r#"
+            function Sys.init 0
+            call Main.main 0
+            label LOOP
+            goto LOOP
+            function Main.main 0
+            call Main.inner 0
+            return
+            function Main.inner 0
+            push constant 0
+            return"#;
 
-        vm.set_mem(SP, 256).unwrap();
-        vm.set_mem(LCL, 300).unwrap();
-        vm.set_mem(ARG, 400).unwrap();
-        vm.set_mem_indirect(ARG, 0, 6).unwrap();
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::default();
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
 
-        vm.set_mem_indirect(ARG, 1, 3000).unwrap();
-        for _ in 0..73 {
+        // function Sys.init, call Main.main, function, call Main.inner, function, push, return,
+        // return
+        let expected = [0, 1, 1, 2, 2, 2, 1, 0];
+        for depth in expected {
             vm.step().unwrap();
+            assert_eq!(depth, vm.call_stack_depth());
+            assert_eq!(vm.call_stack_names().len(), vm.call_stack_depth());
         }
-
-        assert_eq!(Ok(0), vm.mem(3000));
-        assert_eq!(Ok(1), vm.mem(3001));
-        assert_eq!(Ok(1), vm.mem(3002));
-        assert_eq!(Ok(2), vm.mem(3003));
-        assert_eq!(Ok(3), vm.mem(3004));
-        assert_eq!(Ok(5), vm.mem(3005));
     }
 
     #[test]
-    fn fibonacci_series() {
-        let mut vm = VM::default();
-
+    fn test_return_address_chain() {
         let bytecode = r#"
-            // Puts the first argument[0] elements of the Fibonacci series
-            // in the memory, starting in the address given in argument[1].
-            // Argument[0] and argument[1] are initialized by the test script
-            // before this code starts running.
+            function Sys.init 0
+            call Main.main 0
+            label LOOP
+            goto LOOP
+            function Main.main 0
+            call Main.inner 0
+            return
+            function Main.inner 0
+            push constant 0
+            return"#;
 
-            push argument 1
-            pop pointer 1           // that = argument[1]
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::default();
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
 
-            push constant 0
-            pop that 0              // first element in the series = 0
-            push constant 1
-            pop that 1              // second element in the series = 1
+        // function Sys.init, call Main.main, function, call Main.inner, function
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        // the instructions after both calls
+        assert_eq!(vec![2, 5], vm.return_address_chain());
 
-            push argument 0
-            push constant 2
-            sub
-            pop argument 0          // num_of_elements -= 2 (first 2 elements are set)
+        // push, return
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vec![2], vm.return_address_chain());
 
-            label MAIN_LOOP_START
+        // corrupting the saved address is visible in the chain
+        let lcl = vm.memory_at(LCL).unwrap() as Address;
+        vm.set_mem(lcl - 5, 42).unwrap();
+        assert_eq!(vec![42], vm.return_address_chain());
 
-            push argument 0
-            if-goto COMPUTE_ELEMENT // if num_of_elements > 0, goto COMPUTE_ELEMENT
-            goto END_PROGRAM        // otherwise, goto END_PROGRAM
+        // addresses above 32767 are negative words, but still unsigned addresses
+        vm.set_mem(lcl - 5, -1).unwrap();
+        assert_eq!(vec![65535], vm.return_address_chain());
+    }
 
-            label COMPUTE_ELEMENT
+    #[test]
+    fn test_disassemble_at() {
+        let sys = r#"
+            function Sys.init 0
+            call Main.main 0
+            label LOOP
+            goto LOOP"#;
 
-            push that 0
-            push that 1
-            add
-            pop that 2              // that[2] = that[0] + that[1]
+        let main = r#"
+            function Main.main 0
+            push static 1
+            push constant 5
+            call Math.multiply 2
+            label END
+            if-goto END
+            return"#;
 
-            push pointer 1
-            push constant 1
-            add
-            pop pointer 1           // that += 1
+        let programs = vec![
+            SourceFile::new("Sys.vm", sys),
+            SourceFile::new("Main.vm", main),
+        ];
+        let mut vm = VM::new(Stdlib::new());
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
 
-            push argument 0
-            push constant 1
-            sub
-            pop argument 0          // num_of_elements--
+        assert_eq!(Some("function Sys.init 0".to_owned()), vm.disassemble_at(0));
+        assert_eq!(Some("call Main.main 0".to_owned()), vm.disassemble_at(1));
+        assert_eq!(Some("goto 2".to_owned()), vm.disassemble_at(2));
+        // the first instruction of Main.vm is not part of Sys.vm
+        assert_eq!(None, vm.disassemble_at(3));
+        assert_eq!(
+            vec![
+                (1, "call Main.main 0".to_owned(), false),
+                (2, "goto 2".to_owned(), false),
+            ],
+            vm.disassembly_window(2, 1)
+        );
 
-            goto MAIN_LOOP_START
+        // function Sys.init, call Main.main
+        vm.step().unwrap();
+        vm.step().unwrap();
 
-            label END_PROGRAM"#;
+        // the offsets are now relative to Main.vm
+        assert_eq!(
+            Some("function Main.main 0".to_owned()),
+            vm.disassemble_at(0)
+        );
+        // static 1 is the only static of Main.vm, so it gets the first static address
+        assert_eq!(Some("push static 16".to_owned()), vm.disassemble_at(1));
+        assert_eq!(Some("push constant 5".to_owned()), vm.disassemble_at(2));
+        assert_eq!(
+            Some("call Math.multiply 2".to_owned()),
+            vm.disassemble_at(3)
+        );
+        assert_eq!(Some("if-goto 4".to_owned()), vm.disassemble_at(4));
+        assert_eq!(Some("return".to_owned()), vm.disassemble_at(5));
+        assert_eq!(None, vm.disassemble_at(6));
+    }
 
-        let programs = vec![SourceFile::new("FibonacciSeries.vm", bytecode)];
-        let mut bytecode_parser = BytecodeParser::new(programs);
-        let program = bytecode_parser.parse().unwrap();
+    #[test]
+    fn test_call_indirect() {
+        let bytecode = r#"
+            function Sys.init 0
+            push constant 3
+            push constant 4
+            push constant 6
+            call-indirect 2
+            label LOOP
+            goto LOOP
+            function Main.add 0
+            push argument 0
+            push argument 1
+            add
+            return
+            function Main.broken 0
+            push constant 1
+            call-indirect 0
+            return"#;
 
-        vm.load(program);
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut parser = BytecodeParser::new(programs);
+        parser.set_indirect_calls(true);
+        let mut vm = VM::default();
+        vm.load(parser.parse().unwrap());
 
-        vm.set_mem(SP, 256).unwrap();
-        vm.set_mem(LCL, 300).unwrap();
-        vm.set_mem(ARG, 400).unwrap();
-        vm.set_mem_indirect(ARG, 0, 6).unwrap();
+        // function, 3 pushes, call-indirect
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Some("Main.add"), vm.current_function_name());
 
-        vm.set_mem_indirect(ARG, 1, 3000).unwrap();
-        for _ in 0..73 {
+        // function, push, push, add, return
+        for _ in 0..5 {
             vm.step().unwrap();
         }
+        assert_eq!(Some(7), vm.last_return_value());
+        assert_eq!(5, vm.pc);
 
-        assert_eq!(Ok(0), vm.mem(3000));
-        assert_eq!(Ok(1), vm.mem(3001));
-        assert_eq!(Ok(1), vm.mem(3002));
-        assert_eq!(Ok(2), vm.mem(3003));
-        assert_eq!(Ok(3), vm.mem(3004));
-        assert_eq!(Ok(5), vm.mem(3005));
+        // calling something that is not a function is an error
+        vm.reset();
+        vm.set_entry_function("Main.broken").unwrap();
+        // the call of the entry function, function, push
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Err(VMError::IllegalCallTarget(1)), vm.step());
     }
 
     #[test]
-    fn fibonacci_element() {
-        let mut vm = VM::default();
-
-        let main = r#"
-            // Computes the n'th element of the Fibonacci series, recursively.
-            // n is given in argument[0].  Called by the Sys.init function
-            // (part of the Sys.vm file), which also pushes the argument[0]
-            // parameter before this code starts running.
-
-            function Main.fibonacci 0
-            push argument 0
-            push constant 2
-            lt                     // checks if n<2
-            if-goto IF_TRUE
-            goto IF_FALSE
-            label IF_TRUE          // if n<2, return n
+    fn test_step_over() {
+        let bytecode = r#"
+            function Sys.init 0
+            push constant 3
+            call Main.count 1
+            pop temp 0
+            push constant 1
+            call String.new 1
+            push constant 72
+            call String.appendChar 2
+            call Output.printString 1
+            label LOOP
+            goto LOOP
+            function Main.count 0
             push argument 0
+            if-goto REC
+            push constant 0
             return
-            label IF_FALSE         // if n>=2, returns fib(n-2)+fib(n-1)
-            push argument 0
-            push constant 2
-            sub
-            call Main.fibonacci 1  // computes fib(n-2)
+            label REC
             push argument 0
             push constant 1
             sub
-            call Main.fibonacci 1  // computes fib(n-1)
-            add                    // returns fib(n-1) + fib(n-2)
+            call Main.count 1
             return"#;
 
-        let sys = r#"
-            // Pushes a constant, say n, onto the stack, and calls the Main.fibonacii
-            // function, which computes the n'th element of the Fibonacci series.
-            // Note that by convention, the Sys.init function is called "automatically"
-            // by the bootstrap code.
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::new(Stdlib::new());
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
 
-            function Sys.init 0
-            push constant 4
-            call Main.fibonacci 1   // computes the 4'th fibonacci element
-            label WHILE
-            goto WHILE              // loops infinitely"#;
+        // function, push
+        vm.step_over(None).unwrap();
+        vm.step_over(None).unwrap();
+        assert_eq!(2, vm.pc);
+        let depth = vm.call_stack.len();
+
+        // the recursive calls to the same function don't stop the step over
+        vm.step_over(None).unwrap();
+        assert_eq!(3, vm.pc);
+        assert_eq!(depth, vm.call_stack.len());
+        assert_eq!(Some(0), vm.last_return_value());
+
+        // pop, push, String.new, push, String.appendChar
+        for _ in 0..5 {
+            vm.step_over(None).unwrap();
+        }
+        assert_eq!(8, vm.pc);
 
-        let programs = vec![
-            SourceFile::new("Sys.vm", sys),
-            SourceFile::new("Main.vm", main),
-        ];
-        let mut bytecode_parser = BytecodeParser::new(programs);
-        let program = bytecode_parser.parse().unwrap();
+        // builtins that take multiple steps are finished completely
+        vm.step_over(None).unwrap();
+        assert_eq!(9, vm.pc);
+        assert!(!vm.in_builtin());
+        assert_eq!(depth, vm.call_stack.len());
+    }
 
-        vm.load(program);
+    #[test]
+    fn test_step_over_call_that_never_returns() {
+        let bytecode = r#"
+            function Main.main 0
+            call Main.spin 0
+            return
+            function Main.spin 0
+            label LOOP
+            goto LOOP"#;
 
-        vm.set_mem(SP, 261).unwrap();
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::new(Stdlib::new());
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+        vm.step_until_function("Main.main").unwrap();
+        vm.step().unwrap();
 
-        for _ in 0..110 {
-            vm.step().unwrap();
-        }
+        let cycles = vm.cycle_count();
+        vm.step_over(Some(100)).unwrap();
+        assert_eq!(cycles + 100, vm.cycle_count());
+        assert_eq!(Some("Main.spin"), vm.current_function_name());
+    }
 
-        assert_eq!(Ok(262), vm.mem(0));
-        assert_eq!(Ok(3), vm.mem(261));
+    #[test]
+    fn test_finish_function() {
+        let bytecode = r#"
+            function Main.main 0
+            push constant 2
+            call Main.inner 1
+            pop temp 0
+            push constant 0
+            return
+            function Main.inner 0
+            push argument 0
+            push argument 0
+            add
+            return
+            function Main.start 0
+            call Main.spin 0
+            return
+            function Main.spin 0
+            label LOOP
+            goto LOOP"#;
+
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::new(Stdlib::new());
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+
+        vm.step_until_function("Main.inner").unwrap();
+        vm.finish_function(None).unwrap();
+        assert_eq!(Some("Main.main"), vm.current_function_name());
+        assert_eq!(3, vm.pc);
+        assert_eq!(Some(4), vm.last_return_value());
+
+        // back in the builtin Sys.init, which halts the program when finishing it
+        vm.finish_function(None).unwrap();
+        assert_eq!(Some("Sys.init"), vm.current_function_name());
+        assert_eq!(
+            Err(VMError::StdlibError(StdlibError::Halt)),
+            vm.finish_function(None)
+        );
+
+        // the budget stops endless loops
+        vm.reset();
+        vm.set_entry_function("Main.start").unwrap();
+        vm.step_until_function("Main.spin").unwrap();
+        let cycles = vm.cycle_count();
+        vm.finish_function(Some(100)).unwrap();
+        assert_eq!(cycles + 100, vm.cycle_count());
+        assert_eq!(Some("Main.spin"), vm.current_function_name());
     }
 
     #[test]
-    fn nested_call() {
+    fn test_finish_function_in_top_level_code() {
+        let programs = vec![SourceFile::new("Main.vm", "label LOOP\ngoto LOOP")];
         let mut vm = VM::default();
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
 
-        for i in 261..=299 {
-            vm.set_mem(i, -1).unwrap();
-        }
+        vm.finish_function(None).unwrap();
+        assert_eq!(0, vm.cycle_count());
+    }
+
+    #[test]
+    fn test_builtin_string_calls_without_memory_init_keep_the_stack_intact() {
+        // Sys.init in bytecode means that the builtin Memory.init is never called
+        let bytecode = r#"
+            function Sys.init 0
+            push constant 2
+            call String.new 1
+            push constant 72
+            call String.appendChar 2
+            push constant 73
+            call String.appendChar 2
+            call Output.printString 1
+            pop temp 0
+            label LOOP
+            goto LOOP"#;
+
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::new(Stdlib::new());
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+
+        for _ in 0..1000 {
+            vm.step().unwrap();
+        }
+
+        // the string was allocated on the heap instead of on top of SP
+        assert_eq!(Some(INIT_SP), vm.memory_at(SP));
+        assert_eq!(Some(2), vm.memory_at(HEAP_START + 2));
+        assert_eq!(Some(2), vm.memory_at(HEAP_START + 3));
+    }
+
+    #[test]
+    fn test_endless_recursion_is_a_stack_overflow() {
+        let bytecode = r#"
+            function Main.main 0
+            call Main.recurse 0
+            return
+            function Main.recurse 1
+            call Main.recurse 0
+            return"#;
+
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::default();
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+
+        // every call needs 6 words (5 for the frame and 1 local), so this has to fail long before
+        let error = (0..10_000).find_map(|_| vm.step().err());
+        assert_eq!(Some(VMError::StackOverflow(HEAP_START)), error);
+        // the heap is untouched
+        assert_eq!(Some(0), vm.memory_at(HEAP_START));
+    }
+
+    #[test]
+    fn test_local_names_from_annotation() {
+        let bytecode = r#"
+            function Sys.init 0
+            call Main.main 0
+            call Main.other 0
+            label LOOP
+            goto LOOP
+            function Main.main 2 // @locals sum count
+            push constant 0
+            return
+            function Main.other 1
+            // just a comment
+            push constant 0
+            return"#;
+
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::default();
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+        assert_eq!(None, vm.local_names());
+
+        // function Sys.init, call Main.main
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(
+            Some(&["sum".to_owned(), "count".to_owned()][..]),
+            vm.local_names()
+        );
+
+        // function, push, return, call Main.other
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Some("Main.other"), vm.current_function_name());
+        assert_eq!(Some(&[][..]), vm.local_names());
+    }
+
+    #[test]
+    fn test_step_line() {
+        let bytecode = "// adds two numbers
+            push constant 1 push constant 2
+
+            add
+            label END
+            goto END";
+
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        let mut vm = VM::default();
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+        assert_eq!(vec![2, 2, 4, 6], vm.meta.source_lines);
+
+        assert_eq!(Some(2), vm.current_source_line());
+        vm.step_line(None).unwrap();
+        assert_eq!(Some(4), vm.current_source_line());
+        assert_eq!(2, vm.cycle_count());
+
+        vm.step_line(None).unwrap();
+        assert_eq!(Some(6), vm.current_source_line());
+        assert_eq!(Some(3), vm.memory_at(256));
+
+        // the endless loop never leaves the line
+        vm.step_line(None).unwrap();
+        assert_eq!(Some(6), vm.current_source_line());
+        assert_eq!(4, vm.cycle_count());
+
+        // without line information every instruction is its own line
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+        vm.meta.source_lines.clear();
+        vm.step_line(None).unwrap();
+        assert_eq!(None, vm.current_source_line());
+        assert_eq!(1, vm.cycle_count());
+    }
+
+    #[test]
+    fn test_step_line_across_files_and_builtins() {
+        let main = "function Main.main 0
+            call Other.f 0
+            push constant 2 push constant 3 call Math.multiply 2 pop temp 0
+            label END
+            goto END";
+        // the function starts on the same line number as the call in Main.vm
+        let other = "
+            function Other.f 0
+            push constant 0
+            return";
+
+        let programs = vec![
+            SourceFile::new("Main.vm", main),
+            SourceFile::new("Other.vm", other),
+        ];
+        let mut vm = VM::new(Stdlib::new());
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+        vm.set_entry_function("Main.main").unwrap();
+        // jump to Main.main, function
+        vm.step().unwrap();
+        vm.step_line(None).unwrap();
+        assert_eq!(Some(2), vm.current_source_line());
+
+        // the call into another file stops at the line in that file
+        vm.step_line(None).unwrap();
+        assert_eq!(Some("Other.f"), vm.current_function_name());
+        assert_eq!(Some(1), vm.current_module_index());
+        assert_eq!(Some(2), vm.current_source_line());
+
+        // push, return, pop
+        vm.step_line(None).unwrap();
+        vm.step_line(None).unwrap();
+        vm.step_line(None).unwrap();
+        assert_eq!(Some(0), vm.current_module_index());
+        assert_eq!(Some(3), vm.current_source_line());
+
+        // Math.multiply is run until it returns, so the whole line is executed
+        vm.step_line(None).unwrap();
+        assert_eq!(Some(5), vm.current_source_line());
+        assert_eq!(Some(6), vm.memory_at(5));
+
+        // the budget stops the step even if the line isn't left
+        let cycles = vm.cycle_count();
+        vm.step_line(Some(0)).unwrap();
+        assert_eq!(cycles, vm.cycle_count());
+    }
+
+    #[test]
+    fn test_opcode_histogram() {
+        let mut vm = VM::default();
+        assert!(vm.opcode_histogram().is_empty());
+
+        let bytecode = r#"
+            push constant 7
+            push constant 8
+            add
+            pop temp 0"#;
+
+        let programs = vec![SourceFile::new("SimpleAdd.vm", bytecode)];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+
+        assert_eq!(
+            vec![("push", 2), ("add", 1), ("pop", 1)],
+            vm.opcode_histogram()
+        );
+    }
+
+    #[test]
+    fn stack_test() {
+        let mut vm = VM::default();
+
+        let bytecode = r#"
+            push constant 17
+            push constant 17
+            eq
+            push constant 17
+            push constant 16
+            eq
+            push constant 16
+            push constant 17
+            eq
+            push constant 892
+            push constant 891
+            lt
+            push constant 891
+            push constant 892
+            lt
+            push constant 891
+            push constant 891
+            lt
+            push constant 32767
+            push constant 32766
+            gt
+            push constant 32766
+            push constant 32767
+            gt
+            push constant 32766
+            push constant 32766
+            gt
+            push constant 57
+            push constant 31
+            push constant 53
+            add
+            push constant 112
+            sub
+            neg
+            and
+            push constant 82
+            or
+            not"#;
+
+        let programs = vec![SourceFile::new("StackTest.vm", bytecode)];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(0, 256).unwrap();
+
+        for _ in 0..38 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(266), vm.mem(0));
+        assert_eq!(Ok(-1), vm.mem(256));
+        assert_eq!(Ok(0), vm.mem(257));
+        assert_eq!(Ok(0), vm.mem(258));
+        assert_eq!(Ok(0), vm.mem(259));
+        assert_eq!(Ok(-1), vm.mem(260));
+        assert_eq!(Ok(0), vm.mem(261));
+        assert_eq!(Ok(-1), vm.mem(262));
+        assert_eq!(Ok(0), vm.mem(263));
+        assert_eq!(Ok(0), vm.mem(264));
+        assert_eq!(Ok(-91), vm.mem(265));
+    }
+
+    #[test]
+    fn not_and_neg_of_comparisons() {
+        let mut vm = VM::default();
+
+        // true is -1 and false is 0, so not keeps the boolean representation intact,
+        // while neg turns true into 1 which isn't a valid boolean anymore
+        let bytecode = r#"
+            push constant 1
+            push constant 2
+            lt
+            not
+            push constant 2
+            push constant 1
+            lt
+            not
+            push constant 3
+            push constant 3
+            eq
+            neg
+            push constant 3
+            push constant 4
+            eq
+            neg
+            push constant 1
+            push constant 2
+            lt
+            not
+            not
+            push constant 5
+            push constant 5
+            eq
+            neg
+            not
+            push constant 1
+            push constant 2
+            lt
+            push constant 2
+            push constant 1
+            lt
+            not
+            and"#;
+
+        let programs = vec![SourceFile::new("NotNeg.vm", bytecode)];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(0, 256).unwrap();
+
+        for _ in 0..34 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(263), vm.mem(0));
+        assert_eq!(Ok(0), vm.mem(256));
+        assert_eq!(Ok(-1), vm.mem(257));
+        assert_eq!(Ok(1), vm.mem(258));
+        assert_eq!(Ok(0), vm.mem(259));
+        assert_eq!(Ok(-1), vm.mem(260));
+        assert_eq!(Ok(-2), vm.mem(261));
+        assert_eq!(Ok(-1), vm.mem(262));
+    }
+
+    #[test]
+    fn basic_loop() {
+        let mut vm = VM::default();
+
+        let bytecode = r#"
+            // Computes the sum 1 + 2 + ... + argument[0] and pushes the
+            // result onto the stack. Argument[0] is initialized by the test
+            // script before this code starts running.
+            push constant 0
+            pop local 0         // initializes sum = 0
+            label LOOP_START
+            push argument 0
+            push local 0
+            add
+            pop local 0         // sum = sum + counter
+            push argument 0
+            push constant 1
+            sub
+            pop argument 0      // counter--
+            push argument 0
+            if-goto LOOP_START  // If counter != 0, goto LOOP_START
+            push local 0"#;
+
+        let programs = vec![SourceFile::new("BasicLoop.vm", bytecode)];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(SP, 256).unwrap();
+        vm.set_mem(LCL, 300).unwrap();
+        vm.set_mem(ARG, 400).unwrap();
+        vm.set_mem_indirect(ARG, 0, 3).unwrap();
+
+        for _ in 0..33 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(257), vm.mem(0));
+        assert_eq!(Ok(6), vm.mem(256));
+    }
+
+    #[test]
+    fn fibonacci_series_with_stdlib_loaded() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let bytecode = r#"
+            // Puts the first argument[0] elements of the Fibonacci series
+            // in the memory, starting in the address given in argument[1].
+            // Argument[0] and argument[1] are initialized by the test script
+            // before this code starts running.
+
+            push argument 1
+            pop pointer 1           // that = argument[1]
+
+            push constant 0
+            pop that 0              // first element in the series = 0
+            push constant 1
+            pop that 1              // second element in the series = 1
+
+            push argument 0
+            push constant 2
+            sub
+            pop argument 0          // num_of_elements -= 2 (first 2 elements are set)
+
+            label MAIN_LOOP_START
+
+            push argument 0
+            if-goto COMPUTE_ELEMENT // if num_of_elements > 0, goto COMPUTE_ELEMENT
+            goto END_PROGRAM        // otherwise, goto END_PROGRAM
+
+            label COMPUTE_ELEMENT
+
+            push that 0
+            push that 1
+            add
+            pop that 2              // that[2] = that[0] + that[1]
+
+            push pointer 1
+            push constant 1
+            add
+            pop pointer 1           // that += 1
+
+            push argument 0
+            push constant 1
+            sub
+            pop argument 0          // num_of_elements--
+
+            goto MAIN_LOOP_START
+
+            label END_PROGRAM"#;
+
+        let programs = vec![SourceFile::new("FibonacciSeries.vm", bytecode)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(SP, 256).unwrap();
+        vm.set_mem(LCL, 300).unwrap();
+        vm.set_mem(ARG, 400).unwrap();
+        vm.set_mem_indirect(ARG, 0, 6).unwrap();
+
+        vm.set_mem_indirect(ARG, 1, 3000).unwrap();
+        for _ in 0..73 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(0), vm.mem(3000));
+        assert_eq!(Ok(1), vm.mem(3001));
+        assert_eq!(Ok(1), vm.mem(3002));
+        assert_eq!(Ok(2), vm.mem(3003));
+        assert_eq!(Ok(3), vm.mem(3004));
+        assert_eq!(Ok(5), vm.mem(3005));
+    }
+
+    #[test]
+    fn fibonacci_series() {
+        let mut vm = VM::default();
+
+        let bytecode = r#"
+            // Puts the first argument[0] elements of the Fibonacci series
+            // in the memory, starting in the address given in argument[1].
+            // Argument[0] and argument[1] are initialized by the test script
+            // before this code starts running.
+
+            push argument 1
+            pop pointer 1           // that = argument[1]
+
+            push constant 0
+            pop that 0              // first element in the series = 0
+            push constant 1
+            pop that 1              // second element in the series = 1
+
+            push argument 0
+            push constant 2
+            sub
+            pop argument 0          // num_of_elements -= 2 (first 2 elements are set)
+
+            label MAIN_LOOP_START
+
+            push argument 0
+            if-goto COMPUTE_ELEMENT // if num_of_elements > 0, goto COMPUTE_ELEMENT
+            goto END_PROGRAM        // otherwise, goto END_PROGRAM
+
+            label COMPUTE_ELEMENT
+
+            push that 0
+            push that 1
+            add
+            pop that 2              // that[2] = that[0] + that[1]
+
+            push pointer 1
+            push constant 1
+            add
+            pop pointer 1           // that += 1
+
+            push argument 0
+            push constant 1
+            sub
+            pop argument 0          // num_of_elements--
+
+            goto MAIN_LOOP_START
+
+            label END_PROGRAM"#;
+
+        let programs = vec![SourceFile::new("FibonacciSeries.vm", bytecode)];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(SP, 256).unwrap();
+        vm.set_mem(LCL, 300).unwrap();
+        vm.set_mem(ARG, 400).unwrap();
+        vm.set_mem_indirect(ARG, 0, 6).unwrap();
+
+        vm.set_mem_indirect(ARG, 1, 3000).unwrap();
+        for _ in 0..73 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(0), vm.mem(3000));
+        assert_eq!(Ok(1), vm.mem(3001));
+        assert_eq!(Ok(1), vm.mem(3002));
+        assert_eq!(Ok(2), vm.mem(3003));
+        assert_eq!(Ok(3), vm.mem(3004));
+        assert_eq!(Ok(5), vm.mem(3005));
+    }
+
+    #[test]
+    fn fibonacci_element() {
+        let mut vm = VM::default();
+
+        let main = r#"
+            // Computes the n'th element of the Fibonacci series, recursively.
+            // n is given in argument[0].  Called by the Sys.init function
+            // (part of the Sys.vm file), which also pushes the argument[0]
+            // parameter before this code starts running.
+
+            function Main.fibonacci 0
+            push argument 0
+            push constant 2
+            lt                     // checks if n<2
+            if-goto IF_TRUE
+            goto IF_FALSE
+            label IF_TRUE          // if n<2, return n
+            push argument 0
+            return
+            label IF_FALSE         // if n>=2, returns fib(n-2)+fib(n-1)
+            push argument 0
+            push constant 2
+            sub
+            call Main.fibonacci 1  // computes fib(n-2)
+            push argument 0
+            push constant 1
+            sub
+            call Main.fibonacci 1  // computes fib(n-1)
+            add                    // returns fib(n-1) + fib(n-2)
+            return"#;
+
+        let sys = r#"
+            // Pushes a constant, say n, onto the stack, and calls the Main.fibonacii
+            // function, which computes the n'th element of the Fibonacci series.
+            // Note that by convention, the Sys.init function is called "automatically"
+            // by the bootstrap code.
+
+            function Sys.init 0
+            push constant 4
+            call Main.fibonacci 1   // computes the 4'th fibonacci element
+            label WHILE
+            goto WHILE              // loops infinitely"#;
+
+        let programs = vec![
+            SourceFile::new("Sys.vm", sys),
+            SourceFile::new("Main.vm", main),
+        ];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(SP, 261).unwrap();
+
+        for _ in 0..110 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(262), vm.mem(0));
+        assert_eq!(Ok(3), vm.mem(261));
+    }
+
+    const NESTED_CALL: &str = r#"
+            // Sys.vm for NestedCall test.
+
+            // Sys.init()
+            //
+            // Calls Sys.main() and stores return value in temp 1.
+            // Does not return.  (Enters infinite loop.)
+
+            function Sys.init 0
+            push constant 4000	// test THIS and THAT context save
+            pop pointer 0
+            push constant 5000
+            pop pointer 1
+            call Sys.main 0
+            pop temp 1
+            label LOOP
+            goto LOOP
+
+            // Sys.main()
+            //
+            // Sets locals 1, 2 and 3, leaving locals 0 and 4 unchanged to test
+            // default local initialization to 0.  (RAM set to -1 by test setup.)
+            // Calls Sys.add12(123) and stores return value (135) in temp 0.
+            // Returns local 0 + local 1 + local 2 + local 3 + local 4 (456) to confirm
+            // that locals were not mangled by function call.
+
+            function Sys.main 5
+            push constant 4001
+            pop pointer 0
+            push constant 5001
+            pop pointer 1
+            push constant 200
+            pop local 1
+            push constant 40
+            pop local 2
+            push constant 6
+            pop local 3
+            push constant 123
+            call Sys.add12 1
+            pop temp 0
+            push local 0
+            push local 1
+            push local 2
+            push local 3
+            push local 4
+            add
+            add
+            add
+            add
+            return
+
+            // Sys.add12(int n)
+            //
+            // Returns n+12.
+
+            function Sys.add12 0
+            push constant 4002
+            pop pointer 0
+            push constant 5002
+            pop pointer 1
+            push argument 0
+            push constant 12
+            add
+            return"#;
+
+    #[test]
+    fn nested_call() {
+        let mut vm = VM::default();
+
+        for i in 261..=299 {
+            vm.set_mem(i, -1).unwrap();
+        }
 
         vm.set_mem(SP, 261).unwrap();
         vm.set_mem(LCL, 261).unwrap();
@@ -1437,830 +3235,2338 @@ mod tests {
         vm.set_mem(THIS, 3000).unwrap();
         vm.set_mem(THAT, 4000).unwrap();
 
-        let sys = r#"
-            // Sys.vm for NestedCall test.
+        let programs = vec![SourceFile::new("Sys.vm", NESTED_CALL)];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(0, 261).unwrap();
+        vm.set_mem(1, 261).unwrap();
+        vm.set_mem(2, 256).unwrap();
+        vm.set_mem(3, -3).unwrap();
+        vm.set_mem(4, -4).unwrap();
+        vm.set_mem(5, -1).unwrap(); // test results
+        vm.set_mem(6, -1).unwrap();
+        vm.set_mem(256, 1234).unwrap(); // fake stack frame from call Sys.init
+        vm.set_mem(257, -1).unwrap();
+        vm.set_mem(258, -2).unwrap();
+        vm.set_mem(259, -3).unwrap();
+        vm.set_mem(260, -4).unwrap();
+
+        for _ in 0..50 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(261), vm.mem(0));
+        assert_eq!(Ok(261), vm.mem(1));
+        assert_eq!(Ok(256), vm.mem(2));
+        assert_eq!(Ok(4000), vm.mem(3));
+        assert_eq!(Ok(5000), vm.mem(4));
+        assert_eq!(Ok(135), vm.mem(5));
+        assert_eq!(Ok(246), vm.mem(6));
+    }
+
+    #[test]
+    fn simple_function() {
+        let mut vm = VM::default();
+
+        let sys = r#"
+            function SimpleFunction.test 2
+            push local 0
+            push local 1
+            add
+            not
+            push argument 0
+            add
+            push argument 1
+            sub
+            return"#;
+
+        let programs = vec![SourceFile::new("Sys.vm", sys)];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(SP, 317).unwrap();
+        vm.set_mem(LCL, 317).unwrap();
+        vm.set_mem(ARG, 310).unwrap();
+        vm.set_mem(THIS, 3000).unwrap();
+        vm.set_mem(THAT, 4000).unwrap();
+        vm.set_mem_indirect(ARG, 0, 1234).unwrap();
+        vm.set_mem_indirect(ARG, 1, 37).unwrap();
+        vm.set_mem_indirect(ARG, 2, 9).unwrap();
+        vm.set_mem_indirect(ARG, 3, 305).unwrap();
+        vm.set_mem_indirect(ARG, 4, 300).unwrap();
+        vm.set_mem_indirect(ARG, 5, 3010).unwrap();
+        vm.set_mem_indirect(ARG, 6, 4010).unwrap();
+
+        for _ in 0..10 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(311), vm.mem(0));
+        assert_eq!(Ok(305), vm.mem(1));
+        assert_eq!(Ok(300), vm.mem(2));
+        assert_eq!(Ok(3010), vm.mem(3));
+        assert_eq!(Ok(4010), vm.mem(4));
+        assert_eq!(Ok(1196), vm.mem(310));
+    }
+
+    #[test]
+    fn statics_test_with_stdlib() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let sys = r#"
+            // Tests that different functions, stored in two different
+            // class files, manipulate the static segment correctly.
+            function Sys.init 0
+            push constant 6
+            push constant 8
+            call Class1.set 2
+            pop temp 0 // Dumps the return value
+            push constant 23
+            push constant 15
+            call Class2.set 2
+            pop temp 0 // Dumps the return value
+            call Class1.get 0
+            call Class2.get 0
+            label WHILE
+            goto WHILE
+            "#;
+
+        let class1 = r#"
+            // Stores two supplied arguments in static[0] and static[1].
+            function Class1.set 0
+            push argument 0
+            pop static 0
+            push argument 1
+            pop static 1
+            push constant 0
+            return
+
+            // Returns static[0] - static[1].
+            function Class1.get 0
+            push static 0
+            push static 1
+            sub
+            return
+            "#;
+
+        let class2 = r#"
+            // Stores two supplied arguments in static[0] and static[1].
+            function Class2.set 0
+            push argument 0
+            pop static 0
+            push argument 1
+            pop static 1
+            push constant 0
+            return
+
+            // Returns static[0] - static[1].
+            function Class2.get 0
+            push static 0
+            push static 1
+            sub
+            return
+            "#;
+
+        let programs = vec![
+            SourceFile::new("Class1.vm", class1),
+            SourceFile::new("Sys.vm", sys),
+            SourceFile::new("Class2.vm", class2),
+        ];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(SP, 261).unwrap();
+
+        for _ in 0..36 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(263), vm.mem(0));
+        assert_eq!(Ok(-2), vm.mem(261));
+        assert_eq!(Ok(8), vm.mem(262));
+    }
+
+    #[test]
+    fn statics_test() {
+        let mut vm = VM::default();
+
+        let sys = r#"
+            // Tests that different functions, stored in two different
+            // class files, manipulate the static segment correctly.
+            function Sys.init 0
+            push constant 6
+            push constant 8
+            call Class1.set 2
+            pop temp 0 // Dumps the return value
+            push constant 23
+            push constant 15
+            call Class2.set 2
+            pop temp 0 // Dumps the return value
+            call Class1.get 0
+            call Class2.get 0
+            label WHILE
+            goto WHILE
+            "#;
+
+        let class1 = r#"
+            // Stores two supplied arguments in static[0] and static[1].
+            function Class1.set 0
+            push argument 0
+            pop static 0
+            push argument 1
+            pop static 1
+            push constant 0
+            return
+
+            // Returns static[0] - static[1].
+            function Class1.get 0
+            push static 0
+            push static 1
+            sub
+            return
+            "#;
+
+        let class2 = r#"
+            // Stores two supplied arguments in static[0] and static[1].
+            function Class2.set 0
+            push argument 0
+            pop static 0
+            push argument 1
+            pop static 1
+            push constant 0
+            return
+
+            // Returns static[0] - static[1].
+            function Class2.get 0
+            push static 0
+            push static 1
+            sub
+            return
+            "#;
+
+        let programs = vec![
+            SourceFile::new("Sys.vm", sys),
+            SourceFile::new("Class1.vm", class1),
+            SourceFile::new("Class2.vm", class2),
+        ];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        vm.set_mem(SP, 261).unwrap();
+
+        for _ in 0..36 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(Ok(263), vm.mem(0));
+        assert_eq!(Ok(-2), vm.mem(261));
+        assert_eq!(Ok(8), vm.mem(262));
+    }
+
+    #[test]
+    fn test_display_pbm() {
+        let mut vm = VM::default();
+        // the first pixel of a word is its lowest bit
+        vm.set_mem(SCREEN_START, 0b101).unwrap();
+        vm.set_mem(SCREEN_START + 32 + 31, i16::MIN).unwrap();
+
+        let mut rows = vec![
+            format!("101{}", "0".repeat(509)),
+            format!("{}1", "0".repeat(511)),
+        ];
+        rows.resize(256, "0".repeat(512));
+
+        // every row is split into 8 lines of 64 pixels
+        let mut expected = "P1\n512 256\n".to_owned();
+        for row in rows {
+            for line in 0..8 {
+                expected += &row[line * 64..(line + 1) * 64];
+                expected += "\n";
+            }
+        }
+        assert_eq!(expected, vm.display_pbm());
+        assert!(vm.display_pbm().lines().all(|line| line.len() <= 70));
+    }
+
+    #[test]
+    fn display_thick_lines() {
+        let mut vm = VM::default();
+
+        let src = r#"
+            function Lines.init 0
+            call Lines.main 3
+            label END
+            goto END
+
+            function Lines.main 3
+            push constant 16384
+            pop local 2
+            push constant 8192
+            pop local 0
+            push constant 0
+            pop local 1
+            label WHILE_EXP0
+            push local 1
+            push local 0
+            lt
+            not
+            if-goto WHILE_END0
+            push local 1
+            push local 2
+            add
+            push constant 255
+            pop temp 0
+            pop pointer 1
+            push temp 0
+            pop that 0
+            push local 1
+            push constant 1
+            add
+            pop local 1
+            goto WHILE_EXP0
+            label WHILE_END0
+            push constant 0
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Lines.vm", src)];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..500000 {
+            vm.step().unwrap();
+        }
+
+        for i in SCREEN_START..KBD {
+            assert_eq!(Ok(255), vm.mem(i));
+        }
+    }
+
+    #[test]
+    fn test_should_execute_stdlib_implementation() {
+        let mut by_name = HashMap::new();
+        let mut by_address = HashMap::new();
+
+        by_name.insert("Math.abs", u16::MAX);
+        by_address.insert(
+            u16::MAX,
+            BuiltinFunction::new(u16::MAX, "Math.abs", "Math", 1, &|_, _, params| {
+                Ok(StdlibOk::Finished(params[0].abs()))
+            }),
+        );
+
+        let stdlib = Stdlib::of(by_name, by_address);
+
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Lines.init 0
+            push constant 0
+            push constant 42
+            sub
+            call Math.abs 1
+            label LOOP
+            goto LOOP
+            "#;
+
+        let programs = vec![SourceFile::new("MathsTest.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..100 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.pop(), Ok(42));
+    }
+
+    #[test]
+    fn test_should_execute_stdlib_implementation_multiple_args() {
+        let stdlib = Stdlib::new();
+
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Main.main 0
+            push constant 42
+            push constant 3
+            push constant 4
+            call Math.multiply 2
+            push constant 2
+            call Math.multiply 2
+            neg
+            call Math.abs 1
+            label LOOP
+            goto LOOP
+            "#;
+
+        let programs = vec![SourceFile::new("MathsTest.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..20 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.pop(), Ok(24));
+        assert_eq!(vm.pop(), Ok(42));
+    }
+
+    #[test]
+    fn test_calling_vm_from_builtin_function() {
+        let mut by_name = HashMap::new();
+        let mut by_address = HashMap::new();
+
+        fn sys_init(vm: &mut VM, state: State, params: &[Word]) -> StdResult {
+            match state {
+                0 => {
+                    if VMCallOk::WasBuiltinFunction == vm.call("Memory.init", &[])? {
+                        // continue immediately
+                        sys_init(vm, state + 1, params)
+                    } else {
+                        Ok(StdlibOk::ContinueInNextStep(state + 1))
+                    }
+                }
+                1 => {
+                    if VMCallOk::WasBuiltinFunction == vm.call("Main.main", &[])? {
+                        // continue immediately
+                        sys_init(vm, state + 1, params)
+                    } else {
+                        Ok(StdlibOk::ContinueInNextStep(state + 1))
+                    }
+                }
+                _ => Ok(StdlibOk::ContinueInNextStep(state)),
+            }
+        }
+
+        by_name.insert("Sys.init", u16::MAX - 1);
+        by_address.insert(
+            u16::MAX - 1,
+            BuiltinFunction::new(u16::MAX - 1, "Sys.init", "Sys", 0, &sys_init),
+        );
+
+        fn mem_init(_: &mut VM, _: State, _: &[Word]) -> StdResult {
+            Ok(StdlibOk::Finished(20))
+        }
+
+        by_name.insert("Memory.init", u16::MAX);
+        by_address.insert(
+            u16::MAX,
+            BuiltinFunction::new(u16::MAX, "Memory.init", "Memory", 0, &mem_init),
+        );
+
+        let stdlib = Stdlib::of(by_name, by_address);
+
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Main.main 0
+            call Memory.init 0
+            push constant 22
+            add // the 20 should have been pushed by the Memory.init call
+            return // return to Sys.init
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..20 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.pop(), Ok(42));
+    }
+
+    #[test]
+    fn test_reentrant_builtin_function() {
+        let mut by_name = HashMap::new();
+        let mut by_address = HashMap::new();
+
+        fn recurse(vm: &mut VM, state: State, _: &[Word]) -> StdResult {
+            stdlib::call_vm!(vm, state, "Main.recurse", &[])
+        }
+
+        by_name.insert("Main.recurse", u16::MAX);
+        by_address.insert(
+            u16::MAX,
+            BuiltinFunction::new(u16::MAX, "Main.recurse", "Main", 0, &recurse),
+        );
+
+        let stdlib = Stdlib::of(by_name, by_address);
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Sys.init 0
+            call Main.recurse 0
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Sys.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        vm.load(bytecode_parser.parse().unwrap());
+
+        // function Sys.init
+        vm.step().unwrap();
+
+        // the call of Main.recurse immediately calls itself again
+        assert_eq!(
+            Err(VMError::ReentrantBuiltin("Main.recurse".to_owned())),
+            vm.step()
+        );
+    }
+
+    #[test]
+    fn test_indirectly_reentrant_builtin_function() {
+        let mut by_name = HashMap::new();
+        let mut by_address = HashMap::new();
+
+        fn a(vm: &mut VM, state: State, _: &[Word]) -> StdResult {
+            stdlib::call_vm!(vm, state, "Main.b", &[])
+        }
+
+        fn b(vm: &mut VM, state: State, _: &[Word]) -> StdResult {
+            stdlib::call_vm!(vm, state, "Main.a", &[])
+        }
+
+        by_name.insert("Main.a", u16::MAX);
+        by_name.insert("Main.b", u16::MAX - 1);
+        by_address.insert(
+            u16::MAX,
+            BuiltinFunction::new(u16::MAX, "Main.a", "Main", 0, &a),
+        );
+        by_address.insert(
+            u16::MAX - 1,
+            BuiltinFunction::new(u16::MAX - 1, "Main.b", "Main", 0, &b),
+        );
+
+        let stdlib = Stdlib::of(by_name, by_address);
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Sys.init 0
+            call Main.a 0
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Sys.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        vm.load(bytecode_parser.parse().unwrap());
+
+        // function Sys.init
+        vm.step().unwrap();
+
+        // Main.a calls Main.b, which calls Main.a again
+        assert_eq!(
+            Err(VMError::ReentrantBuiltin("Main.a".to_owned())),
+            vm.step()
+        );
+    }
+
+    #[test]
+    fn test_continuing_parked_stdlib_function() {
+        let mut by_name = HashMap::new();
+        let mut by_address = HashMap::new();
+
+        fn sys_wait(_: &mut VM, state: State, params: &[Word]) -> StdResult {
+            if state == 0 {
+                if params[0] < 2 {
+                    return Ok(StdlibOk::Finished(params[0]));
+                }
+                // 2 because one tick is already used
+                return Ok(StdlibOk::ContinueInNextStep(2));
+            }
+
+            if params[0] as State > state {
+                return Ok(StdlibOk::ContinueInNextStep(state + 1));
+            }
+
+            Ok(StdlibOk::Finished(params[0]))
+        }
+
+        fn sys_init(vm: &mut VM, state: State, _: &[Word]) -> StdResult {
+            if state == 0 {
+                vm.call("Main.main", &[])?;
+                return Ok(StdlibOk::ContinueInNextStep(state + 1));
+            }
+            Ok(StdlibOk::ContinueInNextStep(state))
+        }
+
+        by_name.insert("Sys.init", u16::MAX - 1);
+        by_address.insert(
+            u16::MAX - 1,
+            BuiltinFunction::new(u16::MAX - 1, "Sys.init", "Sys", 0, &sys_init),
+        );
+
+        by_name.insert("Sys.wait", u16::MAX);
+        by_address.insert(
+            u16::MAX,
+            BuiltinFunction::new(u16::MAX, "Sys.wait", "Sys", 1, &sys_wait),
+        );
+
+        let stdlib = Stdlib::of(by_name, by_address);
+
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Main.getReturnValue 0
+            push constant 42
+            return
+
+            function Main.main 0
+            push constant 10 // wait 10 ticks
+            call Sys.wait 1
+            push constant 2
+            call Sys.wait 1 // wait 2 more ticks
+            call Main.getReturnValue 0
+            return // return to Sys.init
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..100 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.pop(), Ok(42));
+    }
+
+    #[test]
+    fn test_use_return_value_of_vm_function_in_builtin_function() {
+        let mut by_name = HashMap::new();
+        let mut by_address = HashMap::new();
+
+        fn calc(vm: &mut VM, state: State, _params: &[Word]) -> StdResult {
+            match state {
+                0 => {
+                    // technically this is wrong because the return value isn't incremented, but Main.f
+                    // will always be a VM function, so it's no problem
+                    stdlib::call_vm!(vm, state, "Main.f", &[])
+                }
+                1 => {
+                    let vm_ret = vm.pop()?;
+                    Ok(StdlibOk::Finished(vm_ret + 1))
+                }
+                _ => panic!(""),
+            }
+        }
+
+        by_name.insert("Math.calc", u16::MAX - 1);
+        by_address.insert(
+            u16::MAX - 1,
+            BuiltinFunction::new(u16::MAX - 1, "Math.calc", "Math", 0, &calc),
+        );
+
+        let stdlib = Stdlib::of(by_name, by_address);
+
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Main.main 0
+            call Math.calc 0
+            call Main.f 0
+            push constant 1
+            sub
+            call Math.calc 0
+            call Math.calc 0
+            label END
+            goto END
+
+            function Main.f 0
+            push constant 41
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..30 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.pop(), Ok(42));
+        assert_eq!(vm.pop(), Ok(42));
+        assert_eq!(vm.pop(), Ok(40));
+        assert_eq!(vm.pop(), Ok(42));
+    }
+
+    #[test]
+    fn test_calling_vm_from_builtin_function_multiple_times() {
+        let mut by_name = HashMap::new();
+        let mut by_address = HashMap::new();
+
+        fn sys_init(vm: &mut VM, state: State, params: &[Word]) -> StdResult {
+            match state {
+                0 => {
+                    if let VMCallOk::WasBuiltinFunction = vm.call("Memory.init", &[])? {
+                        // continue immediately
+                        sys_init(vm, state + 1, params)
+                    } else {
+                        Ok(StdlibOk::ContinueInNextStep(state + 1))
+                    }
+                }
+                1 => {
+                    if let VMCallOk::WasBuiltinFunction = vm.call("Main.main", &[])? {
+                        // continue immediately
+                        sys_init(vm, state + 1, params)
+                    } else {
+                        Ok(StdlibOk::ContinueInNextStep(state + 1))
+                    }
+                }
+                // endless loop
+                _ => Ok(StdlibOk::ContinueInNextStep(state)),
+            }
+        }
+
+        by_name.insert("Sys.init", u16::MAX);
+        by_address.insert(
+            u16::MAX,
+            BuiltinFunction::new(u16::MAX, "Sys.init", "Sys", 0, &sys_init),
+        );
+
+        let stdlib = Stdlib::of(by_name, by_address);
+
+        let mut vm = VM::new(stdlib.clone());
+
+        let src = r#"
+            function Memory.init 0
+            push constant 0
+            pop static 0
+            push constant 2048
+            push static 0
+            add
+            push constant 14334
+            pop temp 0
+            pop pointer 1
+            push temp 0
+            pop that 0
+            push constant 2049
+            push static 0
+            add
+            push constant 2050
+            pop temp 0
+            pop pointer 1
+            push temp 0
+            pop that 0
+            push constant 0
+            return
+
+            function Main.main 0
+            call Memory.init 0
+            push constant 22
+            add
+            return // return to Sys.init
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..300 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.pop(), Ok(22));
+    }
+
+    #[test]
+    fn test_code_position_methods() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
+            push constant 5
+            push constant 3
+            sub
+            call String.new 1
+            call Main.doStuff 1
+            call Output.printString 1
+            return
+
+            function Main.doStuff 1
+            push argument 0
+            pop local 0
+            push local 0
+            push constant 65
+            call String.appendChar 2
+            push constant 65
+            call String.appendChar 2
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        // skip Sys.init
+        for _ in 0..7 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.pc, 1);
+
+        // push constants
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Main.main"));
+        assert_eq!(vm.locals(), Some(&[][..]));
+        assert_eq!(vm.args(), Some(&[][..]));
+        assert_eq!(vm.stack(), Some(&[5, 3][..]));
+
+        // sub
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Main.main"));
+        assert_eq!(vm.locals(), Some(&[][..]));
+        assert_eq!(vm.args(), Some(&[][..]));
+        assert_eq!(vm.stack(), Some(&[2][..]));
+
+        // call String.new
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("String.new"));
+        assert_eq!(vm.locals(), None);
+        assert_eq!(vm.args(), Some(&[2][..]));
+        assert_eq!(vm.stack(), None);
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Main.main"));
+        assert_eq!(vm.locals(), Some(&[][..]));
+        assert_eq!(vm.args(), Some(&[][..]));
+        assert_eq!(vm.stack().map(|s| s.len()), Some(1));
+        let s = vm.stack().unwrap()[0];
+        assert!(s > HEAP_START as Word);
+
+        // call Main.doStuff
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Main.doStuff"));
+        assert_eq!(vm.locals(), Some(&[0][..]));
+        assert_eq!(vm.args(), Some(&[s][..]));
+        assert_eq!(vm.stack(), Some(&[][..]));
+
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Main.doStuff"));
+        assert_eq!(vm.locals(), Some(&[s][..]));
+        assert_eq!(vm.args(), Some(&[s][..]));
+        assert_eq!(vm.stack(), Some(&[s, 65][..]));
+
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+
+        // call Output.printString
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Output.printString"));
+        assert_eq!(vm.locals(), None);
+        assert_eq!(vm.args(), Some(&[s][..]));
+        assert_eq!(vm.stack(), None);
+
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Output.printString"));
+        assert_eq!(vm.locals(), None);
+        assert_eq!(vm.args(), Some(&[s][..]));
+        assert_eq!(vm.stack(), None);
+
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Main.main"));
+        assert_eq!(vm.locals(), Some(&[][..]));
+        assert_eq!(vm.args(), Some(&[][..]));
+        assert_eq!(vm.stack(), Some(&[0][..]));
+    }
+
+    #[test]
+    fn test_current_frame_dims() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
+            push constant 1
+            push constant 2
+            call Main.add 2
+            return
+
+            function Main.add 3
+            push argument 0
+            push argument 1
+            add
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        // skip Sys.init
+        for _ in 0..7 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.current_function_name(), Some("Main.main"));
+        assert_eq!(vm.current_frame_dims(), Some((0, 0)));
+
+        // push constants
+        vm.step().unwrap();
+        vm.step().unwrap();
+
+        // call Main.add
+        vm.step().unwrap();
+        assert_eq!(vm.current_function_name(), Some("Main.add"));
+        assert_eq!(vm.current_frame_dims(), Some((2, 3)));
+    }
+
+    #[test]
+    fn test_compute_only_disables_screen() {
+        let mut vm = VM::new(Stdlib::compute_only());
+
+        let src = r#"
+            function Main.main 0
+            push constant 1
+            push constant 2
+            call Math.max 2
+            pop temp 0
+            call Screen.clearScreen 0
+            label LOOP
+            goto LOOP
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::compute_only());
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        // skip Sys.init, the init functions of the peripherals are still callable
+        for _ in 0..7 {
+            vm.step().unwrap();
+        }
+
+        // push constants, call Math.max, pop
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.mem(5).unwrap(), 2);
+
+        assert_eq!(
+            vm.step(),
+            Err(VMError::StdlibError(StdlibError::PeripheralDisabled(
+                "Screen"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_last_return_value() {
+        let mut vm = VM::new(Stdlib::new());
+        assert_eq!(vm.last_return_value(), None);
+
+        let src = r#"
+            function Main.main 0
+            push constant 3
+            push constant 4
+            call Main.add 2
+            push constant 9
+            push constant 2
+            call Math.max 2
+            label LOOP
+            goto LOOP
+
+            function Main.add 0
+            push argument 0
+            push argument 1
+            add
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        // skip Sys.init
+        for _ in 0..7 {
+            vm.step().unwrap();
+        }
+
+        // push constants, call, function, push args, add, return
+        for _ in 0..8 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.current_function_name(), Some("Main.main"));
+        assert_eq!(vm.last_return_value(), Some(7));
+
+        // push constants, call Math.max
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.current_function_name(), Some("Main.main"));
+        assert_eq!(vm.last_return_value(), Some(9));
+
+        vm.load(
+            BytecodeParser::with_stdlib(vec![SourceFile::new("Main.vm", src)], Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(vm.last_return_value(), None);
+    }
+
+    #[test]
+    fn test_statics_of_reports_non_overlapping_ranges_per_file() {
+        let mut vm = VM::default();
+
+        let main = r#"
+            function Main.main 0
+            push constant 1
+            pop static 0
+            push constant 2
+            pop static 2
+            call Other.run 0
+            return
+            "#;
+
+        let other = r#"
+            function Other.run 0
+            push constant 3
+            pop static 1
+            push constant 4
+            pop static 0
+            push constant 0
+            return
+            "#;
+
+        let empty = r#"
+            function Empty.run 0
+            push constant 0
+            return
+            "#;
+
+        let programs = vec![
+            SourceFile::new("Main.vm", main),
+            SourceFile::new("Other.vm", other),
+            SourceFile::new("Empty.vm", empty),
+        ];
+        let mut bytecode_parser = BytecodeParser::new(programs);
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        assert_eq!(Some(16..18), vm.statics_of("Main.vm"));
+        assert_eq!(Some(18..20), vm.statics_of("Other.vm"));
+        assert_eq!(None, vm.statics_of("Empty.vm"));
+        assert_eq!(None, vm.statics_of("Missing.vm"));
+    }
+
+    #[test]
+    fn test_register_builtin_host_function() {
+        fn log(vm: &mut VM, _: State, params: &[Word]) -> StdResult {
+            // append the logged value to a list at 5000
+            let len = vm.mem(5000)?;
+            vm.set_mem(5001 + len as Address, params[0])?;
+            vm.set_mem(5000, len + 1)?;
+            Ok(StdlibOk::Finished(0))
+        }
+
+        let mut vm = VM::new(Stdlib::new());
+        let address = vm.register_builtin("Host.log", 1, &log);
+        assert!(vm.stdlib().by_address(address).is_some());
+        assert_eq!(
+            Some("Host"),
+            vm.stdlib().lookup("Host.log").map(|f| f.file())
+        );
+
+        let src = r#"
+            function Main.main 0
+            push constant 7
+            call Host.log 1
+            pop temp 0
+            push constant 8
+            call Host.log 1
+            label LOOP
+            goto LOOP
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, vm.stdlib().clone());
+        let program = bytecode_parser.parse().unwrap();
+
+        vm.load(program);
+
+        for _ in 0..20 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(vm.mem_range(5000..5003), Some(&[2, 7, 8][..]));
+    }
+
+    #[test]
+    fn test_strict_statics_reports_unwritten_static() {
+        let src = r#"
+            function Main.main 0
+            push constant 7
+            pop static 0
+            push static 0
+            push static 1
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let program = BytecodeParser::new(programs).parse().unwrap();
+        let mut vm = VM::default();
+        vm.load(program);
+        vm.set_strict_statics(true);
+
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Some(7), vm.memory_at(256));
+        assert_eq!(Err(VMError::UnwrittenStatic(17)), vm.step());
 
-            // Sys.init()
-            //
-            // Calls Sys.main() and stores return value in temp 1.
-            // Does not return.  (Enters infinite loop.)
+        // a static set from outside of the program (e.g. by a script) counts as written
+        vm.set_mem(17, 3).unwrap();
+        vm.step().unwrap();
+        assert_eq!(Some(3), vm.memory_at(257));
 
-            function Sys.init 0
-            push constant 4000	// test THIS and THAT context save
-            pop pointer 0
-            push constant 5000
-            pop pointer 1
-            call Sys.main 0
-            pop temp 1
-            label LOOP
-            goto LOOP
+        // the pointers and temp are not statics
+        vm.set_mem(5, 1).unwrap();
+        assert!(!vm.written_statics.contains(&5));
 
-            // Sys.main()
-            //
-            // Sets locals 1, 2 and 3, leaving locals 0 and 4 unchanged to test
-            // default local initialization to 0.  (RAM set to -1 by test setup.)
-            // Calls Sys.add12(123) and stores return value (135) in temp 0.
-            // Returns local 0 + local 1 + local 2 + local 3 + local 4 (456) to confirm
-            // that locals were not mangled by function call.
+        // without strict mode, unwritten statics are just 0
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let program = BytecodeParser::new(programs).parse().unwrap();
+        vm.load(program);
+        vm.set_strict_statics(false);
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Some(258), vm.memory_at(SP));
+        assert_eq!(Some(0), vm.memory_at(257));
+    }
 
-            function Sys.main 5
-            push constant 4001
-            pop pointer 0
-            push constant 5001
-            pop pointer 1
-            push constant 200
-            pop local 1
-            push constant 40
-            pop local 2
-            push constant 6
-            pop local 3
-            push constant 123
-            call Sys.add12 1
-            pop temp 0
-            push local 0
-            push local 1
-            push local 2
-            push local 3
-            push local 4
-            add
-            add
+    #[test]
+    fn test_strict_bool_index_reports_true_in_address() {
+        let src = r#"
+            push constant 3000
+            push constant 2
+            push constant 1
+            lt
             add
+            push constant 1
+            push constant 2
+            lt
+            sub
+            pop temp 0
+            push constant 3000
+            push constant 1
+            push constant 2
+            lt
             add
-            return
-
-            // Sys.add12(int n)
-            //
-            // Returns n+12.
-
-            function Sys.add12 0
-            push constant 4002
-            pop pointer 0
-            push constant 5002
             pop pointer 1
-            push argument 0
-            push constant 12
-            add
-            return"#;
+            push constant 1
+            push constant 2
+            lt
+            if-goto END
+            label END
+            push constant 0
+            not
+            pop pointer 0
+            push constant 1
+            push constant 2
+            lt
+            pop pointer 1"#;
 
-        let programs = vec![SourceFile::new("Sys.vm", sys)];
-        let mut bytecode_parser = BytecodeParser::new(programs);
-        let program = bytecode_parser.parse().unwrap();
+        let load = |vm: &mut VM| {
+            let programs = vec![SourceFile::new("Main.vm", src)];
+            vm.load(BytecodeParser::new(programs).parse().unwrap());
+        };
 
-        vm.load(program);
+        let mut vm = VM::default();
+        load(&mut vm);
+        vm.set_strict_bool_index(true);
 
-        vm.set_mem(0, 261).unwrap();
-        vm.set_mem(1, 261).unwrap();
-        vm.set_mem(2, 256).unwrap();
-        vm.set_mem(3, -3).unwrap();
-        vm.set_mem(4, -4).unwrap();
-        vm.set_mem(5, -1).unwrap(); // test results
-        vm.set_mem(6, -1).unwrap();
-        vm.set_mem(256, 1234).unwrap(); // fake stack frame from call Sys.init
-        vm.set_mem(257, -1).unwrap();
-        vm.set_mem(258, -2).unwrap();
-        vm.set_mem(259, -3).unwrap();
-        vm.set_mem(260, -4).unwrap();
+        // arithmetic with false (0) or true (-1) is fine, as long as it's not used as an address
+        for _ in 0..15 {
+            vm.step().unwrap();
+        }
+        // using 3000 + true as the base of that is not
+        let pop_pointer = |index| Instruction::Pop {
+            segment: Segment::Pointer,
+            index,
+        };
+        assert_eq!(Err(VMError::BooleanInAddress(pop_pointer(1))), vm.step());
 
-        for _ in 0..50 {
+        // without strict mode, the same program runs fine
+        load(&mut vm);
+        vm.set_strict_bool_index(false);
+        for _ in 0..16 {
             vm.step().unwrap();
         }
 
-        assert_eq!(Ok(261), vm.mem(0));
-        assert_eq!(Ok(261), vm.mem(1));
-        assert_eq!(Ok(256), vm.mem(2));
-        assert_eq!(Ok(4000), vm.mem(3));
-        assert_eq!(Ok(5000), vm.mem(4));
-        assert_eq!(Ok(135), vm.mem(5));
-        assert_eq!(Ok(246), vm.mem(6));
+        // a consumed comparison result doesn't count, even if the same stack slot holds -1 again
+        vm.set_strict_bool_index(true);
+        for _ in 0..10 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Err(VMError::BooleanInAddress(pop_pointer(1))), vm.step());
     }
 
     #[test]
-    fn simple_function() {
+    fn test_strict_this_that_reports_uninitialized_pointer() {
+        let src = r#"
+            function Main.main 0
+            push this 0
+            push constant 3000
+            pop pointer 0
+            push this 0
+            return
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let program = BytecodeParser::new(programs).parse().unwrap();
+        let mut vm = VM::default();
+        vm.load(program);
+
+        vm.set_strict_this_that(true);
+        vm.step().unwrap();
+        assert_eq!(
+            Err(VMError::UninitializedThisThat {
+                segment: Segment::This,
+                base: 0
+            }),
+            vm.step()
+        );
+
+        // without strict mode, reading this 0 with THIS == 0 is fine
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let program = BytecodeParser::new(programs).parse().unwrap();
+        vm.load(program);
+        vm.set_strict_this_that(false);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+
+        // with an initialized pointer, strict mode allows the access
+        vm.set_strict_this_that(true);
+        vm.step().unwrap();
+        assert_eq!(Ok(0), vm.tos());
+
+        // pointers past the end of the heap (e.g. into the screen) are reported as well
+        vm.set_mem(THIS, SCREEN_START as Word).unwrap();
+        assert_eq!(
+            Err(VMError::UninitializedThisThat {
+                segment: Segment::This,
+                base: SCREEN_START as Word
+            }),
+            vm.this_that_base(Segment::This, THIS)
+        );
+    }
+
+    #[test]
+    fn test_pop_constant_error_contains_instruction() {
+        let program = vec![Instruction::Pop {
+            segment: Segment::Constant,
+            index: 5,
+        }];
+        let program = ParsedProgram::new(program, HashMap::new(), HashMap::new());
+
         let mut vm = VM::default();
+        vm.load(program);
+        let error = vm.step().unwrap_err();
 
-        let sys = r#"
-            function SimpleFunction.test 2
-            push local 0
-            push local 1
-            add
-            not
-            push argument 0
-            add
-            push argument 1
-            sub
-            return"#;
+        assert_eq!(
+            "Trying to get address of constant in 'pop constant 5'",
+            error.to_string()
+        );
+    }
 
-        let programs = vec![SourceFile::new("Sys.vm", sys)];
+    #[test]
+    fn test_call_events_of_nested_call() {
+        let mut vm = VM::default();
+
+        let programs = vec![SourceFile::new("Sys.vm", NESTED_CALL)];
         let mut bytecode_parser = BytecodeParser::new(programs);
         let program = bytecode_parser.parse().unwrap();
 
         vm.load(program);
+        vm.set_record_call_events(true);
 
-        vm.set_mem(SP, 317).unwrap();
-        vm.set_mem(LCL, 317).unwrap();
-        vm.set_mem(ARG, 310).unwrap();
-        vm.set_mem(THIS, 3000).unwrap();
-        vm.set_mem(THAT, 4000).unwrap();
-        vm.set_mem_indirect(ARG, 0, 1234).unwrap();
-        vm.set_mem_indirect(ARG, 1, 37).unwrap();
-        vm.set_mem_indirect(ARG, 2, 9).unwrap();
-        vm.set_mem_indirect(ARG, 3, 305).unwrap();
-        vm.set_mem_indirect(ARG, 4, 300).unwrap();
-        vm.set_mem_indirect(ARG, 5, 3010).unwrap();
-        vm.set_mem_indirect(ARG, 6, 4010).unwrap();
+        for _ in 0..50 {
+            vm.step().unwrap();
+        }
 
-        for _ in 0..10 {
+        use CallEventKind::{Enter, Exit};
+        assert_eq!(
+            vec![
+                CallEvent::new(Enter, "Sys.main"),
+                CallEvent::new(Enter, "Sys.add12"),
+                CallEvent::new(Exit, "Sys.add12"),
+                CallEvent::new(Exit, "Sys.main"),
+            ],
+            vm.take_call_events()
+        );
+        assert_eq!(Vec::<CallEvent>::new(), vm.take_call_events());
+
+        // without recording, no events are collected
+        vm.set_record_call_events(false);
+        vm.load(
+            BytecodeParser::new(vec![SourceFile::new("Sys.vm", NESTED_CALL)])
+                .parse()
+                .unwrap(),
+        );
+        for _ in 0..50 {
             vm.step().unwrap();
         }
+        assert_eq!(Vec::<CallEvent>::new(), vm.take_call_events());
+    }
 
-        assert_eq!(Ok(311), vm.mem(0));
-        assert_eq!(Ok(305), vm.mem(1));
-        assert_eq!(Ok(300), vm.mem(2));
-        assert_eq!(Ok(3010), vm.mem(3));
-        assert_eq!(Ok(4010), vm.mem(4));
-        assert_eq!(Ok(1196), vm.mem(310));
+    #[test]
+    fn test_set_input_string_queues_key_codes() {
+        let mut vm = VM::new(Stdlib::new());
+        vm.set_input_string("hi");
+        assert_eq!(
+            vec![104, 105, NEWLINE_KEY],
+            Vec::from(vm.queued_input().clone())
+        );
+
+        // characters without a key code are skipped
+        let mut vm = VM::new(Stdlib::new());
+        vm.set_input_string("a\u{e4}\tb");
+        assert_eq!(
+            vec![97, 98, NEWLINE_KEY],
+            Vec::from(vm.queued_input().clone())
+        );
     }
 
     #[test]
-    fn statics_test_with_stdlib() {
+    fn test_clear_input() {
         let mut vm = VM::new(Stdlib::new());
 
-        let sys = r#"
-            // Tests that different functions, stored in two different
-            // class files, manipulate the static segment correctly.
-            function Sys.init 0
-            push constant 6
-            push constant 8
-            call Class1.set 2
-            pop temp 0 // Dumps the return value
-            push constant 23
-            push constant 15
-            call Class2.set 2
-            pop temp 0 // Dumps the return value
-            call Class1.get 0
-            call Class2.get 0
-            label WHILE
-            goto WHILE
+        let src = r#"
+            function Main.main 0
+            call Keyboard.keyPressed 0
+            pop temp 0
+            label LOOP
+            goto LOOP
             "#;
 
-        let class1 = r#"
-            // Stores two supplied arguments in static[0] and static[1].
-            function Class1.set 0
-            push argument 0
-            pop static 0
-            push argument 1
-            pop static 1
-            push constant 0
-            return
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
 
-            // Returns static[0] - static[1].
-            function Class1.get 0
-            push static 0
-            push static 1
-            sub
-            return
-            "#;
+        vm.load(program);
+        vm.set_key_release_delay(100);
+        vm.set_input_string("abc");
+        vm.set_input_key(65).unwrap();
+        vm.set_input_key(0).unwrap();
 
-        let class2 = r#"
-            // Stores two supplied arguments in static[0] and static[1].
-            function Class2.set 0
-            push argument 0
-            pop static 0
-            push argument 1
-            pop static 1
-            push constant 0
-            return
+        vm.clear_input();
+        assert!(vm.queued_input().is_empty());
+
+        // make sure that keyPressed actually writes the 0
+        vm.set_mem(5, -1).unwrap();
+        for _ in 0..20 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Ok(0), vm.mem(5));
+    }
 
-            // Returns static[0] - static[1].
-            function Class2.get 0
-            push static 0
-            push static 1
-            sub
-            return
+    #[test]
+    fn test_read_char_consumes_queued_input() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
+            call Keyboard.readChar 0
+            call Keyboard.readChar 0
+            label LOOP
+            goto LOOP
             "#;
 
-        let programs = vec![
-            SourceFile::new("Class1.vm", class1),
-            SourceFile::new("Sys.vm", sys),
-            SourceFile::new("Class2.vm", class2),
-        ];
+        let programs = vec![SourceFile::new("Main.vm", src)];
         let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
         let program = bytecode_parser.parse().unwrap();
 
         vm.load(program);
+        vm.set_input_string("h");
 
-        vm.set_mem(SP, 261).unwrap();
-
-        for _ in 0..36 {
+        for _ in 0..100 {
             vm.step().unwrap();
         }
 
-        assert_eq!(Ok(263), vm.mem(0));
-        assert_eq!(Ok(-2), vm.mem(261));
-        assert_eq!(Ok(8), vm.mem(262));
+        assert_eq!(vm.stack(), Some(&[104, NEWLINE_KEY][..]));
+        assert!(vm.queued_input().is_empty());
     }
 
     #[test]
-    fn statics_test() {
-        let mut vm = VM::default();
+    fn test_read_int_ignores_backspace_at_start_of_line() {
+        let mut vm = VM::new(Stdlib::new());
 
-        let sys = r#"
-            // Tests that different functions, stored in two different
-            // class files, manipulate the static segment correctly.
-            function Sys.init 0
-            push constant 6
-            push constant 8
-            call Class1.set 2
-            pop temp 0 // Dumps the return value
-            push constant 23
-            push constant 15
-            call Class2.set 2
-            pop temp 0 // Dumps the return value
-            call Class1.get 0
-            call Class2.get 0
-            label WHILE
-            goto WHILE
+        let src = r#"
+            function Main.main 0
+            push constant 0
+            call String.new 1
+            call Keyboard.readInt 1
+            pop temp 0
+            label LOOP
+            goto LOOP
             "#;
 
-        let class1 = r#"
-            // Stores two supplied arguments in static[0] and static[1].
-            function Class1.set 0
-            push argument 0
-            pop static 0
-            push argument 1
-            pop static 1
-            push constant 0
-            return
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        vm.load(bytecode_parser.parse().unwrap());
+
+        let minus = '-' as Word;
+        vm.input_queue.extend([
+            BACKSPACE_KEY,
+            BACKSPACE_KEY,
+            minus,
+            BACKSPACE_KEY,
+            BACKSPACE_KEY,
+            minus,
+            '4' as Word,
+            '2' as Word,
+            NEWLINE_KEY,
+        ]);
+
+        for _ in 0..500 {
+            vm.step().unwrap();
+        }
 
-            // Returns static[0] - static[1].
-            function Class1.get 0
-            push static 0
-            push static 1
-            sub
-            return
-            "#;
+        assert!(vm.queued_input().is_empty());
+        assert_eq!(Ok(-42), vm.mem(5));
+    }
 
-        let class2 = r#"
-            // Stores two supplied arguments in static[0] and static[1].
-            function Class2.set 0
-            push argument 0
-            pop static 0
-            push argument 1
-            pop static 1
+    #[test]
+    fn test_is_waiting_for_input() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
             push constant 0
-            return
+            call String.new 1
+            call Keyboard.readLine 1
+            pop temp 0
+            label LOOP
+            goto LOOP
+            "#;
 
-            // Returns static[0] - static[1].
-            function Class2.get 0
-            push static 0
-            push static 1
-            sub
-            return
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        vm.load(bytecode_parser.parse().unwrap());
+        assert!(!vm.is_waiting_for_input());
+
+        for _ in 0..500 {
+            vm.step().unwrap();
+        }
+        // readLine is blocked in readChar
+        assert!(vm.is_waiting_for_input());
+
+        vm.input_queue.extend(['a' as Word, NEWLINE_KEY]);
+        for _ in 0..500 {
+            vm.step().unwrap();
+        }
+        assert!(vm.queued_input().is_empty());
+        assert!(!vm.is_waiting_for_input());
+    }
+
+    #[test]
+    fn test_read_line_ignores_non_ascii_input() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
+            push constant 0
+            call String.new 1
+            call Keyboard.readLine 1
+            pop temp 0
+            push temp 0
+            call String.length 1
+            pop temp 1
+            push temp 0
+            push constant 1
+            call String.charAt 2
+            pop temp 2
+            label LOOP
+            goto LOOP
             "#;
 
-        let programs = vec![
-            SourceFile::new("Sys.vm", sys),
-            SourceFile::new("Class1.vm", class1),
-            SourceFile::new("Class2.vm", class2),
-        ];
-        let mut bytecode_parser = BytecodeParser::new(programs);
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
         let program = bytecode_parser.parse().unwrap();
 
         vm.load(program);
+        // 'H', 'ä', UP_ARROW, 'i'
+        vm.input_queue.extend([72, 228, 131, 105, NEWLINE_KEY]);
 
-        vm.set_mem(SP, 261).unwrap();
-
-        for _ in 0..36 {
+        for _ in 0..500 {
             vm.step().unwrap();
         }
 
-        assert_eq!(Ok(263), vm.mem(0));
-        assert_eq!(Ok(-2), vm.mem(261));
-        assert_eq!(Ok(8), vm.mem(262));
+        assert!(vm.queued_input().is_empty());
+        assert_eq!(Ok(2), vm.mem(6));
+        assert_eq!(Ok(105), vm.mem(7));
     }
 
     #[test]
-    fn display_thick_lines() {
+    fn test_mem_range_padded() {
         let mut vm = VM::default();
+        vm.set_mem(MEM_SIZE - 2, 1).unwrap();
+        vm.set_mem(MEM_SIZE - 1, 2).unwrap();
 
-        let src = r#"
-            function Lines.init 0
-            call Lines.main 3
-            label END
-            goto END
+        assert_eq!(None, vm.mem_range((MEM_SIZE - 2)..(MEM_SIZE + 2)));
+        assert_eq!(
+            vec![1, 2, 0, 0],
+            vm.mem_range_padded((MEM_SIZE - 2)..(MEM_SIZE + 2))
+        );
+        assert_eq!(
+            vec![0, 0],
+            vm.mem_range_padded((MEM_SIZE + 5)..(MEM_SIZE + 7))
+        );
+    }
 
-            function Lines.main 3
-            push constant 16384
-            pop local 2
-            push constant 8192
+    #[test]
+    fn test_heap_stats() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 1
+            push constant 10
+            call Memory.alloc 1
             pop local 0
-            push constant 0
-            pop local 1
-            label WHILE_EXP0
-            push local 1
-            push local 0
-            lt
-            not
-            if-goto WHILE_END0
-            push local 1
-            push local 2
-            add
-            push constant 255
+            push constant 20
+            call Memory.alloc 1
             pop temp 0
-            pop pointer 1
-            push temp 0
-            pop that 0
-            push local 1
-            push constant 1
-            add
-            pop local 1
-            goto WHILE_EXP0
-            label WHILE_END0
-            push constant 0
-            return
+            push local 0
+            call Memory.deAlloc 1
+            label LOOP
+            goto LOOP
             "#;
 
-        let programs = vec![SourceFile::new("Lines.vm", src)];
-        let mut bytecode_parser = BytecodeParser::new(programs);
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
         let program = bytecode_parser.parse().unwrap();
 
         vm.load(program);
 
-        for _ in 0..500000 {
+        // skip Sys.init
+        for _ in 0..7 {
             vm.step().unwrap();
         }
 
-        for i in SCREEN_START..KBD {
-            assert_eq!(Ok(255), vm.mem(i));
-        }
-    }
+        let total = HEAP_END + 1 - HEAP_START;
+        let initial_free = total - 2;
+        assert_eq!(
+            heap::HeapStats {
+                total,
+                free: initial_free,
+                largest_free_block: initial_free,
+                free_blocks: 1,
+            },
+            vm.heap_stats()
+        );
 
-    #[test]
-    fn test_should_execute_stdlib_implementation() {
-        let mut by_name = HashMap::new();
-        let mut by_address = HashMap::new();
+        // both allocs
+        for _ in 0..6 {
+            vm.step().unwrap();
+        }
 
-        by_name.insert("Math.abs", u16::MAX);
-        by_address.insert(
-            u16::MAX,
-            BuiltinFunction::new(u16::MAX, "Math.abs", "Math", 1, &|_, _, params| {
-                Ok(StdlibOk::Finished(params[0].abs()))
-            }),
+        let free = initial_free - (10 + 2) - (20 + 2);
+        assert_eq!(
+            heap::HeapStats {
+                total,
+                free,
+                largest_free_block: free,
+                free_blocks: 1,
+            },
+            vm.heap_stats()
         );
 
-        let stdlib = Stdlib::of(by_name, by_address);
+        // deAlloc
+        vm.step().unwrap();
+        vm.step().unwrap();
 
-        let mut vm = VM::new(stdlib.clone());
+        assert_eq!(
+            heap::HeapStats {
+                total,
+                free: free + 10,
+                largest_free_block: free,
+                free_blocks: 2,
+            },
+            vm.heap_stats()
+        );
+    }
+
+    #[test]
+    fn test_heap_limit() {
+        let mut vm = VM::new(Stdlib::new());
+        // 28 words of capacity
+        vm.set_heap_limit(HEAP_START + 29);
 
         let src = r#"
-            function Lines.init 0
-            push constant 0
-            push constant 42
-            sub
-            call Math.abs 1
+            function Main.main 0
+            push constant 10
+            call Memory.alloc 1
+            pop temp 0
+            push constant 10
+            call Memory.alloc 1
+            pop temp 1
+            push constant 10
+            call Memory.alloc 1
             label LOOP
             goto LOOP
             "#;
 
-        let programs = vec![SourceFile::new("MathsTest.vm", src)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
         let program = bytecode_parser.parse().unwrap();
 
         vm.load(program);
 
-        for _ in 0..100 {
+        // skip Sys.init
+        for _ in 0..7 {
             vm.step().unwrap();
         }
+        assert_eq!(vm.heap_stats().total, 30);
 
-        assert_eq!(vm.pop(), Ok(42));
+        // the first two allocations fit
+        for _ in 0..6 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.mem(5).unwrap(), HEAP_START as Word + 2);
+        assert_eq!(vm.mem(6).unwrap(), HEAP_START as Word + 14);
+
+        vm.step().unwrap();
+        assert_eq!(
+            vm.step(),
+            Err(VMError::StdlibError(StdlibError::MemoryHeapOverflow))
+        );
+
+        // the limit is clamped to the real heap
+        vm.set_heap_limit(HEAP_END + 100);
+        assert_eq!(vm.heap_end(), HEAP_END);
+        vm.set_heap_limit(0);
+        assert_eq!(vm.heap_end(), HEAP_START + 2);
     }
 
     #[test]
-    fn test_should_execute_stdlib_implementation_multiple_args() {
-        let stdlib = Stdlib::new();
+    fn test_set_entry_function() {
+        let mut vm = VM::default();
 
-        let mut vm = VM::new(stdlib.clone());
+        let first = r#"
+            function First.main 0
+            push constant 1
+            pop temp 0
+            label LOOP
+            goto LOOP
+            "#;
 
-        let src = r#"
-            function Main.main 0
-            push constant 42
-            push constant 3
-            push constant 4
-            call Math.multiply 2
+        let second = r#"
+            function Second.main 0
             push constant 2
-            call Math.multiply 2
-            neg
-            call Math.abs 1
+            pop temp 0
             label LOOP
             goto LOOP
             "#;
 
-        let programs = vec![SourceFile::new("MathsTest.vm", src)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
-        let program = bytecode_parser.parse().unwrap();
+        let programs = vec![
+            SourceFile::new("First.vm", first),
+            SourceFile::new("Second.vm", second),
+        ];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
 
-        vm.load(program);
+        assert_eq!(
+            Err(VMError::UnknownFunction("Third.main".to_owned())),
+            vm.set_entry_function("Third.main")
+        );
+        vm.set_entry_function("Second.main").unwrap();
 
-        for _ in 0..20 {
+        // jump to Second.main, function, push, pop
+        for _ in 0..4 {
             vm.step().unwrap();
         }
 
-        assert_eq!(vm.pop(), Ok(24));
-        assert_eq!(vm.pop(), Ok(42));
+        assert_eq!(Ok(2), vm.mem(5));
     }
 
     #[test]
-    fn test_calling_vm_from_builtin_function() {
-        let mut by_name = HashMap::new();
-        let mut by_address = HashMap::new();
+    fn test_cycle_breakpoint() {
+        let mut vm = VM::default();
 
-        fn sys_init(vm: &mut VM, state: State, params: &[Word]) -> StdResult {
-            match state {
-                0 => {
-                    if VMCallOk::WasBuiltinFunction == vm.call("Memory.init", &[])? {
-                        // continue immediately
-                        sys_init(vm, state + 1, params)
-                    } else {
-                        Ok(StdlibOk::ContinueInNextStep(state + 1))
-                    }
-                }
-                1 => {
-                    if VMCallOk::WasBuiltinFunction == vm.call("Main.main", &[])? {
-                        // continue immediately
-                        sys_init(vm, state + 1, params)
-                    } else {
-                        Ok(StdlibOk::ContinueInNextStep(state + 1))
-                    }
-                }
-                _ => Ok(StdlibOk::ContinueInNextStep(state)),
-            }
-        }
+        let bytecode = r#"
+            label LOOP
+            push constant 1
+            pop temp 0
+            goto LOOP"#;
 
-        by_name.insert("Sys.init", u16::MAX - 1);
-        by_address.insert(
-            u16::MAX - 1,
-            BuiltinFunction::new(u16::MAX - 1, "Sys.init", "Sys", 0, &sys_init),
-        );
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+        vm.add_cycle_breakpoint(50);
 
-        fn mem_init(_: &mut VM, _: State, _: &[Word]) -> StdResult {
-            Ok(StdlibOk::Finished(20))
+        vm.step_times(1000).unwrap();
+        assert_eq!(50, vm.cycle_count());
+
+        // continuing moves past the breakpoint
+        vm.step_times(10).unwrap();
+        assert_eq!(60, vm.cycle_count());
+    }
+
+    #[test]
+    fn test_instruction_counters() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
+            push constant 3
+            call Sys.wait 1
+            pop temp 0
+            label LOOP
+            goto LOOP
+            "#;
+
+        let parse = || {
+            let programs = vec![SourceFile::new("Main.vm", src)];
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap()
+        };
+        vm.load(parse());
+
+        for _ in 0..10000 {
+            vm.step().unwrap();
         }
 
-        by_name.insert("Memory.init", u16::MAX);
-        by_address.insert(
-            u16::MAX,
-            BuiltinFunction::new(u16::MAX, "Memory.init", "Memory", 0, &mem_init),
+        // the jump into Sys.init is neither an instruction nor a builtin step
+        assert_eq!(
+            vm.cycle_count(),
+            1 + vm.instructions_executed() + vm.builtin_steps()
         );
+        // Sys.wait takes 1000 steps per millisecond
+        assert!(vm.builtin_steps() > 3000);
 
-        let stdlib = Stdlib::of(by_name, by_address);
+        vm.load(parse());
+        assert_eq!(0, vm.instructions_executed());
+        assert_eq!(0, vm.builtin_steps());
+    }
 
-        let mut vm = VM::new(stdlib.clone());
+    #[test]
+    fn test_reset_counters() {
+        let mut vm = VM::new(Stdlib::new());
 
         let src = r#"
             function Main.main 0
-            call Memory.init 0
-            push constant 22
-            add // the 20 should have been pushed by the Memory.init call
-            return // return to Sys.init
+            push constant 1
+            call Sys.wait 1
+            pop temp 0
+            label LOOP
+            goto LOOP
             "#;
 
         let programs = vec![SourceFile::new("Main.vm", src)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
-        let program = bytecode_parser.parse().unwrap();
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
 
-        vm.load(program);
+        for _ in 0..500 {
+            vm.step().unwrap();
+        }
 
-        for _ in 0..20 {
+        vm.reset_cycle_count();
+        vm.reset_profiler();
+        assert_eq!(0, vm.cycle_count());
+        assert_eq!(0, vm.instructions_executed());
+        assert_eq!(0, vm.builtin_steps());
+
+        // the rest of Sys.wait and then the endless loop
+        for _ in 0..1000 {
             vm.step().unwrap();
         }
 
-        assert_eq!(vm.pop(), Ok(42));
+        assert_eq!(1000, vm.cycle_count());
+        assert_eq!(1000, vm.instructions_executed() + vm.builtin_steps());
+        assert!(vm.instructions_executed() > 0);
+        assert!(vm.builtin_steps() > 0);
+
+        // cycle breakpoints are relative to the reset
+        vm.add_cycle_breakpoint(1001);
+        vm.step().unwrap();
+        assert!(vm.hit_breakpoint());
     }
 
     #[test]
-    fn test_continuing_parked_stdlib_function() {
-        let mut by_name = HashMap::new();
-        let mut by_address = HashMap::new();
-
-        fn sys_wait(_: &mut VM, state: State, params: &[Word]) -> StdResult {
-            if state == 0 {
-                if params[0] < 2 {
-                    return Ok(StdlibOk::Finished(params[0]));
-                }
-                // 2 because one tick is already used
-                return Ok(StdlibOk::ContinueInNextStep(2));
-            }
-
-            if params[0] as State > state {
-                return Ok(StdlibOk::ContinueInNextStep(state + 1));
-            }
+    fn test_breakpoint_hit_counts() {
+        let mut vm = VM::default();
 
-            Ok(StdlibOk::Finished(params[0]))
+        let bytecode = r#"
+            push constant 3
+            pop temp 0
+            label LOOP
+            push temp 0
+            push constant 1
+            sub
+            pop temp 0
+            push temp 0
+            if-goto LOOP
+            label END
+            goto END"#;
+
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+
+        // the sub inside of the loop
+        let sub = BreakpointTarget::Instruction(4);
+        vm.add_breakpoint(sub);
+        vm.add_breakpoint(BreakpointTarget::Cycle(1000));
+        vm.set_breakpoint_enabled(BreakpointTarget::Cycle(1000), false);
+
+        for iteration in 1..=3 {
+            vm.step_times(1000).unwrap();
+            assert_eq!(4, vm.pc);
+            assert_eq!(
+                BreakpointInfo {
+                    target: sub,
+                    enabled: true,
+                    hits: iteration
+                },
+                vm.breakpoints()[0]
+            );
         }
 
-        fn sys_init(vm: &mut VM, state: State, _: &[Word]) -> StdResult {
-            if state == 0 {
-                vm.call("Main.main", &[])?;
-                return Ok(StdlibOk::ContinueInNextStep(state + 1));
-            }
-            Ok(StdlibOk::ContinueInNextStep(state))
-        }
+        // the loop is over, so only the disabled cycle breakpoint could stop the vm
+        vm.step_times(1000).unwrap();
+        assert_eq!(3, vm.breakpoints()[0].hits);
+        assert_eq!(0, vm.breakpoints()[1].hits);
+        assert!(!vm.breakpoints()[1].enabled);
+    }
 
-        by_name.insert("Sys.init", u16::MAX - 1);
-        by_address.insert(
-            u16::MAX - 1,
-            BuiltinFunction::new(u16::MAX - 1, "Sys.init", "Sys", 0, &sys_init),
-        );
+    #[test]
+    fn test_file_offset_breakpoint() {
+        let sys = r#"
+            function Sys.init 0
+            call Main.main 0
+            label LOOP
+            goto LOOP"#;
+        let main = r#"
+            function Main.main 0
+            push constant 1
+            push constant 2
+            add
+            return"#;
 
-        by_name.insert("Sys.wait", u16::MAX);
-        by_address.insert(
-            u16::MAX,
-            BuiltinFunction::new(u16::MAX, "Sys.wait", "Sys", 1, &sys_wait),
+        let programs = vec![
+            SourceFile::new("Sys.vm", sys),
+            SourceFile::new("Main.vm", main),
+        ];
+        let mut vm = VM::default();
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+
+        // the add in Main.vm
+        let add = BreakpointTarget::FileOffset { file: 1, offset: 3 };
+        // the same offset in Sys.vm is never reached
+        vm.add_breakpoint(BreakpointTarget::FileOffset { file: 0, offset: 3 });
+        vm.add_breakpoint(add);
+
+        vm.step_times(1000).unwrap();
+        assert_eq!(Some(3), vm.current_file_offset());
+        assert_eq!(Some(Instruction::Add), vm.current_instruction());
+        // the add was not executed yet
+        assert_eq!(
+            Some(2),
+            vm.memory_at(vm.memory_at(SP).unwrap() as Address - 1)
         );
 
-        let stdlib = Stdlib::of(by_name, by_address);
+        // resuming executes the add and runs into the endless loop
+        assert!(vm.remove_breakpoint(add));
+        assert!(!vm.remove_breakpoint(add));
+        vm.step_times(10).unwrap();
+        assert_eq!(Some(3), vm.last_return_value());
+        assert_eq!(0, vm.breakpoints()[0].hits);
+    }
 
-        let mut vm = VM::new(stdlib.clone());
+    #[test]
+    fn test_current_key() {
+        let mut vm = VM::default();
+        assert_eq!(0, vm.current_key());
 
-        let src = r#"
-            function Main.getReturnValue 0
-            push constant 42
-            return
+        vm.set_input_key(65).unwrap();
+        assert_eq!(65, vm.current_key());
+        assert_eq!(Some(65), vm.memory_at(KBD));
 
-            function Main.main 0
-            push constant 10 // wait 10 ticks
-            call Sys.wait 1
-            push constant 2
-            call Sys.wait 1 // wait 2 more ticks
-            call Main.getReturnValue 0
-            return // return to Sys.init
-            "#;
+        vm.set_input_key(0).unwrap();
+        assert_eq!(0, vm.current_key());
+    }
 
-        let programs = vec![SourceFile::new("Main.vm", src)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
-        let program = bytecode_parser.parse().unwrap();
+    #[test]
+    fn test_key_release_delay() {
+        let mut vm = VM::default();
 
-        vm.load(program);
+        let programs = vec![SourceFile::new("Main.vm", "label LOOP\ngoto LOOP")];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+        vm.set_key_release_delay(3);
 
-        for _ in 0..100 {
+        vm.set_input_key(65).unwrap();
+        vm.step().unwrap();
+        vm.set_input_key(0).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(Ok(65), vm.mem(KBD));
             vm.step().unwrap();
         }
+        assert_eq!(Ok(65), vm.mem(KBD));
+        vm.step().unwrap();
+        assert_eq!(Ok(0), vm.mem(KBD));
 
-        assert_eq!(vm.pop(), Ok(42));
+        // pressing a new key cancels the release
+        vm.set_input_key(66).unwrap();
+        vm.set_input_key(0).unwrap();
+        vm.set_input_key(67).unwrap();
+        for _ in 0..10 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Ok(67), vm.mem(KBD));
     }
 
     #[test]
-    fn test_use_return_value_of_vm_function_in_builtin_function() {
-        let mut by_name = HashMap::new();
-        let mut by_address = HashMap::new();
+    fn test_step_until_function() {
+        let mut vm = VM::new(Stdlib::new());
 
-        fn calc(vm: &mut VM, state: State, _params: &[Word]) -> StdResult {
-            match state {
-                0 => {
-                    // technically this is wrong because the return value isn't incremented, but Main.f
-                    // will always be a VM function, so it's no problem
-                    stdlib::call_vm!(vm, state, "Main.f", &[])
-                }
-                1 => {
-                    let vm_ret = vm.pop()?;
-                    Ok(StdlibOk::Finished(vm_ret + 1))
-                }
-                _ => panic!(""),
-            }
-        }
+        let main = r#"
+            function Main.main 0
+            call Main.outer 0
+            pop temp 0
+            call Sys.halt 0
 
-        by_name.insert("Math.calc", u16::MAX - 1);
-        by_address.insert(
-            u16::MAX - 1,
-            BuiltinFunction::new(u16::MAX - 1, "Math.calc", "Math", 0, &calc),
+            function Main.outer 0
+            call Main.inner 0
+            return
+
+            function Main.inner 0
+            push constant 3
+            return
+            "#;
+
+        let sys = r#"
+            function Sys.init 0
+            call Main.main 0
+            label LOOP
+            goto LOOP
+            "#;
+
+        let programs = vec![
+            SourceFile::new("Main.vm", main),
+            SourceFile::new("Sys.vm", sys),
+        ];
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
         );
 
-        let stdlib = Stdlib::of(by_name, by_address);
+        assert_eq!(
+            Err(VMError::UnknownFunction("Main.missing".to_owned())),
+            vm.step_until_function("Main.missing")
+        );
 
-        let mut vm = VM::new(stdlib.clone());
+        // jump to Sys.init, function, call, function, call, function, call
+        // a call already enters the function
+        assert_eq!(Ok(7), vm.step_until_function("Main.inner"));
+        // already there
+        assert_eq!(Ok(0), vm.step_until_function("Main.inner"));
 
-        let src = r#"
-            function Main.main 0
-            call Math.calc 0
-            call Main.f 0
-            push constant 1
-            sub
-            call Math.calc 0
-            call Math.calc 0
-            label END
-            goto END
+        // function, push, return (to Main.outer), return (to Main.main)
+        assert_eq!(Ok(4), vm.step_until_function("Main.main"));
+        assert_eq!(
+            Some(Instruction::Pop {
+                segment: Segment::Temp,
+                index: 0
+            }),
+            vm.current_instruction()
+        );
+        assert_eq!(Ok(3), vm.mem(vm.mem(SP).unwrap() as Address - 1));
 
-            function Main.f 0
-            push constant 41
-            return
-            "#;
+        // the program halts before Main.outer is called again
+        assert_eq!(Ok(2), vm.step_until_function("Main.outer"));
+    }
+
+    #[test]
+    fn test_current_function_name_in_builtin() {
+        let mut vm = VM::new(Stdlib::new());
 
-        let programs = vec![SourceFile::new("Main.vm", src)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
-        let program = bytecode_parser.parse().unwrap();
+        let bytecode = r#"
+            function Main.main 0
+            push constant 5
+            call Sys.wait 1
+            pop temp 0
+            label LOOP
+            goto LOOP"#;
 
-        vm.load(program);
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+        vm.set_entry_function("Main.main").unwrap();
 
-        for _ in 0..30 {
+        // jump to Main.main, function, push
+        for _ in 0..3 {
             vm.step().unwrap();
         }
+        // the entry function has no frame of its own
+        assert_eq!(None, vm.current_function_name());
 
-        assert_eq!(vm.pop(), Ok(42));
-        assert_eq!(vm.pop(), Ok(42));
-        assert_eq!(vm.pop(), Ok(40));
-        assert_eq!(vm.pop(), Ok(42));
+        // the call runs the first step of Sys.wait, which then has to be continued
+        vm.step().unwrap();
+        assert_eq!(Some("Sys.wait"), vm.current_function_name());
+        vm.step().unwrap();
+        assert_eq!(Some("Sys.wait"), vm.current_function_name());
     }
 
     #[test]
-    fn test_calling_vm_from_builtin_function_multiple_times() {
-        let mut by_name = HashMap::new();
-        let mut by_address = HashMap::new();
+    fn test_replay() {
+        let bytecode = r#"
+            label LOOP
+            push constant 24576
+            pop pointer 1
+            push that 0
+            push static 0
+            add
+            pop static 0
+            goto LOOP"#;
 
-        fn sys_init(vm: &mut VM, state: State, params: &[Word]) -> StdResult {
-            match state {
-                0 => {
-                    if let VMCallOk::WasBuiltinFunction = vm.call("Memory.init", &[])? {
-                        // continue immediately
-                        sys_init(vm, state + 1, params)
-                    } else {
-                        Ok(StdlibOk::ContinueInNextStep(state + 1))
-                    }
-                }
-                1 => {
-                    if let VMCallOk::WasBuiltinFunction = vm.call("Main.main", &[])? {
-                        // continue immediately
-                        sys_init(vm, state + 1, params)
-                    } else {
-                        Ok(StdlibOk::ContinueInNextStep(state + 1))
-                    }
-                }
-                // endless loop
-                _ => Ok(StdlibOk::ContinueInNextStep(state)),
+        let load = |vm: &mut VM| {
+            let programs = vec![SourceFile::new("Main.vm", bytecode)];
+            vm.load(BytecodeParser::new(programs).parse().unwrap());
+        };
+
+        let mut recorded = VM::default();
+        load(&mut recorded);
+        recorded.set_record_replay(true);
+
+        let mut steps = 0;
+        let mut step = |vm: &mut VM, times| {
+            for _ in 0..times {
+                vm.step().unwrap();
             }
-        }
+            steps += times;
+        };
 
-        by_name.insert("Sys.init", u16::MAX);
-        by_address.insert(
-            u16::MAX,
-            BuiltinFunction::new(u16::MAX, "Sys.init", "Sys", 0, &sys_init),
+        step(&mut recorded, 5);
+        recorded.set_input_key(65).unwrap();
+        step(&mut recorded, 13);
+        recorded.set_input_key(0).unwrap();
+        step(&mut recorded, 4);
+        recorded.set_input_key(66).unwrap();
+        recorded.set_input_string("hi");
+        step(&mut recorded, 9);
+
+        let log = recorded.replay_log().unwrap().clone();
+        assert_eq!(
+            vec![
+                ReplayEvent {
+                    cycle: 5,
+                    input: ReplayInput::Key(65)
+                },
+                ReplayEvent {
+                    cycle: 18,
+                    input: ReplayInput::Key(0)
+                },
+                ReplayEvent {
+                    cycle: 22,
+                    input: ReplayInput::Key(66)
+                },
+                ReplayEvent {
+                    cycle: 22,
+                    input: ReplayInput::Text("hi".to_owned())
+                },
+            ],
+            log.events
         );
 
-        let stdlib = Stdlib::of(by_name, by_address);
+        let mut replayed = VM::default();
+        load(&mut replayed);
+        replayed.replay(log);
+        for _ in 0..steps {
+            replayed.step().unwrap();
+        }
 
-        let mut vm = VM::new(stdlib.clone());
+        assert_ne!(0, recorded.memory_at(16).unwrap());
+        assert_eq!(recorded.memory(), replayed.memory());
+        assert_eq!(recorded.queued_input(), replayed.queued_input());
+    }
 
-        let src = r#"
-            function Memory.init 0
-            push constant 0
-            pop static 0
-            push constant 2048
-            push static 0
-            add
-            push constant 14334
-            pop temp 0
+    #[test]
+    fn test_replay_clear_input() {
+        let bytecode = r#"
+            label LOOP
+            push constant 24576
             pop pointer 1
-            push temp 0
-            pop that 0
-            push constant 2049
+            push that 0
             push static 0
             add
-            push constant 2050
-            pop temp 0
-            pop pointer 1
-            push temp 0
-            pop that 0
-            push constant 0
-            return
+            pop static 0
+            goto LOOP"#;
 
-            function Main.main 0
-            call Memory.init 0
-            push constant 22
-            add
-            return // return to Sys.init
-            "#;
+        let load = |vm: &mut VM| {
+            let programs = vec![SourceFile::new("Main.vm", bytecode)];
+            vm.load(BytecodeParser::new(programs).parse().unwrap());
+        };
 
-        let programs = vec![SourceFile::new("Main.vm", src)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, stdlib);
-        let program = bytecode_parser.parse().unwrap();
+        let mut recorded = VM::default();
+        load(&mut recorded);
+        recorded.set_record_replay(true);
 
-        vm.load(program);
+        for _ in 0..3 {
+            recorded.step().unwrap();
+        }
+        recorded.set_input_string("abc");
+        for _ in 0..4 {
+            recorded.step().unwrap();
+        }
+        recorded.clear_input();
+        for _ in 0..4 {
+            recorded.step().unwrap();
+        }
 
-        for _ in 0..300 {
-            vm.step().unwrap();
+        let log = recorded.replay_log().unwrap().clone();
+        assert_eq!(
+            vec![
+                ReplayEvent {
+                    cycle: 3,
+                    input: ReplayInput::Text("abc".to_owned())
+                },
+                ReplayEvent {
+                    cycle: 7,
+                    input: ReplayInput::Clear
+                },
+            ],
+            log.events
+        );
+
+        let mut replayed = VM::default();
+        load(&mut replayed);
+        replayed.replay(log);
+        for _ in 0..11 {
+            replayed.step().unwrap();
         }
 
-        assert_eq!(vm.pop(), Ok(22));
+        assert!(replayed.queued_input().is_empty());
+        assert_eq!(recorded.memory(), replayed.memory());
     }
 
     #[test]
-    fn test_code_position_methods() {
+    fn test_in_builtin() {
         let mut vm = VM::new(Stdlib::new());
 
-        let src = r#"
+        let bytecode = r#"
             function Main.main 0
             push constant 5
-            push constant 3
-            sub
-            call String.new 1
-            call Main.doStuff 1
-            call Output.printString 1
-            return
+            call Sys.wait 1
+            pop temp 0
+            label LOOP
+            goto LOOP"#;
 
-            function Main.doStuff 1
-            push argument 0
-            pop local 0
-            push local 0
-            push constant 65
-            call String.appendChar 2
-            push constant 65
-            call String.appendChar 2
-            return
-            "#;
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        vm.load(
+            BytecodeParser::with_stdlib(programs, Stdlib::new())
+                .parse()
+                .unwrap(),
+        );
+        vm.set_entry_function("Main.main").unwrap();
 
-        let programs = vec![SourceFile::new("Main.vm", src)];
-        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
-        let program = bytecode_parser.parse().unwrap();
+        for _ in 0..3 {
+            assert!(!vm.in_builtin());
+            vm.step().unwrap();
+        }
+        assert!(!vm.in_builtin());
 
-        vm.load(program);
+        // Sys.wait yields after its first step
+        vm.step().unwrap();
+        assert!(vm.in_builtin());
 
-        // skip Sys.init
-        for _ in 0..7 {
+        while vm.in_builtin() {
             vm.step().unwrap();
         }
+        assert_eq!(
+            Some(Instruction::Pop {
+                segment: Segment::Temp,
+                index: 0
+            }),
+            vm.current_instruction()
+        );
+    }
 
-        assert_eq!(vm.pc, 1);
+    #[test]
+    fn test_with_memory() {
+        let mut memory = Box::new([0; MEM_SIZE]);
+        memory[SP] = 300;
+        memory[LCL] = 300;
+        memory[300] = 17;
+        memory[SCREEN_START] = -1;
+
+        let mut vm = VM::with_memory(Stdlib::new(), memory);
+        assert_eq!(Some(300), vm.memory_at(SP));
+        assert_eq!(Some(17), vm.memory_at(300));
+        assert_eq!(Some(-1), vm.memory_at(SCREEN_START));
+
+        let bytecode = "push local 0\npush constant 1\nadd";
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        vm.load_preserving_memory(BytecodeParser::new(programs).parse().unwrap());
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(Some(301), vm.memory_at(SP));
+        assert_eq!(Some(18), vm.memory_at(300));
+        assert_eq!(Some(-1), vm.memory_at(SCREEN_START));
+
+        // a normal load still starts from a clean memory
+        let programs = vec![SourceFile::new("Main.vm", bytecode)];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
+        assert_eq!(Some(INIT_SP), vm.memory_at(SP));
+        assert_eq!(Some(0), vm.memory_at(SCREEN_START));
+    }
 
-        // push constants
-        vm.step().unwrap();
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Main.main"));
-        assert_eq!(vm.locals(), Some(&[][..]));
-        assert_eq!(vm.args(), Some(&[][..]));
-        assert_eq!(vm.stack(), Some(&[5, 3][..]));
+    #[test]
+    fn test_bytecode_sys_init_is_entered() {
+        let main = r#"
+            function Main.main 0
+            push constant 1
+            pop temp 0
+            return
+            "#;
 
-        // sub
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Main.main"));
-        assert_eq!(vm.locals(), Some(&[][..]));
-        assert_eq!(vm.args(), Some(&[][..]));
-        assert_eq!(vm.stack(), Some(&[2][..]));
+        let sys = r#"
+            function Sys.init 0
+            push constant 2
+            pop temp 1
+            label LOOP
+            goto LOOP
+            "#;
 
-        // call String.new
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("String.new"));
-        assert_eq!(vm.locals(), None);
-        assert_eq!(vm.args(), Some(&[2][..]));
-        assert_eq!(vm.stack(), None);
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Main.main"));
-        assert_eq!(vm.locals(), Some(&[][..]));
-        assert_eq!(vm.args(), Some(&[][..]));
-        assert_eq!(vm.stack().map(|s| s.len()), Some(1));
-        let s = vm.stack().unwrap()[0];
-        assert_eq!(s > HEAP_START as Word, true);
+        // Sys.init behind another function and as the very first function of the program
+        for programs in [
+            vec![
+                SourceFile::new("Main.vm", main),
+                SourceFile::new("Sys.vm", sys),
+            ],
+            vec![
+                SourceFile::new("Sys.vm", sys),
+                SourceFile::new("Main.vm", main),
+            ],
+        ] {
+            let mut vm = VM::default();
+            vm.load(BytecodeParser::new(programs).parse().unwrap());
+
+            for _ in 0..10 {
+                vm.step().unwrap();
+            }
 
-        // call Main.doStuff
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Main.doStuff"));
-        assert_eq!(vm.locals(), Some(&[0][..]));
-        assert_eq!(vm.args(), Some(&[s][..]));
-        assert_eq!(vm.stack(), Some(&[][..]));
+            assert_eq!(Ok(0), vm.mem(5));
+            assert_eq!(Ok(2), vm.mem(6));
+            // stuck in the endless loop of Sys.init
+            assert!(matches!(
+                vm.current_instruction(),
+                Some(Instruction::Goto { .. })
+            ));
+        }
+    }
 
-        vm.step().unwrap();
-        vm.step().unwrap();
-        vm.step().unwrap();
-        vm.step().unwrap();
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Main.doStuff"));
-        assert_eq!(vm.locals(), Some(&[s][..]));
-        assert_eq!(vm.args(), Some(&[s][..]));
-        assert_eq!(vm.stack(), Some(&[s, 65][..]));
+    #[test]
+    fn test_instruction_at() {
+        let mut vm = VM::default();
+        assert_eq!(None, vm.instruction_at(0));
 
-        vm.step().unwrap();
-        vm.step().unwrap();
-        vm.step().unwrap();
-        vm.step().unwrap();
+        let programs = vec![SourceFile::new("Main.vm", "push constant 7\nneg")];
+        vm.load(BytecodeParser::new(programs).parse().unwrap());
 
-        // call Output.printString
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Output.printString"));
-        assert_eq!(vm.locals(), None);
-        assert_eq!(vm.args(), Some(&[s][..]));
-        assert_eq!(vm.stack(), None);
+        assert_eq!(
+            Some(Instruction::Push {
+                segment: Segment::Constant,
+                index: 7
+            }),
+            vm.instruction_at(0)
+        );
+        assert_eq!(Some(Instruction::Neg), vm.instruction_at(1));
+        assert_eq!(None, vm.instruction_at(2));
+    }
 
-        vm.step().unwrap();
-        vm.step().unwrap();
-        vm.step().unwrap();
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Output.printString"));
-        assert_eq!(vm.locals(), None);
-        assert_eq!(vm.args(), Some(&[s][..]));
-        assert_eq!(vm.stack(), None);
+    #[test]
+    fn test_disassembly_window() {
+        let mut vm = VM::default();
 
-        vm.step().unwrap();
-        assert_eq!(vm.current_function_name(), Some("Main.main"));
-        assert_eq!(vm.locals(), Some(&[][..]));
-        assert_eq!(vm.args(), Some(&[][..]));
-        assert_eq!(vm.stack(), Some(&[0][..]));
+        let bytecode = r#"
+            push constant 1
+            push constant 2
+            add
+            push constant 3
+            sub
+            neg"#;
+
+        let programs = vec![SourceFile::new("Window.vm", bytecode)];
+        let program = BytecodeParser::new(programs).parse().unwrap();
+        vm.load(program);
+        vm.set_mem(0, 256).unwrap();
+
+        assert_eq!(
+            vec![
+                (0, "push constant 1".to_owned(), true),
+                (1, "push constant 2".to_owned(), false),
+            ],
+            vm.disassembly_window(0, 1)
+        );
+
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                (1, "push constant 2".to_owned(), false),
+                (2, "add".to_owned(), false),
+                (3, "push constant 3".to_owned(), true),
+                (4, "sub".to_owned(), false),
+                (5, "neg".to_owned(), false),
+            ],
+            vm.disassembly_window(3, 2)
+        );
+        assert_eq!(
+            vec![(4, "sub".to_owned(), false), (5, "neg".to_owned(), false),],
+            vm.disassembly_window(5, usize::MAX)[4..]
+        );
     }
 }
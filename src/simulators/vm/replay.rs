@@ -0,0 +1,27 @@
+use crate::definitions::Word;
+use serde::{Deserialize, Serialize};
+
+/// Input is the only nondeterminism in the vm, so a program can be replayed exactly by
+/// applying the same input at the same cycles
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub enum ReplayInput {
+    /// a key press (or release if the key is 0) via set_input_key
+    Key(Word),
+    /// a string typed via set_input_string
+    Text(String),
+    /// dropping all queued input via clear_input
+    Clear,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayEvent {
+    /// the number of steps the vm had executed when the input happened
+    pub cycle: u64,
+    pub input: ReplayInput,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayLog {
+    pub events: Vec<ReplayEvent>,
+}
@@ -1,8 +1,9 @@
-use crate::definitions::Word;
 use crate::parse::script::lexer::{ident_kind, int_kind, Token};
 use crate::parse::script::parser::ScriptParser;
 use crate::parse::script::tst::{Command, CommandKind, VMEmulatorCommand, VMSetTarget};
-use crate::parse::script::{CmdResult, ParseError, ParseResult, SimulatorCommandParser};
+use crate::parse::script::{
+    parse_index, set_value, CmdResult, ParseError, ParseResult, SimulatorCommandParser,
+};
 use crate::parse::Spanned;
 
 mod run;
@@ -59,7 +60,10 @@ impl<'tst> SimulatorCommandParser<VMEmulatorCommand>
 
                 let value = self.consume_token_kind(int_kind())?;
                 let value = if let Token::IntLiteral(value) = value.content {
-                    value as Word
+                    set_value(value).ok_or_else(|| ParseError::ValueOutOfRange {
+                        command: ident.content.to_string(),
+                        value,
+                    })?
                 } else {
                     unreachable!()
                 };
@@ -89,9 +93,8 @@ pub fn parse_set_target(ident: &str) -> ParseResult<VMSetTarget> {
                 return Err(ParseError::InvalidSetTarget(ident.to_string()));
             }
 
-            (&ident[(opening_index + 1)..closing_index])
-                .parse::<usize>()
-                .map_err(|_| ParseError::InvalidSetTarget(ident.to_string()))?
+            parse_index(&ident[(opening_index + 1)..closing_index])
+                .ok_or_else(|| ParseError::InvalidSetTarget(ident.to_string()))?
         }};
     }
 
@@ -135,7 +138,7 @@ pub fn parse_set_target(ident: &str) -> ParseResult<VMSetTarget> {
         "line" => no_index!(VMSetTarget::Line),
         "temp" => req_index!(VMSetTarget::Temp),
         "RAM" => req_index!(VMSetTarget::Ram),
-        _ => todo!(),
+        _ => Err(ParseError::InvalidSetTarget(ident.to_string())),
     }
 }
 
@@ -171,6 +174,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parser_reports_unknown_set_target() {
+        let mut parser = ScriptParser::<VMEmulatorCommandParser, VMEmulatorCommand>::new(
+            Path::new("Test.tst"),
+            "set bogus 5;",
+        );
+        assert_eq!(
+            Some(Err(ParseError::InvalidSetTarget("bogus".to_string()))),
+            parser.next()
+        );
+    }
+
     #[test]
     fn test_parser_load_vm_file() {
         let parser = ScriptParser::<VMEmulatorCommandParser, VMEmulatorCommand>::new(
@@ -215,4 +230,76 @@ mod tests {
             parser.consume_token_kind(int_kind())
         );
     }
+
+    #[test]
+    fn test_parser_set_value_out_of_range() {
+        let mut parser = ScriptParser::<VMEmulatorCommandParser, VMEmulatorCommand>::new(
+            Path::new("Test.tst"),
+            "set RAM[0] 70000,",
+        );
+        assert_eq!(
+            Some(Err(ParseError::ValueOutOfRange {
+                command: "set".to_string(),
+                value: 70000
+            })),
+            parser.next()
+        );
+    }
+
+    #[test]
+    fn test_parser_set_unsigned_16_bit_value() {
+        let parser = ScriptParser::<VMEmulatorCommandParser, VMEmulatorCommand>::new(
+            Path::new("Test.tst"),
+            "set RAM[0] %XFFFF, set RAM[1] %B1111111111111111, set RAM[2] -32768,",
+        );
+        let values: Vec<_> = parser
+            .map(|cmd| match cmd.unwrap().content.kind {
+                CommandKind::Simulator(VMEmulatorCommand::Set(_, value)) => value,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(vec![-1, -1, -32768], values);
+    }
+
+    #[test]
+    fn test_parse_set_target_with_index_expression() {
+        assert_eq!(Ok(VMSetTarget::Ram(260)), parse_set_target("RAM[256+4]"));
+        assert_eq!(Ok(VMSetTarget::Ram(252)), parse_set_target("RAM[256-4]"));
+        assert_eq!(
+            Ok(VMSetTarget::Local(Some(3))),
+            parse_set_target("local[1+1+1]")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidSetTarget("RAM[4-5]".to_string())),
+            parse_set_target("RAM[4-5]")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidSetTarget("RAM[256+]".to_string())),
+            parse_set_target("RAM[256+]")
+        );
+        // terms that don't fit into an isize are an error instead of wrapping around
+        assert_eq!(
+            Err(ParseError::InvalidSetTarget(
+                "RAM[0-9223372036854775808]".to_string()
+            )),
+            parse_set_target("RAM[0-9223372036854775808]")
+        );
+
+        let parser = ScriptParser::<VMEmulatorCommandParser, VMEmulatorCommand>::new(
+            Path::new("Test.tst"),
+            "set RAM[256+4] 7;",
+        );
+        assert_eq!(
+            vec![Ok(Spanned::new(
+                0,
+                17,
+                1,
+                Command::terminated(
+                    CommandKind::Simulator(VMEmulatorCommand::Set(VMSetTarget::Ram(260), 7)),
+                    Terminator::SingleStep
+                )
+            ))],
+            parser.collect::<Vec<_>>()
+        );
+    }
 }
@@ -95,4 +95,13 @@ impl SimulatorExecutor<VMEmulatorCommand> for VM {
         };
         Ok(())
     }
+
+    fn clear_screen(&mut self) -> ExecResult {
+        VM::clear_screen(self);
+        Ok(())
+    }
+
+    fn clear_output(&mut self) -> ExecResult {
+        Ok(VM::clear_output(self)?)
+    }
 }
@@ -11,6 +11,8 @@ pub enum StdlibError {
     IncorrectNumberOfArgs,
     CallingNonExistendFunction,
     ContinuingFinishedFunction,
+    // calling Output/Screen/Keyboard with Stdlib::compute_only
+    PeripheralDisabled(&'static str),
 
     // this needs to be a box, because VMError and StdlibError have a circular relationship
     VMError(Box<VMError>),
@@ -19,8 +21,7 @@ pub enum StdlibError {
     SysError(Word), // returned by the Sys.err function
     SysWaitNegativeDuration,
 
-    // Math.vm errors
-    MathDivideByZero,
+    // Math.vm errors (division by zero is reported as VMError::DivisionByZero)
     MathNegativeSqrt,
 
     // Memory.vm errors
@@ -47,6 +48,16 @@ pub enum StdlibError {
     OutputBlockedFirstInWordMutex,
     OutputBlockedWordInLineMutex,
     OutputMoveCursorIllegalPosition,
+    // recoverable, the character is drawn as a filled block like in the official OS
+    OutputCharWithoutGlyph(Word),
+}
+
+impl StdlibError {
+    /// Recoverable errors are only recorded in the stdlib log of the vm (see
+    /// VM::take_stdlib_log) and the execution continues
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Self::OutputCharWithoutGlyph(_))
+    }
 }
 
 impl From<VMError> for StdlibError {
@@ -76,6 +87,15 @@ const VM_ERRORS: [&str; 18] = [
     "Illegal cursor location",
 ];
 
+/// The description of a Sys.error code, None for unknown codes
+pub fn sys_error_message(code: Word) -> Option<&'static str> {
+    if (1..VM_ERRORS.len() as Word).contains(&code) {
+        Some(VM_ERRORS[code as usize])
+    } else {
+        None
+    }
+}
+
 impl fmt::Display for StdlibError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -85,16 +105,15 @@ impl fmt::Display for StdlibError {
                 write!(f, "Trying to call non existing stdlib function")
             }
             Self::ContinuingFinishedFunction => write!(f, "Trying to continue finished function"),
-            Self::VMError(vm_error) => write!(f, "{}", vm_error),
-            Self::SysError(error) => {
-                if (1..18).contains(error) {
-                    write!(f, "{}", VM_ERRORS[*error as usize])
-                } else {
-                    write!(f, "Unknown error code: {}", error)
-                }
+            Self::PeripheralDisabled(class) => {
+                write!(f, "The {} peripheral is disabled in this simulator", class)
             }
+            Self::VMError(vm_error) => write!(f, "{}", vm_error),
+            Self::SysError(error) => match sys_error_message(*error) {
+                Some(message) => write!(f, "{}", message),
+                None => write!(f, "Unknown error code: {}", error),
+            },
             Self::SysWaitNegativeDuration => write!(f, "{}", VM_ERRORS[1]),
-            Self::MathDivideByZero => write!(f, "{}", VM_ERRORS[3]),
             Self::MathNegativeSqrt => write!(f, "{}", VM_ERRORS[4]),
             Self::MemoryAllocNonPositiveSize => write!(f, "{}", VM_ERRORS[5]),
             Self::MemoryHeapOverflow => write!(f, "{}", VM_ERRORS[6]),
@@ -123,6 +142,9 @@ impl fmt::Display for StdlibError {
                 "Blocked word_in_line mutex in Screen, this should be impossible"
             ),
             Self::OutputMoveCursorIllegalPosition => write!(f, "{}", VM_ERRORS[17]),
+            Self::OutputCharWithoutGlyph(c) => {
+                write!(f, "Character {} cannot be printed, it has no glyph", c)
+            }
         }
     }
 }
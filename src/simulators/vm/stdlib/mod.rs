@@ -14,7 +14,7 @@ mod os_sys;
 
 use crate::definitions::{Address, Symbol, Word};
 use crate::simulators::vm::VM;
-pub use error::StdlibError;
+pub use error::{sys_error_message, StdlibError};
 use std::collections::HashMap;
 use std::fmt;
 
@@ -145,6 +145,34 @@ impl Stdlib {
         }
     }
 
+    /// A stdlib without any peripherals for programs that only compute something
+    ///
+    /// Calling a function of Output, Screen or Keyboard results in an error. Their init
+    /// functions still exist, because Sys.init calls them before Main.main
+    pub fn compute_only() -> Self {
+        let mut stdlib = Self::new();
+
+        let peripherals: Vec<_> = stdlib
+            .by_address
+            .values()
+            .filter(|f| PERIPHERALS.contains(&f.file()))
+            .copied()
+            .collect();
+
+        for builtin in peripherals {
+            let function: &'static dyn Fn(&mut VM, State, &[Word]) -> StdResult =
+                match (builtin.file(), builtin.name().ends_with(".init")) {
+                    (_, true) => &disabled_init,
+                    ("Output", _) => &disabled_output,
+                    ("Screen", _) => &disabled_screen,
+                    _ => &disabled_keyboard,
+                };
+            stdlib.register(builtin.name(), builtin.file(), builtin.num_args(), function);
+        }
+
+        stdlib
+    }
+
     pub fn by_address(&self, function: Symbol) -> Option<&BuiltinFunction> {
         self.by_address.get(&function)
     }
@@ -158,6 +186,35 @@ impl Stdlib {
     pub fn by_name(&self) -> &HashMap<&'static str, Symbol> {
         &self.by_name
     }
+
+    /// Add a new builtin function or replace the implementation of an existing one
+    ///
+    /// New functions get the next free virtual address below the already used ones
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        file: &'static str,
+        num_args: usize,
+        function: &'static dyn Fn(&mut VM, State, &[Word]) -> StdResult,
+    ) -> Symbol {
+        let address = self.by_name.get(name).copied().unwrap_or_else(|| {
+            self.by_address
+                .keys()
+                .min()
+                .map(|lowest| lowest - 1)
+                .unwrap_or(Symbol::MAX)
+        });
+
+        let builtin = BuiltinFunction::new(address, name, file, num_args, function);
+        self.by_address.insert(address, builtin);
+        self.by_name.insert(name, address);
+        address
+    }
+
+    /// The bitmap that Output.printChar uses to draw the character with the given code
+    pub fn char_glyph(code: u32) -> Option<&'static [Word; 11]> {
+        os_output::char_glyph(code)
+    }
 }
 
 #[cfg(test)]
@@ -177,6 +234,29 @@ impl Stdlib {
     }
 }
 
+/// Move the cursor of the builtin Output class back to the top left corner
+pub fn reset_output_cursor(vm: &mut VM) -> Result<(), StdlibError> {
+    os_output::init(vm, 0, &[]).map(|_| ())
+}
+
+const PERIPHERALS: [&str; 3] = ["Output", "Screen", "Keyboard"];
+
+fn disabled_init(_vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
+    Ok(StdlibOk::Finished(0))
+}
+
+fn disabled_output(_vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
+    Err(StdlibError::PeripheralDisabled("Output"))
+}
+
+fn disabled_screen(_vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
+    Err(StdlibError::PeripheralDisabled("Screen"))
+}
+
+fn disabled_keyboard(_vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
+    Err(StdlibError::PeripheralDisabled("Keyboard"))
+}
+
 fn stdlib() -> (
     HashMap<&'static str, Symbol>,
     HashMap<Symbol, BuiltinFunction>,
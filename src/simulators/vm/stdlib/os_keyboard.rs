@@ -18,14 +18,19 @@ pub fn read_char(vm: &mut VM, state: State, _params: &[Word]) -> StdResult {
         1 => {
             let key = vm.mem(KBD)?;
             // stay in this state until the key state was 0 (don't allow holding the key)
-            if key != 0 {
+            // queued keys don't need to be released
+            if key != 0 && !vm.has_queued_input() {
                 Ok(StdlibOk::ContinueInNextStep(state))
             } else {
                 Ok(StdlibOk::ContinueInNextStep(state + 1))
             }
         }
         2 => {
-            let key = vm.mem(KBD)?;
+            // keys typed with set_input_string take precedence over the keyboard
+            let key = match vm.next_queued_key() {
+                Some(key) => key,
+                None => vm.mem(KBD)?,
+            };
             // stay in this state until the user presses a key
             if key == 0 {
                 Ok(StdlibOk::ContinueInNextStep(state))
@@ -54,6 +59,13 @@ pub fn read_char(vm: &mut VM, state: State, _params: &[Word]) -> StdResult {
     }
 }
 
+/// Jack strings only contain ascii, so readLine only appends printable ascii characters
+/// Everything else (e.g. arrow keys or non ascii characters) is erased from the screen again and
+/// otherwise ignored
+fn is_line_char(c: Word) -> bool {
+    (32..=126).contains(&c)
+}
+
 pub fn read_line(vm: &mut VM, state: State, params: &[Word]) -> StdResult {
     // use the upper 16 bits for the string address and the lower 16 bits for the actual state
     let string_s = (state >> 16) & 0xFFFF;
@@ -85,20 +97,35 @@ pub fn read_line(vm: &mut VM, state: State, params: &[Word]) -> StdResult {
             match c {
                 NEWLINE_KEY => Ok(StdlibOk::Finished(line)),
                 BACKSPACE_KEY => {
-                    vm.call("String.eraseLastChar", &[line])?;
+                    vm.call("String.length", &[line])?;
+                    Ok(StdlibOk::ContinueInNextStep((string_s << 16) | 6))
+                }
+                c if is_line_char(c) => {
+                    vm.call("String.appendChar", &[line, c])?;
                     Ok(StdlibOk::ContinueInNextStep((string_s << 16) | 5))
                 }
                 _ => {
-                    vm.call("String.appendChar", &[line, c])?;
+                    // remove the character that readChar printed
+                    vm.call("Output.backSpace", &[])?;
                     Ok(StdlibOk::ContinueInNextStep((string_s << 16) | 5))
                 }
             }
         }
         5 => {
-            vm.pop()?; // String.eraseLastChar or String.appendChar
+            vm.pop()?; // String.eraseLastChar, String.appendChar or Output.backSpace
             vm.call("Keyboard.readChar", &[])?;
             Ok(StdlibOk::ContinueInNextStep((string_s << 16) | 4))
         }
+        6 => {
+            // like the official implementation, a backspace at the start of the line is ignored
+            if vm.pop()? > 0 {
+                vm.call("String.eraseLastChar", &[line])?;
+                Ok(StdlibOk::ContinueInNextStep((string_s << 16) | 5))
+            } else {
+                vm.call("Keyboard.readChar", &[])?;
+                Ok(StdlibOk::ContinueInNextStep((string_s << 16) | 4))
+            }
+        }
         _ => unreachable!("reached unreachable state in read_line {}", state),
     }
 }
@@ -1,12 +1,13 @@
 use super::*;
-use crate::simulators::vm::VM;
+use crate::simulators::vm::{checked_divide, VM};
 
 pub fn init(_vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
     Ok(StdlibOk::Finished(0))
 }
 
 pub fn abs(_vm: &mut VM, _: State, params: &[Word]) -> StdResult {
-    Ok(StdlibOk::Finished(params[0].abs()))
+    // -32768 has no positive counterpart, the hack platform just wraps around
+    Ok(StdlibOk::Finished(params[0].wrapping_abs()))
 }
 
 pub fn multiply(_vm: &mut VM, _: State, params: &[Word]) -> StdResult {
@@ -17,11 +18,7 @@ pub fn multiply(_vm: &mut VM, _: State, params: &[Word]) -> StdResult {
 }
 
 pub fn divide(_vm: &mut VM, _: State, params: &[Word]) -> StdResult {
-    if params[1] != 0 {
-        Ok(StdlibOk::Finished(params[0] / params[1]))
-    } else {
-        Err(StdlibError::MathDivideByZero)
-    }
+    Ok(StdlibOk::Finished(checked_divide(params[0], params[1])?))
 }
 
 pub fn min(_vm: &mut VM, _: State, params: &[Word]) -> StdResult {
@@ -44,6 +41,7 @@ pub fn sqrt(_vm: &mut VM, _: State, params: &[Word]) -> StdResult {
 mod tests {
     use super::*;
     use crate::parse::bytecode::{BytecodeParser, SourceFile};
+    use crate::simulators::vm::VMError;
 
     // this test comes from the MathTest directory in project 12
     #[test]
@@ -267,4 +265,75 @@ mod tests {
         assert_eq!(Ok(27), vm.mem(8012));
         assert_eq!(Ok(32767), vm.mem(8013));
     }
+
+    #[test]
+    fn test_divide_by_zero() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let test = r#"
+            function Main.main 0
+            push constant 5
+            push constant 0
+            call Math.divide 2
+            label LOOP
+            goto LOOP
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", test)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
+        vm.load(program);
+
+        // skip Sys.init and push the arguments
+        for _ in 0..9 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(
+            Err(VMError::DivisionByZero {
+                dividend: 5,
+                divisor: 0
+            }),
+            vm.step()
+        );
+    }
+
+    #[test]
+    fn test_abs() {
+        let mut vm = VM::new(Stdlib::new());
+
+        for (input, expected) in [(5, 5), (-5, 5), (0, 0), (32767, 32767), (-32768, -32768)] {
+            let result = abs(&mut vm, 0, &[input]);
+            assert!(
+                matches!(result, Ok(StdlibOk::Finished(v)) if v == expected),
+                "abs({})",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_divide_signs_match_the_book() {
+        // the book divides the absolute values and negates the result if the signs differ,
+        // which rounds towards zero
+        let mut vm = VM::default();
+        let mut div = |a, b| match divide(&mut vm, 0, &[a, b]) {
+            Ok(StdlibOk::Finished(result)) => Ok(result),
+            Ok(StdlibOk::ContinueInNextStep(_)) => unreachable!("divide always finishes"),
+            Err(e) => Err(e),
+        };
+
+        assert_eq!(Ok(3), div(7, 2));
+        assert_eq!(Ok(-3), div(-7, 2));
+        assert_eq!(Ok(-3), div(7, -2));
+        assert_eq!(Ok(3), div(-7, -2));
+        assert_eq!(Ok(i16::MIN), div(i16::MIN, -1));
+        assert_eq!(
+            Err(StdlibError::VMError(Box::new(VMError::DivisionByZero {
+                dividend: 1,
+                divisor: 0
+            }))),
+            div(1, 0)
+        );
+    }
 }
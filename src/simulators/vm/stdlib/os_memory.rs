@@ -1,11 +1,12 @@
 use super::*;
 use crate::simulators::vm::VM;
 
-use crate::definitions::{HEAP_END, HEAP_START};
+use crate::definitions::HEAP_START;
 
 pub fn init(vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
-    vm.set_mem(HEAP_START, ((HEAP_END + 1) - (HEAP_START + 2)) as Word)?;
-    vm.set_mem(HEAP_START + 1, HEAP_END as Word + 1)?;
+    let heap_end = vm.heap_end();
+    vm.set_mem(HEAP_START, ((heap_end + 1) - (HEAP_START + 2)) as Word)?;
+    vm.set_mem(HEAP_START + 1, heap_end as Word + 1)?;
 
     Ok(StdlibOk::Finished(0))
 }
@@ -25,9 +26,17 @@ pub fn alloc(vm: &mut VM, _: State, params: &[Word]) -> StdResult {
         return Err(StdlibError::MemoryAllocNonPositiveSize);
     }
 
+    // Memory.init never sets a next pointer to 0, so the heap was never initialized
+    // This happens if Sys.init is implemented in bytecode and doesn't call Memory.init
+    // Without this, the walk below would follow the 0 and allocate on top of SP
+    if vm.mem(HEAP_START + 1)? == 0 {
+        init(vm, 0, &[])?;
+    }
+
+    let heap_end = vm.heap_end();
     let mut seg_addr = HEAP_START;
     let mut seg_cap = 0;
-    while seg_addr <= HEAP_END {
+    while (HEAP_START..=heap_end).contains(&seg_addr) {
         seg_cap = vm.mem(seg_addr)? as usize;
         if seg_cap >= size {
             break;
@@ -35,7 +44,7 @@ pub fn alloc(vm: &mut VM, _: State, params: &[Word]) -> StdResult {
         seg_addr = vm.mem(seg_addr + 1)? as usize;
     }
 
-    if seg_addr > HEAP_END {
+    if !(HEAP_START..=heap_end).contains(&seg_addr) {
         return Err(StdlibError::MemoryHeapOverflow);
     }
 
@@ -55,7 +64,7 @@ pub fn de_alloc(vm: &mut VM, _: State, params: &[Word]) -> StdResult {
     let next_seg_addr = vm.mem(seg_addr + 1)? as usize;
 
     let next_cap = vm.mem(next_seg_addr)? as usize;
-    if next_seg_addr > HEAP_END || next_cap == 0 {
+    if next_seg_addr > vm.heap_end() || next_cap == 0 {
         vm.set_mem(seg_addr, (next_seg_addr - seg_addr - 2) as Word)?;
     } else {
         vm.set_mem(seg_addr, (next_seg_addr - seg_addr + next_cap) as Word)?;
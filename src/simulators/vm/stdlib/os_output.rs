@@ -125,6 +125,12 @@ lazy_static! {
     };
 }
 
+/// The 11 rows of the bitmap used to draw a character
+/// Only the printable ascii characters (32-126) and the cursor (0) have a glyph
+pub fn char_glyph(c: u32) -> Option<&'static [Word; 11]> {
+    MAP.get(&c).copied()
+}
+
 pub fn init(_vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
     set_mutex!(WORD_IN_LINE, 0, OutputBlockedWordInLineMutex);
     set_mutex!(ADDRESS, START_ADDRESS, OutputBlockedAddressMutex);
@@ -207,6 +213,9 @@ fn print_char_impl(vm: &mut VM, c: Word) -> Result<(), StdlibError> {
         NEWLINE_KEY => println_impl()?,
         BACKSPACE_KEY => backspace_impl(vm)?,
         _ => {
+            if !(32..127).contains(&c) {
+                vm.report_stdlib_error(StdlibError::OutputCharWithoutGlyph(c))?;
+            }
             draw_char(vm, c as u8 as char)?;
 
             let mut address = get_mutex!(ADDRESS, OutputBlockedAddressMutex);
@@ -329,3 +338,109 @@ pub fn backspace(vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
     backspace_impl(vm)?;
     Ok(StdlibOk::Finished(0))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::bytecode::{BytecodeParser, SourceFile};
+
+    #[test]
+    fn test_char_glyph() {
+        assert_eq!(
+            Some(&[12, 30, 51, 51, 63, 51, 51, 51, 51, 0, 0]),
+            char_glyph('A' as u32)
+        );
+        assert_eq!(
+            Some(&[63, 63, 63, 63, 63, 63, 63, 63, 63, 0, 0]),
+            char_glyph(0)
+        );
+        assert_eq!(None, char_glyph(127));
+        assert_eq!(None, char_glyph(NEWLINE_KEY as u32));
+    }
+
+    #[test]
+    fn test_print_string_with_newline() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
+            push constant 3
+            call String.new 1
+            push constant 65
+            call String.appendChar 2
+            push constant 128
+            call String.appendChar 2
+            push constant 66
+            call String.appendChar 2
+            call Output.printString 1
+            pop temp 0
+            label LOOP
+            goto LOOP
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        let program = bytecode_parser.parse().unwrap();
+        vm.load(program);
+
+        for _ in 0..100 {
+            vm.step().unwrap();
+        }
+
+        let glyph_at = |address: usize| {
+            (0..11)
+                .map(|row| vm.memory_at(SCREEN_START + address + row * (SCREEN_WIDTH >> 4)))
+                .map(|word| word.unwrap() & 0xFF)
+                .collect::<Vec<_>>()
+        };
+
+        // A in the first column of the first line and B in the first column of the second one
+        let a = START_ADDRESS;
+        let b = START_ADDRESS + 11 * (SCREEN_WIDTH >> 4);
+        assert_eq!(&glyph_at(a)[..], &char_glyph('A' as u32).unwrap()[..]);
+        assert_eq!(&glyph_at(b)[..], &char_glyph('B' as u32).unwrap()[..]);
+        // the newline itself is not drawn
+        assert_eq!(Some(0), vm.memory_at(SCREEN_START + a).map(|w| w >> 8));
+    }
+
+    #[test]
+    fn test_sys_error_prints_message() {
+        let mut vm = VM::new(Stdlib::new());
+
+        let src = r#"
+            function Main.main 0
+            push constant 2
+            call Sys.error 1
+            "#;
+
+        let programs = vec![SourceFile::new("Main.vm", src)];
+        let mut bytecode_parser = BytecodeParser::with_stdlib(programs, Stdlib::new());
+        vm.load(bytecode_parser.parse().unwrap());
+
+        let err = (0..1000).find_map(|_| vm.step().err());
+        assert_eq!(
+            Some(crate::simulators::vm::VMError::StdlibError(
+                StdlibError::SysError(2)
+            )),
+            err
+        );
+
+        // two characters share one word, the first one is in the lower byte
+        let glyph_at = |index: usize| {
+            let (address, shift) = (START_ADDRESS + index / 2, (index % 2) * 8);
+            (0..11)
+                .map(|row| vm.memory_at(SCREEN_START + address + row * (SCREEN_WIDTH >> 4)))
+                .map(|word| (word.unwrap() >> shift) & 0xFF)
+                .collect::<Vec<_>>()
+        };
+
+        for (index, c) in "ERR2: Array size must be positive".chars().enumerate() {
+            assert_eq!(
+                &glyph_at(index)[..],
+                &char_glyph(c as u32).unwrap()[..],
+                "{}",
+                c
+            );
+        }
+    }
+}
@@ -38,8 +38,29 @@ pub fn halt(_vm: &mut VM, _: State, _params: &[Word]) -> StdResult {
     Err(StdlibError::Halt)
 }
 
-pub fn error(_vm: &mut VM, _: State, params: &[Word]) -> StdResult {
-    Err(StdlibError::SysError(params[0]))
+/// Prints "ERR<code>: <description>" (or just "ERR<code>" for unknown codes) and halts
+/// The state is the index of the next character to print
+pub fn error(vm: &mut VM, state: State, params: &[Word]) -> StdResult {
+    let code = params[0];
+    let message = match sys_error_message(code) {
+        Some(description) => format!("ERR{}: {}", code, description),
+        None => format!("ERR{}", code),
+    };
+
+    if state > 0 {
+        // the return value of the last printChar
+        vm.pop()?;
+    }
+
+    if let Some(&c) = message.as_bytes().get(state as usize) {
+        // without the Output peripheral the code can only be reported as the error itself
+        if vm.call("Output.printChar", &[c as Word]).is_err() {
+            return Err(StdlibError::SysError(code));
+        }
+        return Ok(StdlibOk::ContinueInNextStep(state + 1));
+    }
+
+    Err(StdlibError::SysError(code))
 }
 
 pub fn wait(_vm: &mut VM, state: State, params: &[Word]) -> StdResult {
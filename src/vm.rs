@@ -1,11 +1,15 @@
 use parse::bytecode::{BytecodeParser, SourceFile};
+use parse::script::lexer::{Lexer, Token};
 use parse::script::parser::ScriptParser;
-use simulators::execute_script;
+use simulators::cpu::Cpu;
 use simulators::vm::stdlib::Stdlib;
 use simulators::vm::VM;
+use simulators::{execute_script, ScriptError};
 
 mod definitions;
 mod keyboard;
+#[cfg_attr(not(feature = "desktop"), allow(dead_code))]
+mod pacing;
 mod parse;
 mod simulators;
 
@@ -16,14 +20,16 @@ use std::io::{self, Write};
 use std::path::PathBuf;
 
 #[cfg(feature = "desktop")]
-fn run(vm: &mut VM, steps_per_tick: usize) {
+fn run(vm: &mut VM, steps_per_tick: usize, fps: Option<u32>) {
     use definitions::{SCREEN_HEIGHT, SCREEN_WIDTH};
     use keyboard::get_key_code;
+    use pacing::FramePacer;
     use sdl2::event::Event;
     use sdl2::keyboard::Keycode;
     use sdl2::pixels::{Color, PixelFormatEnum};
     use simulators::vm::error::VMError;
     use simulators::vm::stdlib::StdlibError;
+    use std::time::Instant;
 
     let logical_width = SCREEN_WIDTH as u32;
     let logical_height = SCREEN_HEIGHT as u32;
@@ -58,8 +64,11 @@ fn run(vm: &mut VM, steps_per_tick: usize) {
 
     canvas.set_draw_color(Color::RGB(255, 255, 255));
 
+    let mut pacer = fps.map(|fps| FramePacer::new(fps, steps_per_tick));
+
     let mut event_pump = sdl_context.event_pump().unwrap();
     'running: loop {
+        let frame_start = Instant::now();
         canvas.clear();
 
         for event in event_pump.poll_iter() {
@@ -122,7 +131,10 @@ fn run(vm: &mut VM, steps_per_tick: usize) {
             }
         }
 
-        for _ in 0..steps_per_tick {
+        let steps = pacer
+            .as_ref()
+            .map_or(steps_per_tick, |p| p.steps_per_tick());
+        for _ in 0..steps {
             let result = vm.step();
             if matches!(&result, Err(VMError::StdlibError(StdlibError::Halt))) {
                 break 'running;
@@ -160,12 +172,15 @@ fn run(vm: &mut VM, steps_per_tick: usize) {
 
         canvas.copy(&bg_texture, None, None).unwrap();
         canvas.present();
-        // ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+
+        if let Some(pacer) = &mut pacer {
+            std::thread::sleep(pacer.end_frame(frame_start.elapsed()));
+        }
     }
 }
 
 #[cfg(not(feature = "desktop"))]
-fn run(vm: &mut VM, _: usize) {
+fn run(vm: &mut VM, _: usize, _: Option<u32>) {
     println!("You are running in headless mode!");
     println!("If you want to see the program being executed,");
     println!("you will need to compile the application with the desktop feature enabled");
@@ -198,6 +213,7 @@ fn find_files(dir: &PathBuf) -> Result<HashMap<String, String>, Box<dyn std::err
 pub fn execute_dir(
     use_vm_stdlib: bool,
     steps_per_tick: usize,
+    fps: Option<u32>,
     vm_files: HashMap<String, String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let stdlib = if use_vm_stdlib {
@@ -236,18 +252,32 @@ pub fn execute_dir(
     let program = BytecodeParser::with_stdlib(programs, stdlib).parse()?;
 
     vm.load(program);
-    run(&mut vm, steps_per_tick);
+    run(&mut vm, steps_per_tick, fps);
     Ok(())
 }
 
+/// Scripts for the cpu emulator either load assembly/machine code or use the cpu only ticktock command
+fn is_cpu_script(tst_content: &str) -> bool {
+    Lexer::new(tst_content).any(|token| match token.content {
+        Token::Identifier(ident) => {
+            ident == "ticktock" || ident.ends_with(".asm") || ident.ends_with(".hack")
+        }
+        _ => false,
+    })
+}
+
 pub fn execute_test<'w>(
     tst_file: (PathBuf, String),
     writer: impl Into<Option<&'w mut dyn Write>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), ScriptError> {
     let (tst_name, tst_content) = tst_file;
-    let parser = ScriptParser::new(&tst_name, tst_content.as_str());
-    execute_script(parser, VM::new(Stdlib::new()), writer)?;
-    Ok(())
+    if is_cpu_script(&tst_content) {
+        let parser = ScriptParser::new(&tst_name, tst_content.as_str());
+        execute_script(parser, Cpu::default(), writer)
+    } else {
+        let parser = ScriptParser::new(&tst_name, tst_content.as_str());
+        execute_script(parser, VM::new(Stdlib::new()), writer)
+    }
 }
 
 fn main() {
@@ -260,6 +290,9 @@ fn main() {
         .value_parser(value_parser!(usize))
         .default_value("30000");
 
+    let fps_arg = arg!(--fps <FPS> "Limit the frames per second instead of running at full speed")
+        .value_parser(value_parser!(u32).range(1..));
+
     let use_vm_arg = arg!(--vm "Use the VM stdlib implementations").action(ArgAction::SetTrue);
     let use_stdout_arg =
         arg!(--"print-outfile" "Use stdout instead of the output-file in the script runner")
@@ -268,18 +301,20 @@ fn main() {
     let matches = command!()
         .arg(dir_or_test_arg)
         .arg(step_arg)
+        .arg(fps_arg)
         .arg(use_vm_arg)
         .arg(use_stdout_arg)
         .get_matches();
 
     let dir_or_test = matches.get_one::<PathBuf>("dir_or_test").unwrap();
     let steps_per_tick = *matches.get_one::<usize>("steps").unwrap();
+    let fps = matches.get_one::<u32>("fps").copied();
     let use_vm_stdlib = *matches.get_one::<bool>("vm").unwrap();
     let use_stdout = *matches.get_one::<bool>("print-outfile").unwrap();
 
     if dir_or_test.is_dir() {
         let vm_files = find_files(dir_or_test).unwrap();
-        execute_dir(use_vm_stdlib, steps_per_tick, vm_files).unwrap();
+        execute_dir(use_vm_stdlib, steps_per_tick, fps, vm_files).unwrap();
     } else if dir_or_test.extension().and_then(|s| s.to_str()) == Some("tst") {
         let mut out = io::stdout();
         let writer = if use_stdout {
@@ -298,6 +333,8 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use parse::script::builder::ScriptBuilder;
+    use std::path::Path;
 
     macro_rules! vm_test {
         ($name:expr) => {
@@ -313,6 +350,35 @@ mod tests {
         }};
     }
 
+    /// A temp dir that is unique to a single test, which is removed again when it is dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(test_name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "nand-to-browser-{}-{}",
+                test_name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl std::ops::Deref for TestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
     #[test]
     fn test_07_memory_access_basic_test() {
         let tst = vm_filepath_tuple!("BasicTest/BasicTestVME.tst");
@@ -327,4 +393,371 @@ mod tests {
 
         assert_eq!(cmp, res);
     }
+
+    #[test]
+    fn test_cpu_script() {
+        // run the script in a temp dir, so that Mult.out is not written next to the sources
+        let sources = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/res/tests/cpu/mult"));
+        let dir = TestDir::new("cpu-script");
+        for name in ["Mult.asm", "Mult.cmp"] {
+            fs::copy(sources.join(name), dir.join(name)).unwrap();
+        }
+
+        let tst = (
+            dir.join("Mult.tst"),
+            fs::read_to_string(sources.join("Mult.tst")).unwrap(),
+        );
+        assert!(is_cpu_script(&tst.1));
+        execute_test(tst, None).unwrap();
+
+        let vm_tst = vm_filepath_tuple!("BasicTest/BasicTestVME.tst");
+        assert!(!is_cpu_script(&vm_tst.1));
+    }
+
+    #[test]
+    fn test_script_builder() {
+        let script = ScriptBuilder::new()
+            .output_list(&["RAM[0]%D1.3.1", "RAM[1]%D1.3.1"])
+            .set("RAM[0]", 1)
+            .output()
+            .repeat(2, |b| b.set("RAM[1]", -7).output())
+            .build();
+        let tst = (PathBuf::from("Builder.tst"), script);
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        assert_eq!(
+            "|RAM[0|RAM[1|\n|   1 |   0 |\n|   1 |  -7 |\n|   1 |  -7 |\n",
+            String::from_utf8(v).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_unsigned_16_bit_value() {
+        let tst = (
+            PathBuf::from("Unsigned.tst"),
+            "output-list RAM[0]%D1.6.1;
+             set RAM[0] %XFFFF, output;"
+                .to_owned(),
+        );
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        assert_eq!("| RAM[0] |\n|     -1 |\n", String::from_utf8(v).unwrap());
+    }
+
+    #[test]
+    fn test_interleaved_set_and_output_are_executed_in_order() {
+        let script = "output-list RAM[256]%D1.6.1;
+            set RAM[256] 1, output;
+            set RAM[256] 2, output;
+            set RAM[256] 3, set RAM[256] 4, output;
+            output;
+            set RAM[256] 5, output;";
+        let tst = (PathBuf::from("Interleaved.tst"), script.to_owned());
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        let res = String::from_utf8(v).unwrap();
+        assert_eq!(
+            "|RAM[256]|\n|      1 |\n|      2 |\n|      4 |\n|      4 |\n|      5 |\n",
+            res
+        );
+    }
+
+    #[test]
+    fn test_repeat_zero_skips_the_block() {
+        let script = "output-list RAM[256]%D1.6.1;
+            set RAM[256] 1;
+            repeat 0 {
+                set RAM[256] 2, output;
+            }
+            output;";
+        let tst = (PathBuf::from("RepeatZero.tst"), script.to_owned());
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        let res = String::from_utf8(v).unwrap();
+        assert_eq!("|RAM[256]|\n|      1 |\n", res);
+
+        let tst = (
+            PathBuf::from("RepeatNegative.tst"),
+            "repeat -1 { set RAM[256] 2; }".to_owned(),
+        );
+        let err = execute_test(tst, None).unwrap_err();
+        assert!(matches!(err, ScriptError::Parse(_)));
+    }
+
+    #[test]
+    fn test_output_without_output_file() {
+        let tst = (
+            PathBuf::from("NoOutputFile.tst"),
+            "output-list RAM[0]%D1.6.1; output;".to_owned(),
+        );
+
+        let err = execute_test(tst, None).unwrap_err();
+        assert!(matches!(err, ScriptError::NoOutputFile));
+        assert_eq!("Trying to output without an output file", err.to_string());
+    }
+
+    #[test]
+    fn test_output_list_string_format() {
+        let tst = (
+            PathBuf::from("StringFormat.tst"),
+            "output-list RAM[0]%S1.6.1 RAM[1]%S1.2.1;
+             set RAM[0] 42, set RAM[1] -7, output;"
+                .to_owned(),
+        );
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        // the values are padded like numbers
+        assert_eq!(
+            "| RAM[0] |RAM[|\n|     42 | -7 |\n",
+            String::from_utf8(v).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_output_list_truncates_to_the_rightmost_chars() {
+        let tst = (
+            PathBuf::from("Truncate.tst"),
+            "output-list RAM[0]%D1.3.1; set RAM[0] -12345, output;".to_owned(),
+        );
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        assert_eq!("|RAM[0|\n| 345 |\n", String::from_utf8(v).unwrap());
+    }
+
+    #[test]
+    fn test_output_list_auto_width_aligns_columns() {
+        let tst = (
+            PathBuf::from("AutoWidth.tst"),
+            "output-list RAM[0]%Dauto RAM[1]%D1.2.1;
+             set RAM[0] 5, set RAM[1] 1, output;
+             set RAM[0] 12345, output;
+             set RAM[0] -42, output;"
+                .to_owned(),
+        );
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        assert_eq!(
+            "| RAM[0] |RAM[|\n|      5 |  1 |\n|  12345 |  1 |\n|    -42 |  1 |\n",
+            String::from_utf8(v).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_missing_compare_file_is_reported_with_path_and_command() {
+        let tst = (
+            PathBuf::from("Missing.tst"),
+            "compare-to DoesNotExist.cmp;".to_owned(),
+        );
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        let err = execute_test(tst, w).unwrap_err();
+        assert!(matches!(err, ScriptError::MissingFile(_)));
+
+        let message = err.to_string();
+        assert!(message.contains("DoesNotExist.cmp"), "{}", message);
+        assert!(message.contains("compare-to"), "{}", message);
+    }
+
+    #[test]
+    fn test_streaming_compare_fails_at_first_mismatch() {
+        use simulators::{execute_script_with_options, ScriptOptions};
+
+        let dir = TestDir::new("streaming-compare");
+        fs::write(
+            dir.join("Streaming.cmp"),
+            "|RAM[256]|\n|      1 |\n|      9 |\n|      3 |\n",
+        )
+        .unwrap();
+
+        let script = "compare-to Streaming.cmp,
+            output-list RAM[256]%D1.6.1;
+            set RAM[256] 1, output;
+            set RAM[256] 2, output;
+            set RAM[256] 3, output;";
+
+        let tst_path = dir.join("Streaming.tst");
+        let parser = ScriptParser::new(&tst_path, script);
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        let options = ScriptOptions {
+            streaming_compare: true,
+            ..Default::default()
+        };
+        let err =
+            execute_script_with_options(parser, VM::new(Stdlib::new()), w, options).unwrap_err();
+
+        assert!(matches!(err, ScriptError::Comparison(_)));
+        assert!(err
+            .to_string()
+            .ends_with(":3:7 (output command in line 4 of the script)"));
+
+        // the last output command was never executed
+        let res = String::from_utf8(v).unwrap();
+        assert_eq!("|RAM[256]|\n|      1 |\n|      2 |\n", res);
+    }
+
+    #[test]
+    fn test_compare_ignores_final_newline() {
+        let dir = TestDir::new("final-newline");
+        // the output file always ends with a newline
+        fs::write(dir.join("NoNewline.cmp"), "|RAM[256]|\n|     42 |").unwrap();
+        fs::write(dir.join("TwoNewlines.cmp"), "|RAM[256]|\n|     42 |\n\n").unwrap();
+
+        for cmp in ["NoNewline.cmp", "TwoNewlines.cmp"] {
+            let script = format!(
+                "output-file FinalNewline.out,
+                compare-to {},
+                output-list RAM[256]%D1.6.1;
+                set RAM[256] 42, output;",
+                cmp
+            );
+            let tst_path = dir.join("FinalNewline.tst");
+            let parser = ScriptParser::new(&tst_path, &script);
+            execute_script(parser, VM::new(Stdlib::new()), None).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_custom_wildcard() {
+        use simulators::{execute_script_with_options, ScriptOptions};
+
+        let dir = TestDir::new("custom-wildcard");
+        fs::write(dir.join("Wildcard.cmp"), "|RAM[256]|\n|     ?? |\n").unwrap();
+
+        let script = "output-file Wildcard.out,
+            compare-to Wildcard.cmp,
+            output-list RAM[256]%D1.6.1;
+            set RAM[256] 42, output;";
+        let tst_path = dir.join("Wildcard.tst");
+
+        let options = ScriptOptions {
+            wildcard: '?',
+            ..Default::default()
+        };
+        let parser = ScriptParser::new(&tst_path, script);
+        execute_script_with_options(parser, VM::new(Stdlib::new()), None, options).unwrap();
+
+        // '?' is not a placeholder by default
+        let parser = ScriptParser::new(&tst_path, script);
+        let err = execute_script(parser, VM::new(Stdlib::new()), None).unwrap_err();
+        assert!(matches!(err, ScriptError::Comparison(_)));
+    }
+
+    #[test]
+    fn test_clear_screen_command_zeroes_the_screen() {
+        let script = "output-list RAM[16384]%D1.8.1 RAM[24575]%D1.8.1 RAM[16383]%D1.8.1;
+            set RAM[16384] 5, set RAM[24575] 7, set RAM[16383] 3, output;
+            clear-screen, output;
+            clear-output;";
+        let tst = (PathBuf::from("ClearScreen.tst"), script.to_owned());
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        execute_test(tst, w).unwrap();
+
+        // the memory right before the screen is untouched
+        let res = String::from_utf8(v).unwrap();
+        assert_eq!(
+            "|RAM[16384]|RAM[24575]|RAM[16383]|\n|        5 |        7 |        3 |\n|        0 |        0 |        3 |\n",
+            res
+        );
+    }
+
+    #[test]
+    fn test_output_limit_stops_endless_output() {
+        use simulators::{execute_script_with_options, ScriptOptions};
+
+        let script = "output-list RAM[256]%D1.6.1;
+            repeat {
+                output;
+            }";
+        let tst_path = PathBuf::from("Endless.tst");
+        let parser = ScriptParser::new(&tst_path, script);
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        let options = ScriptOptions {
+            max_output_bytes: Some(40),
+            ..Default::default()
+        };
+        let err =
+            execute_script_with_options(parser, VM::new(Stdlib::new()), w, options).unwrap_err();
+
+        assert!(matches!(err, ScriptError::OutputLimit(_)));
+        assert_eq!("The output exceeded the limit of 40 bytes", err.to_string());
+
+        // the line which would have exceeded the limit is not written
+        let res = String::from_utf8(v).unwrap();
+        assert_eq!("|RAM[256]|\n|      0 |\n|      0 |\n", res);
+    }
+
+    #[test]
+    fn test_output_limit_counts_auto_width_rows() {
+        use simulators::{execute_script_with_options, ScriptOptions};
+
+        let script = "output-list RAM[256]%Dauto;
+            repeat {
+                output;
+            }";
+        let tst_path = PathBuf::from("EndlessAuto.tst");
+        let parser = ScriptParser::new(&tst_path, script);
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        let options = ScriptOptions {
+            max_output_bytes: Some(40),
+            ..Default::default()
+        };
+        let err =
+            execute_script_with_options(parser, VM::new(Stdlib::new()), w, options).unwrap_err();
+
+        assert!(matches!(err, ScriptError::OutputLimit(_)));
+        // the rows that were output before the limit was reached are still written
+        let res = String::from_utf8(v).unwrap();
+        assert_eq!("| RAM[256] |\n|        0 |\n|        0 |\n", res);
+    }
+
+    #[test]
+    fn test_auto_width_rows_are_written_on_error() {
+        let tst = (
+            PathBuf::from("AutoWidthError.tst"),
+            "output-list RAM[0]%Dauto;
+             set RAM[0] 5, output;
+             set RAM[0] 123, output;
+             set RAM[99999] 1;"
+                .to_owned(),
+        );
+
+        let mut v = Vec::new();
+        let w: &mut dyn Write = &mut v;
+        assert!(execute_test(tst, w).is_err());
+
+        assert_eq!(
+            "| RAM[0] |\n|      5 |\n|    123 |\n",
+            String::from_utf8(v).unwrap()
+        );
+    }
 }
@@ -5,6 +5,7 @@ import { FilePicker } from './FilePicker.jsx';
 import { SpeedSlider } from './SpeedSlider.jsx';
 import { CodeView } from './CodeView.jsx';
 import { MemoryWatchBlock } from './MemoryWatchBlock.jsx';
+import { StopReason } from 'nand-to-tetris-web';
 
 const readAllFiles = (fileNames) => {
   return new Promise((resolve, reject) => {
@@ -72,12 +73,14 @@ export function VMEmulatorStepper({app}) {
   const [activeFunction, setActiveFunction] = useState(null);
 
   const run_steps = () => {
-    try {
-      app.step_times(stepsPerTick);
-    } catch(error) {
+    const report = app.step_times(stepsPerTick);
+    if (report.reason !== StopReason.Completed) {
       setRunning(false);
-      showError(error);
     }
+    if (report.reason === StopReason.Error) {
+      showError(report.error);
+    }
+    report.free();
   };
 
   useEffect(() => {